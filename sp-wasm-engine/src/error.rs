@@ -1,5 +1,6 @@
-use super::sandbox::engine::error::Error as EngineError;
+use super::sandbox::engine::error::{Error as EngineError, Failure};
 use sp_wasm_memfs::error::Error as MemFSError;
+use std::fmt;
 use std::io::Error as IoError;
 use std::path::{Path, PathBuf, StripPrefixError};
 use std::string::FromUtf8Error;
@@ -10,6 +11,30 @@ pub enum Error {
     InvalidPath(String),
     #[error("{1}: {0}")]
     FileError(IoError, PathBuf),
+    #[error("symlinks are not allowed in inputs: {0}")]
+    Symlink(PathBuf),
+    #[error("symlink points outside of the mapped dir: {0}")]
+    SymlinkEscape(PathBuf),
+    #[error("symlink creates a cycle: {0}")]
+    SymlinkCycle(PathBuf),
+    #[error("special files are not allowed in inputs: {0}")]
+    SpecialFile(PathBuf),
+    #[error("missing output files: {}", display_paths(.0))]
+    MissingOutputs(Vec<PathBuf>),
+    #[error("output file already exists: {0}")]
+    OutputExists(PathBuf),
+    #[error("unsupported archive format: {0}")]
+    UnsupportedArchive(PathBuf),
+    #[error("invalid archive {0}: {1}")]
+    Archive(PathBuf, String),
+    #[error("input exceeds the {kind} limit: {actual} > {limit}")]
+    LimitExceeded {
+        kind: Limit,
+        actual: u64,
+        limit: u64,
+    },
+    #[error("invalid environment variable: {0}")]
+    InvalidEnv(String),
     #[error("{0}")]
     StripPrefix(#[from] StripPrefixError),
     #[error("{0}")]
@@ -24,6 +49,42 @@ pub enum Error {
 
 pub type Result<T> = std::result::Result<T, Error>;
 
+impl Error {
+    /// How the guest failed, if that's what the error is about.
+    pub fn failure(&self) -> Option<Failure> {
+        match self {
+            Error::Engine(EngineError::SMJS(ref err)) => Some(err.failure),
+            _ => None,
+        }
+    }
+}
+
+/// Input limit reported by `Error::LimitExceeded`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Limit {
+    Bytes,
+    Files,
+    Depth,
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Limit::Bytes => write!(f, "total size (bytes)"),
+            Limit::Files => write!(f, "file count"),
+            Limit::Depth => write!(f, "depth"),
+        }
+    }
+}
+
+fn display_paths(paths: &[PathBuf]) -> String {
+    paths
+        .iter()
+        .map(|path| path.display().to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
 pub(crate) trait FileContext<T, P> {
     fn file_context(self, path: P) -> Result<T>;
 }
@@ -40,6 +101,34 @@ impl PartialEq for Error {
             (&Error::InvalidPath(ref p_left), &Error::InvalidPath(ref p_right)) => {
                 p_left == p_right
             }
+            (&Error::Symlink(ref left), &Error::Symlink(ref right)) => left == right,
+            (&Error::SymlinkEscape(ref left), &Error::SymlinkEscape(ref right)) => left == right,
+            (&Error::SymlinkCycle(ref left), &Error::SymlinkCycle(ref right)) => left == right,
+            (&Error::SpecialFile(ref left), &Error::SpecialFile(ref right)) => left == right,
+            (&Error::MissingOutputs(ref left), &Error::MissingOutputs(ref right)) => left == right,
+            (&Error::OutputExists(ref left), &Error::OutputExists(ref right)) => left == right,
+            (&Error::UnsupportedArchive(ref left), &Error::UnsupportedArchive(ref right)) => {
+                left == right
+            }
+            (
+                &Error::Archive(ref left, ref left_msg),
+                &Error::Archive(ref right, ref right_msg),
+            ) => left == right && left_msg == right_msg,
+            (
+                &Error::LimitExceeded {
+                    kind: ref left_kind,
+                    actual: left_actual,
+                    limit: left_limit,
+                },
+                &Error::LimitExceeded {
+                    kind: ref right_kind,
+                    actual: right_actual,
+                    limit: right_limit,
+                },
+            ) => {
+                left_kind == right_kind && left_actual == right_actual && left_limit == right_limit
+            }
+            (&Error::InvalidEnv(ref left), &Error::InvalidEnv(ref right)) => left == right,
             (&Error::StripPrefix(ref left), &Error::StripPrefix(ref right)) => left == right,
             (&Error::FromUtf8(ref left), &Error::FromUtf8(ref right)) => {
                 left.utf8_error() == right.utf8_error()
@@ -4,10 +4,20 @@ extern crate mozjs;
 pub mod error;
 pub mod sandbox;
 
-pub use error::{Error, Result};
+pub use error::{Error, Limit, Result};
 
 pub mod prelude {
+    pub use super::sandbox::archive::{ArchiveEntry, ArchiveFormat};
+    pub use super::sandbox::convert::{FromJsValue, ToJsValue};
+    pub use super::sandbox::engine::error::{Failure, Trap};
     pub use super::sandbox::engine::{Engine, Runtime};
-    pub use super::sandbox::vfs::VirtualFS;
+    pub use super::sandbox::host::{HostFunction, State};
+    pub use super::sandbox::hostdir::HostDir;
+    pub use super::sandbox::manifest::{Manifest, ManifestOutput};
+    pub use super::sandbox::stdio::{Sink, Stdin};
+    pub use super::sandbox::vfs::{
+        FsBackend, FsFile, Layer, Limits, MapOptions, MemFS, MemFSError, Metadata,
+        SpecialFilePolicy, SymlinkPolicy, Usage, VirtualFS,
+    };
     pub use super::sandbox::Sandbox;
 }
@@ -0,0 +1,405 @@
+use super::vfs::{hostfs, MapOptions, SpecialFilePolicy, SymlinkPolicy, Usage, VirtualFS};
+use crate::error::FileContext;
+use crate::{Error, Result};
+
+use std::ffi::OsString;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::os::unix::ffi::OsStringExt;
+use std::path;
+
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+
+/// Format of an input archive.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArchiveFormat {
+    Tar,
+    TarGz,
+    Zip,
+}
+
+impl ArchiveFormat {
+    /// Guesses the format of the archive at `path` from its extension:
+    /// `.tar`, `.tar.gz` (or `.tgz`) or `.zip`.
+    pub fn from_path<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<path::Path>,
+    {
+        let name = path
+            .as_ref()
+            .file_name()
+            .map(|name| name.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+
+        if name.ends_with(".tar") {
+            Ok(ArchiveFormat::Tar)
+        } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Ok(ArchiveFormat::TarGz)
+        } else if name.ends_with(".zip") {
+            Ok(ArchiveFormat::Zip)
+        } else {
+            Err(Error::UnsupportedArchive(path.as_ref().to_owned()))
+        }
+    }
+}
+
+/// Entry of an input archive, as seen by the guest.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ArchiveEntry {
+    Dir,
+    File(u64),
+    Symlink(path::PathBuf),
+}
+
+impl Usage {
+    /// Measures the archive at `archive_path` the way `map_archive` would
+    /// map it, reading it in full.
+    pub fn measure_archive<P>(archive_path: P, options: &MapOptions) -> Result<Self>
+    where
+        P: AsRef<path::Path>,
+    {
+        let archive_path = archive_path.as_ref();
+        let format = ArchiveFormat::from_path(archive_path)?;
+
+        let mut usage = Self::default();
+        walk_archive(archive_path, format, options, &mut |path, entry, _| {
+            match entry {
+                ArchiveEntry::Dir => usage.add(path, None),
+                ArchiveEntry::File(len) => usage.add(path, Some(*len)),
+                ArchiveEntry::Symlink(_) => usage.add(path, Some(0)),
+            }
+            Ok(())
+        })?;
+
+        Ok(usage)
+    }
+}
+
+/// Raw entry read from an archive, before any policies are applied.
+enum RawEntry {
+    Entry(ArchiveEntry),
+    // hard links, devices, FIFOs and the like
+    Special,
+}
+
+impl VirtualFS {
+    /// Streams the contents of the archive at `archive_path` onto
+    /// `dest_path`, calling `cb` with the guest path and kind of every
+    /// entry after it is mapped, including dirs missing from the archive
+    /// itself.
+    ///
+    /// Entry paths which are absolute or contain `..` are rejected. The
+    /// symlink, special file and limit options apply as they do in
+    /// `map_path_with`, except that symlinks in archives cannot be
+    /// followed, so `SymlinkPolicy::FollowWithinRoot` rejects them. If any
+    /// limits are set, the archive is read twice and nothing is mapped
+    /// when it exceeds them.
+    pub fn map_archive<P1, P2>(
+        &mut self,
+        archive_path: P1,
+        dest_path: P2,
+        options: &MapOptions,
+        cb: &mut dyn FnMut(&path::Path, &ArchiveEntry),
+    ) -> Result<()>
+    where
+        P1: AsRef<path::Path>,
+        P2: AsRef<path::Path>,
+    {
+        let archive_path = archive_path.as_ref();
+        let format = ArchiveFormat::from_path(archive_path)?;
+
+        if !options.limits.is_unlimited() {
+            let usage = Usage::measure_archive(archive_path, options)?;
+            log::debug!("input usage = {:?}", usage);
+            options.limits.check(&usage)?;
+        }
+
+        walk_archive(
+            archive_path,
+            format,
+            options,
+            &mut |path, entry, contents| {
+                let dest_path = dest_path.as_ref().join(path);
+                if let Some(parent) = dest_path.parent() {
+                    self.create_parent_dirs(parent, cb)?;
+                }
+
+                match entry {
+                    ArchiveEntry::Dir => {
+                        if self.is_dir(&dest_path)? {
+                            return Ok(());
+                        }
+                        self.create_dir_all(&dest_path)?;
+                        log::debug!("mapped dir = {:?}", dest_path);
+                    }
+                    ArchiveEntry::Symlink(target) => {
+                        self.create_symlink(&dest_path, target)?;
+                        log::debug!("mapped symlink {:?} => {:?}", dest_path, target);
+                    }
+                    ArchiveEntry::File(len) => {
+                        let mut file = self.create_file(&dest_path)?;
+                        // never take more than measured, even if the entry lies
+                        // about its size
+                        let mut contents = contents.take(*len);
+                        io::copy(&mut contents, &mut file).file_context(archive_path)?;
+                        let mut extra = [0; 1];
+                        if contents
+                            .into_inner()
+                            .read(&mut extra)
+                            .file_context(archive_path)?
+                            > 0
+                        {
+                            return Err(Error::Archive(
+                                archive_path.to_owned(),
+                                format!("entry larger than declared: {}", path.display()),
+                            ));
+                        }
+                        log::debug!("mapped file {:?} from {:?}", dest_path, archive_path);
+                    }
+                }
+
+                cb(&dest_path, entry);
+
+                Ok(())
+            },
+        )
+    }
+
+    /// Packs the guest files at `paths` into a `.tar` or `.tar.gz` archive
+    /// at `archive_path` on the host, as part of `transaction`.
+    ///
+    /// Entries are named after the guest paths without the leading `/`,
+    /// sorted by name and stripped of owners and timestamps, so the same
+    /// files always produce a byte-identical archive. The archive only
+    /// appears once `transaction` is committed, see `hostfs::Transaction`.
+    pub fn save_archive<P>(
+        &mut self,
+        transaction: &mut hostfs::Transaction,
+        archive_path: P,
+        paths: &[path::PathBuf],
+    ) -> Result<()>
+    where
+        P: AsRef<path::Path>,
+    {
+        let archive_path = archive_path.as_ref();
+        let format = ArchiveFormat::from_path(archive_path)?;
+        if format == ArchiveFormat::Zip {
+            return Err(Error::UnsupportedArchive(archive_path.to_owned()));
+        }
+
+        let mut paths = paths
+            .iter()
+            .map(Self::normalize_path)
+            .collect::<Result<Vec<_>>>()?;
+        paths.sort();
+        paths.dedup();
+
+        let file = transaction.create(archive_path)?;
+        if format == ArchiveFormat::TarGz {
+            self.write_tar(GzEncoder::new(file, Compression::default()), &paths)?
+                .finish()
+                .file_context(archive_path)?;
+        } else {
+            self.write_tar(file, &paths)?;
+        }
+
+        Ok(())
+    }
+
+    fn write_tar<W>(&mut self, writer: W, paths: &[path::PathBuf]) -> Result<W>
+    where
+        W: Write,
+    {
+        let mut builder = tar::Builder::new(writer);
+        for path in paths {
+            let len = self.metadata(path)?.len;
+            let file = self.open_file(path)?;
+
+            let mut header = tar::Header::new_gnu();
+            header.set_entry_type(tar::EntryType::Regular);
+            header.set_size(len);
+            header.set_mode(0o644);
+            header.set_uid(0);
+            header.set_gid(0);
+            header.set_mtime(0);
+
+            let name = path.strip_prefix("/")?;
+            builder
+                .append_data(&mut header, name, file)
+                .file_context(path)?;
+            log::debug!("archived file {:?}", path);
+        }
+
+        Ok(builder.into_inner()?)
+    }
+
+    /// Creates `dir` along with any missing parents, reporting each newly
+    /// created dir to `cb`.
+    fn create_parent_dirs(
+        &mut self,
+        dir: &path::Path,
+        cb: &mut dyn FnMut(&path::Path, &ArchiveEntry),
+    ) -> Result<()> {
+        let mut ancestors: Vec<_> = dir.ancestors().collect();
+        ancestors.reverse();
+
+        for dir in ancestors {
+            if !self.is_dir(dir)? {
+                self.create_dir_all(dir)?;
+                cb(dir, &ArchiveEntry::Dir);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Reads the archive at `archive_path`, applying the path sanitisation
+/// and the symlink and special file policies from `options`, and calls
+/// `visit` with the relative path, kind and contents of every entry that
+/// should be mapped.
+fn walk_archive(
+    archive_path: &path::Path,
+    format: ArchiveFormat,
+    options: &MapOptions,
+    visit: &mut dyn FnMut(&path::Path, &ArchiveEntry, &mut dyn Read) -> Result<()>,
+) -> Result<()> {
+    let mut filter = |path: &path::Path, entry: RawEntry, contents: &mut dyn Read| {
+        let path = hostfs::sanitize_archive_path(path)?;
+        if path.as_os_str().is_empty() {
+            // the archive root itself
+            return Ok(());
+        }
+
+        match entry {
+            RawEntry::Entry(entry) => {
+                if let ArchiveEntry::Symlink(_) = entry {
+                    match options.symlinks {
+                        SymlinkPolicy::Reject | SymlinkPolicy::FollowWithinRoot => {
+                            return Err(Error::Symlink(path))
+                        }
+                        SymlinkPolicy::Skip => {
+                            log::warn!("Skipping symlink {:?}", path);
+                            return Ok(());
+                        }
+                        SymlinkPolicy::PreserveAsLink => {}
+                    }
+                }
+                visit(&path, &entry, contents)
+            }
+            RawEntry::Special => match options.special_files {
+                SpecialFilePolicy::Reject => Err(Error::SpecialFile(path)),
+                SpecialFilePolicy::Skip => {
+                    log::warn!("Skipping special file {:?}", path);
+                    Ok(())
+                }
+            },
+        }
+    };
+
+    let file = fs::File::open(archive_path).file_context(archive_path)?;
+    match format {
+        ArchiveFormat::Tar => walk_tar(archive_path, file, &mut filter),
+        ArchiveFormat::TarGz => walk_tar(archive_path, GzDecoder::new(file), &mut filter),
+        ArchiveFormat::Zip => walk_zip(archive_path, file, &mut filter),
+    }
+}
+
+fn walk_tar<R>(
+    archive_path: &path::Path,
+    reader: R,
+    visit: &mut dyn FnMut(&path::Path, RawEntry, &mut dyn Read) -> Result<()>,
+) -> Result<()>
+where
+    R: Read,
+{
+    use tar::EntryType;
+
+    let mut archive = tar::Archive::new(reader);
+    for entry in archive.entries().file_context(archive_path)? {
+        let mut entry = entry.file_context(archive_path)?;
+        let path = entry.path().file_context(archive_path)?.into_owned();
+
+        let raw = match entry.header().entry_type() {
+            EntryType::Regular | EntryType::Continuous => {
+                RawEntry::Entry(ArchiveEntry::File(entry.size()))
+            }
+            EntryType::Directory => RawEntry::Entry(ArchiveEntry::Dir),
+            EntryType::Symlink => match entry.link_name().file_context(archive_path)? {
+                Some(target) => RawEntry::Entry(ArchiveEntry::Symlink(target.into_owned())),
+                None => {
+                    return Err(Error::Archive(
+                        archive_path.to_owned(),
+                        format!("symlink without a target: {}", path.display()),
+                    ))
+                }
+            },
+            // metadata consumed by the `tar` crate itself
+            EntryType::XGlobalHeader
+            | EntryType::XHeader
+            | EntryType::GNULongName
+            | EntryType::GNULongLink => continue,
+            _ => RawEntry::Special,
+        };
+
+        visit(&path, raw, &mut entry)?;
+    }
+
+    Ok(())
+}
+
+fn walk_zip(
+    archive_path: &path::Path,
+    file: fs::File,
+    visit: &mut dyn FnMut(&path::Path, RawEntry, &mut dyn Read) -> Result<()>,
+) -> Result<()> {
+    // `S_IFMT` and `S_IFLNK`, zip entries keep the unix mode regardless of
+    // the host the archive is read on
+    const TYPE_MASK: u32 = 0o170_000;
+    const SYMLINK: u32 = 0o120_000;
+    // `PATH_MAX`
+    const MAX_TARGET_LEN: u64 = 4096;
+
+    let zip_error =
+        |err: zip::result::ZipError| Error::Archive(archive_path.to_owned(), err.to_string());
+
+    let mut archive = zip::ZipArchive::new(file).map_err(zip_error)?;
+    for i in 0..archive.len() {
+        let mut entry = archive.by_index(i).map_err(zip_error)?;
+        let path = path::PathBuf::from(entry.name());
+
+        let raw = if entry.is_dir() {
+            RawEntry::Entry(ArchiveEntry::Dir)
+        } else if entry.unix_mode().map(|mode| mode & TYPE_MASK) == Some(SYMLINK) {
+            // the target is stored as the contents of the entry, whatever
+            // size the archive claims it has
+            let mut target = Vec::new();
+            (&mut entry)
+                .take(MAX_TARGET_LEN + 1)
+                .read_to_end(&mut target)
+                .file_context(archive_path)?;
+            if target.len() as u64 > MAX_TARGET_LEN {
+                return Err(Error::Archive(
+                    archive_path.to_owned(),
+                    format!("symlink target too long: {}", path.display()),
+                ));
+            }
+            RawEntry::Entry(ArchiveEntry::Symlink(path::PathBuf::from(
+                OsString::from_vec(target),
+            )))
+        } else {
+            match entry.unix_mode().map(|mode| mode & TYPE_MASK) {
+                None | Some(0) | Some(0o100_000) => {
+                    RawEntry::Entry(ArchiveEntry::File(entry.size()))
+                }
+                Some(_) => RawEntry::Special,
+            }
+        };
+
+        visit(&path, raw, &mut entry)?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,251 @@
+use super::engine::error::{Error, JSError, Utf16Buffer};
+use super::engine::js_string_to_utf8;
+use crate::Result;
+use mozjs::{
+    jsapi::{JSContext, JSObject, JS_NewUCStringCopyN},
+    jsval::{
+        BooleanValue, DoubleValue, Int32Value, NullValue, ObjectValue, StringValue, UndefinedValue,
+    },
+    rust::{jsapi_wrapped::JS_Stringify, HandleObject, HandleValue, MutableHandleValue},
+    typedarray::{CreateWith, Uint8Array},
+};
+use std::ptr;
+
+/// Conversion of the result of a script into a Rust value, while it's
+/// still rooted; see `Runtime::evaluate`.
+pub trait FromJsValue: Sized {
+    unsafe fn from_js_value(ctx: *mut JSContext, value: HandleValue) -> Result<Self>;
+}
+
+/// Ignores the result.
+impl FromJsValue for () {
+    unsafe fn from_js_value(_ctx: *mut JSContext, _value: HandleValue) -> Result<Self> {
+        Ok(())
+    }
+}
+
+impl FromJsValue for bool {
+    unsafe fn from_js_value(_ctx: *mut JSContext, value: HandleValue) -> Result<Self> {
+        if !value.is_boolean() {
+            return Err(mismatch("a boolean", value));
+        }
+
+        Ok(value.to_boolean())
+    }
+}
+
+impl FromJsValue for f64 {
+    unsafe fn from_js_value(_ctx: *mut JSContext, value: HandleValue) -> Result<Self> {
+        if !value.is_number() {
+            return Err(mismatch("a number", value));
+        }
+
+        Ok(value.to_number())
+    }
+}
+
+macro_rules! from_js_integer {
+    ($($t:ty: $min:expr, $end:expr;)*) => {
+        $(
+            /// Only numbers without a fractional part which fit in the type
+            /// are converted.
+            impl FromJsValue for $t {
+                unsafe fn from_js_value(ctx: *mut JSContext, value: HandleValue) -> Result<Self> {
+                    let number = f64::from_js_value(ctx, value)?;
+                    // `$end` itself is out of range, but exact as an f64
+                    if number.fract() != 0.0 || number < $min || number >= $end {
+                        return Err(Error::Conversion(format!(
+                            "expected {}, got {}",
+                            stringify!($t),
+                            number
+                        ))
+                        .into());
+                    }
+
+                    Ok(number as $t)
+                }
+            }
+        )*
+    };
+}
+
+from_js_integer! {
+    i32: -2_147_483_648.0, 2_147_483_648.0;
+    u32: 0.0, 4_294_967_296.0;
+    i64: -9_223_372_036_854_775_808.0, 9_223_372_036_854_775_808.0;
+    u64: 0.0, 18_446_744_073_709_551_616.0;
+}
+
+impl FromJsValue for String {
+    unsafe fn from_js_value(ctx: *mut JSContext, value: HandleValue) -> Result<Self> {
+        if !value.is_string() {
+            return Err(mismatch("a string", value));
+        }
+
+        Ok(js_string_to_utf8(ctx, value.to_string()))
+    }
+}
+
+/// Bytes of a typed array, e.g. `Uint8Array`, or an `ArrayBuffer`.
+impl FromJsValue for Vec<u8> {
+    unsafe fn from_js_value(ctx: *mut JSContext, value: HandleValue) -> Result<Self> {
+        if !value.is_object() {
+            return Err(mismatch("a typed array or ArrayBuffer", value));
+        }
+
+        typedarray!(in(ctx) let view: ArrayBufferView = value.to_object());
+        if let Ok(view) = view {
+            return Ok(view.to_vec());
+        }
+
+        typedarray!(in(ctx) let buffer: ArrayBuffer = value.to_object());
+        match buffer {
+            Ok(buffer) => Ok(buffer.to_vec()),
+            Err(()) => Err(mismatch("a typed array or ArrayBuffer", value)),
+        }
+    }
+}
+
+/// Anything `JSON.stringify` can handle; values it skips, such as
+/// `undefined` and functions, become `null`.
+impl FromJsValue for serde_json::Value {
+    unsafe fn from_js_value(ctx: *mut JSContext, value: HandleValue) -> Result<Self> {
+        rooted!(in(ctx) let mut value = value.get());
+        let mut json = Utf16Buffer::new(usize::max_value());
+        if !JS_Stringify(
+            ctx,
+            &mut value.handle_mut(),
+            HandleObject::null(),
+            HandleValue::null(),
+            Some(Utf16Buffer::push_cb),
+            &mut json as *mut Utf16Buffer as *mut libc::c_void,
+        ) {
+            // e.g. a cycle
+            let err = JSError::new(ctx);
+            return Err(Error::Conversion(err.message).into());
+        }
+
+        let json = json.into_string();
+        if json.is_empty() {
+            return Ok(serde_json::Value::Null);
+        }
+
+        serde_json::from_str(&json).map_err(|err| Error::Conversion(err.to_string()).into())
+    }
+}
+
+/// `None` for `null` and `undefined`.
+impl<T: FromJsValue> FromJsValue for Option<T> {
+    unsafe fn from_js_value(ctx: *mut JSContext, value: HandleValue) -> Result<Self> {
+        if value.is_null() || value.is_undefined() {
+            return Ok(None);
+        }
+
+        T::from_js_value(ctx, value).map(Some)
+    }
+}
+
+/// Conversion of a Rust value into the result of a host function; see
+/// `Runtime::define_function`.
+pub trait ToJsValue {
+    unsafe fn to_js_value(self, ctx: *mut JSContext, rval: MutableHandleValue) -> Result<()>;
+}
+
+/// `undefined`.
+impl ToJsValue for () {
+    unsafe fn to_js_value(self, _ctx: *mut JSContext, mut rval: MutableHandleValue) -> Result<()> {
+        rval.set(UndefinedValue());
+        Ok(())
+    }
+}
+
+impl ToJsValue for bool {
+    unsafe fn to_js_value(self, _ctx: *mut JSContext, mut rval: MutableHandleValue) -> Result<()> {
+        rval.set(BooleanValue(self));
+        Ok(())
+    }
+}
+
+impl ToJsValue for f64 {
+    unsafe fn to_js_value(self, _ctx: *mut JSContext, mut rval: MutableHandleValue) -> Result<()> {
+        rval.set(DoubleValue(self));
+        Ok(())
+    }
+}
+
+impl ToJsValue for i32 {
+    unsafe fn to_js_value(self, _ctx: *mut JSContext, mut rval: MutableHandleValue) -> Result<()> {
+        rval.set(Int32Value(self));
+        Ok(())
+    }
+}
+
+impl ToJsValue for u32 {
+    unsafe fn to_js_value(self, ctx: *mut JSContext, rval: MutableHandleValue) -> Result<()> {
+        f64::from(self).to_js_value(ctx, rval)
+    }
+}
+
+impl<'a> ToJsValue for &'a str {
+    unsafe fn to_js_value(self, ctx: *mut JSContext, mut rval: MutableHandleValue) -> Result<()> {
+        let utf16: Vec<u16> = self.encode_utf16().collect();
+        let string = JS_NewUCStringCopyN(ctx, utf16.as_ptr(), utf16.len());
+        if string.is_null() {
+            return Err(Error::SMNullPtr.into());
+        }
+
+        rval.set(StringValue(&*string));
+        Ok(())
+    }
+}
+
+impl ToJsValue for String {
+    unsafe fn to_js_value(self, ctx: *mut JSContext, rval: MutableHandleValue) -> Result<()> {
+        self.as_str().to_js_value(ctx, rval)
+    }
+}
+
+/// A `Uint8Array`.
+impl ToJsValue for Vec<u8> {
+    unsafe fn to_js_value(self, ctx: *mut JSContext, mut rval: MutableHandleValue) -> Result<()> {
+        rooted!(in(ctx) let mut array = ptr::null_mut::<JSObject>());
+        Uint8Array::create(ctx, CreateWith::Slice(&self), array.handle_mut())
+            .map_err(|_| Error::SliceToUint8ArrayConversion)?;
+
+        rval.set(ObjectValue(array.get()));
+        Ok(())
+    }
+}
+
+/// `null` for `None`.
+impl<T: ToJsValue> ToJsValue for Option<T> {
+    unsafe fn to_js_value(self, ctx: *mut JSContext, mut rval: MutableHandleValue) -> Result<()> {
+        match self {
+            Some(value) => value.to_js_value(ctx, rval),
+            None => {
+                rval.set(NullValue());
+                Ok(())
+            }
+        }
+    }
+}
+
+fn mismatch(expected: &str, value: HandleValue) -> crate::Error {
+    let actual = if value.is_undefined() {
+        "undefined"
+    } else if value.is_null() {
+        "null"
+    } else if value.is_boolean() {
+        "a boolean"
+    } else if value.is_number() {
+        "a number"
+    } else if value.is_string() {
+        "a string"
+    } else if value.is_symbol() {
+        "a symbol"
+    } else {
+        "an object"
+    };
+
+    Error::Conversion(format!("expected {}, got {}", expected, actual)).into()
+}
@@ -1,33 +1,51 @@
-use super::VFS;
+use super::convert::FromJsValue;
+use super::env;
+use super::host::{self, Host, HostFunction, State};
+use super::spfs;
+use super::stdio::{Fd, Stdio};
+use super::vfs::{names, VirtualFS};
 use crate::Result;
 use mozjs::{
     glue::SetBuildId,
     jsapi::{
         BuildIdCharVector, CallArgs, CompartmentOptions, ContextOptionsRef, InitSelfHostedCode,
-        JSAutoCompartment, JSContext, JSGCParamKey, JSObject, JSString, JS_BeginRequest,
-        JS_DefineFunction, JS_DestroyContext, JS_EncodeStringToUTF8, JS_EndRequest, JS_NewContext,
-        JS_NewGlobalObject, JS_ReportErrorASCII, JS_SetGCParameter, JS_SetNativeStackQuota,
-        OnNewGlobalHookOption, RunJobs, SetBuildIdOp, UseInternalJobQueues, Value, JS,
+        JSAutoCompartment, JSContext, JSGCParamKey, JSObject, JSString, JS_AddInterruptCallback,
+        JS_BeginRequest, JS_DefineFunction, JS_DestroyContext, JS_EncodeStringToUTF8,
+        JS_EndRequest, JS_NewContext, JS_NewGlobalObject, JS_ReportErrorASCII,
+        JS_RequestInterruptCallback, JS_SetContextPrivate, JS_SetGCParameter,
+        JS_SetNativeStackQuota, OnNewGlobalHookOption, RunJobs, SetBuildIdOp, UseInternalJobQueues,
+        Value, JS,
     },
-    jsval::{ObjectValue, UndefinedValue},
+    jsval::{DoubleValue, Int32Value, NullValue, ObjectValue, UndefinedValue},
     panic::maybe_resume_unwind,
     rust::{
-        CompileOptionsWrapper, Handle, HandleObject, JSEngine, MutableHandleValue, ToString,
-        ToUint64, SIMPLE_GLOBAL_CLASS,
+        CompileOptionsWrapper, Handle, HandleObject, HandleValue, JSEngine, MutableHandleValue,
+        ToString, ToUint64, SIMPLE_GLOBAL_CLASS,
     },
     typedarray::{ArrayBuffer, CreateWith},
 };
 use std::{
-    ffi,
+    cell::{Cell, RefMut},
+    collections::BTreeMap,
+    ffi::{self, CString},
+    io::{Read, Write},
     ops::Deref,
     os::raw::c_uint,
+    path::Path,
     ptr::{self, NonNull},
-    sync::Arc,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc,
+    },
+    thread,
+    time::Duration,
 };
 
 const STACK_QUOTA: usize = 128 * 8 * 1024;
 const SYSTEM_CODE_BUFFER: usize = 10 * 1024;
 const TRUSTED_SCRIPT_BUFFER: usize = 8 * 12800;
+// longest `usleep` goes without checking for a timeout
+const USLEEP_SLICE: Duration = Duration::from_millis(10);
 
 unsafe fn new_root_context() -> Result<NonNull<JSContext>> {
     let ctx = match NonNull::new(JS_NewContext(
@@ -56,6 +74,7 @@ unsafe fn new_root_context() -> Result<NonNull<JSContext>> {
     (*contextopts).set_wasm_(true);
     (*contextopts).set_wasmBaseline_(true);
     (*contextopts).set_wasmIon_(true);
+    JS_AddInterruptCallback(ctx_ptr, Some(Runtime::interrupt));
     JS_BeginRequest(ctx_ptr);
 
     Ok(ctx)
@@ -131,6 +150,10 @@ impl Deref for Engine {
 pub struct Runtime {
     ctx: NonNull<JSContext>,
     global: NonNull<JSObject>,
+    max_error_len: Cell<usize>,
+    timeout: Cell<Option<Duration>>,
+    // boxed, as the context keeps a pointer to it
+    host: Box<Host>,
 }
 
 impl Drop for Runtime {
@@ -171,6 +194,11 @@ impl Runtime {
 
         SetBuildIdOp(ctx_ptr, Some(Self::sp_build_id));
 
+        // reached by natives through the context
+        let host = Box::new(Host::default());
+        JS_SetContextPrivate(ctx_ptr, &*host as *const Host as *mut libc::c_void);
+        JS::SetOutOfMemoryCallback(ctx_ptr, Some(Self::out_of_memory), ptr::null_mut());
+
         // callbacks
         let global_ptr = global.as_ptr();
         rooted!(in(ctx_ptr) let global_root = global_ptr);
@@ -186,6 +214,24 @@ impl Runtime {
             0,
         );
 
+        JS_DefineFunction(
+            ctx_ptr,
+            gl.into(),
+            b"printErr\0".as_ptr() as *const libc::c_char,
+            Some(Self::print_err),
+            0,
+            0,
+        );
+
+        JS_DefineFunction(
+            ctx_ptr,
+            gl.into(),
+            b"golem_writeStdio\0".as_ptr() as *const libc::c_char,
+            Some(Self::write_stdio),
+            0,
+            0,
+        );
+
         JS_DefineFunction(
             ctx_ptr,
             gl.into(),
@@ -213,26 +259,64 @@ impl Runtime {
             0,
         );
 
-        // init print funcs
+        JS_DefineFunction(
+            ctx_ptr,
+            gl.into(),
+            b"readDevice\0".as_ptr() as *const libc::c_char,
+            Some(Self::read_device),
+            0,
+            0,
+        );
+
+        JS_DefineFunction(
+            ctx_ptr,
+            gl.into(),
+            b"writeDevice\0".as_ptr() as *const libc::c_char,
+            Some(Self::write_device),
+            0,
+            0,
+        );
+
+        JS_DefineFunction(
+            ctx_ptr,
+            gl.into(),
+            b"golem_readStdin\0".as_ptr() as *const libc::c_char,
+            Some(Self::read_stdin),
+            0,
+            0,
+        );
+
+        JS_DefineFunction(
+            ctx_ptr,
+            gl.into(),
+            b"golem_randEmu\0".as_ptr() as *const libc::c_char,
+            Some(Self::rand_emu),
+            0,
+            0,
+        );
+
+        spfs::define_natives(ctx_ptr, gl);
+        env::define_natives(ctx_ptr, gl);
+
+        // init print funcs; the guest's stdout and stderr go byte by byte
+        // through devices rather than line by line through Emscripten's TTY
         Self::eval(
             ctx,
             global,
             "var Module = {
-                'printErr': print,
+                'printErr': printErr,
                 'print': print,
+                'stdout': function(c) { golem_writeStdio(1, c); },
+                'stderr': function(c) { golem_writeStdio(2, c); },
             };",
         )?;
 
-        // init /dev/random emulation
+        // init /dev/random emulation; the generator is shared with
+        // /dev/urandom in the VirtualFS
         Self::eval(
             ctx,
             global,
-            "var golem_MAGIC = 0;
-            golem_randEmu = function() {
-                golem_MAGIC = Math.pow(golem_MAGIC + 1.8912, 3) % 1;
-                return golem_MAGIC;
-            };
-            var crypto = {
+            "var crypto = {
                 getRandomValues: function(array) {
                     for (var i = 0; i < array.length; i++)
                         array[i] = (golem_randEmu() * 256) | 0
@@ -240,6 +324,25 @@ impl Runtime {
             };",
         )?;
 
+        // expose VirtualFS devices to the guest
+        Self::eval(
+            ctx,
+            global,
+            "golem_createDevice = function(parent, name) {
+                var path = parent + '/' + name;
+                try { FS.unlink(path); } catch (e) {}
+                FS.createDevice(parent, name,
+                    function() { return readDevice(path); },
+                    function(c) { writeDevice(path, c); });
+            };",
+        )?;
+
+        // filesystem backed by the VirtualFS, see `Sandbox::set_spfs`
+        Self::eval(ctx, global, spfs::SPFS_JS)?;
+
+        // environment of the guest, see `Sandbox::set_env`
+        Self::eval(ctx, global, env::ENV_JS)?;
+
         // make time ops fully deterministic
         Self::eval(
             ctx,
@@ -251,16 +354,31 @@ impl Runtime {
              ",
         )?;
 
-        Ok(Self { ctx, global })
+        Ok(Self {
+            ctx,
+            global,
+            max_error_len: Cell::new(error::JSError::DEFAULT_MAX_LEN),
+            timeout: Cell::new(None),
+            host,
+        })
     }
 
-    unsafe fn eval<S>(
+    unsafe fn eval<S>(ctx: NonNull<JSContext>, global: NonNull<JSObject>, script: S) -> Result<()>
+    where
+        S: AsRef<str>,
+    {
+        Self::eval_with(ctx, global, script, error::JSError::DEFAULT_MAX_LEN)
+    }
+
+    unsafe fn eval_with<S, T>(
         ctx: NonNull<JSContext>,
         global: NonNull<JSObject>,
         script: S,
-    ) -> Result<Value>
+        max_error_len: usize,
+    ) -> Result<T>
     where
         S: AsRef<str>,
+        T: FromJsValue,
     {
         let ctx_ptr = ctx.as_ptr();
         let global = global.as_ptr();
@@ -271,22 +389,122 @@ impl Runtime {
 
         rooted!(in(ctx_ptr) let mut rval = UndefinedValue());
 
-        if let None = evaluate_script(ctx, global, script.as_ref(), "noname", 0, rval.handle_mut())
+        Host::from_context(ctx_ptr).out_of_memory.set(false);
+        if let None = evaluate_script(ctx, global, script.as_ref(), "noname", 1, rval.handle_mut())
         {
-            return Err(error::Error::SMJS(error::JSError::new(ctx_ptr)).into());
+            return Err(
+                error::Error::SMJS(error::JSError::with_max_len(ctx_ptr, max_error_len)).into(),
+            );
         }
 
         RunJobs(ctx_ptr);
 
-        Ok(rval.get())
+        T::from_js_value(ctx_ptr, rval.handle())
     }
 
-    pub fn evaluate_script<S>(&self, script: S) -> Result<Value>
+    /// Evaluates `script` and converts its result to `T` while it's still
+    /// rooted.
+    pub fn evaluate<T, S>(&self, script: S) -> Result<T>
     where
+        T: FromJsValue,
         S: AsRef<str>,
     {
         log::debug!("Evaluating script {}", script.as_ref());
-        unsafe { Self::eval(self.ctx, self.global, script) }
+        let _watchdog = self
+            .timeout
+            .get()
+            .map(|timeout| Watchdog::start(self.ctx, timeout, &self.host.timed_out));
+        unsafe { Self::eval_with(self.ctx, self.global, script, self.max_error_len.get()) }
+    }
+
+    #[deprecated(note = "the returned Value isn't rooted, use `evaluate` instead")]
+    pub fn evaluate_script<S>(&self, script: S) -> Result<Value>
+    where
+        S: AsRef<str>,
+    {
+        self.evaluate::<RawValue, _>(script).map(|value| value.0)
+    }
+
+    /// Defines a global JS function `name` calling `function`, replacing any
+    /// previous definition. Arguments and the result are converted with
+    /// `FromJsValue` and `ToJsValue`; errors, including failed conversions,
+    /// are thrown as JS `Error`s prefixed with `name`.
+    pub fn define_function<F, Args>(&self, name: &str, function: F) -> Result<()>
+    where
+        F: HostFunction<Args>,
+        Args: 'static,
+    {
+        if name.is_empty() || name.contains('\0') {
+            return Err(error::Error::InvalidFunctionName(name.to_owned()).into());
+        }
+        let cname = CString::new(name).unwrap();
+
+        self.host.insert(name, function);
+
+        let ctx_ptr = self.ctx.as_ptr();
+        unsafe {
+            rooted!(in(ctx_ptr) let global_root = self.global.as_ptr());
+            let gl = global_root.handle();
+            let _ac = JSAutoCompartment::new(ctx_ptr, gl.get());
+
+            if JS_DefineFunction(
+                ctx_ptr,
+                gl.into(),
+                cname.as_ptr(),
+                Some(host::call_host),
+                F::ARGC,
+                0,
+            )
+            .is_null()
+            {
+                return Err(error::Error::SMJS(error::JSError::new(ctx_ptr)).into());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// State shared by the host functions of this runtime.
+    pub fn state(&self) -> &State {
+        &self.host.state
+    }
+
+    /// Standard streams of the guest.
+    pub(super) fn stdio(&self) -> RefMut<Stdio> {
+        self.host.stdio.borrow_mut()
+    }
+
+    /// Environment of the guest, see `env::ENV_JS`.
+    pub(super) fn env(&self) -> RefMut<BTreeMap<String, String>> {
+        self.host.env.borrow_mut()
+    }
+
+    /// Filesystem of the guest.
+    pub(super) fn vfs(&self) -> RefMut<VirtualFS> {
+        self.host.vfs.borrow_mut()
+    }
+
+    /// Interrupts subsequent scripts running for longer than `timeout`,
+    /// which then fail with `Failure::Timeout`.
+    pub fn set_timeout(&self, timeout: Option<Duration>) {
+        self.timeout.set(timeout);
+    }
+
+    /// Sets the maximum length in bytes of thrown strings and objects in
+    /// the `JSError`s of subsequent scripts; anything longer is cut short
+    /// and marked as truncated.
+    pub fn set_max_error_len(&self, max_len: usize) {
+        self.max_error_len.set(max_len);
+    }
+
+    unsafe extern "C" fn interrupt(ctx: *mut JSContext) -> bool {
+        // returning false terminates the script
+        !Host::from_context(ctx).timed_out.load(Ordering::SeqCst)
+    }
+
+    unsafe extern "C" fn out_of_memory(ctx: *mut JSContext, _data: *mut libc::c_void) {
+        // the exception thrown is a plain string the guest could throw too
+        Host::from_context(ctx).out_of_memory.set(true);
     }
 
     unsafe extern "C" fn sp_build_id(build_id: *mut BuildIdCharVector) -> bool {
@@ -309,7 +527,10 @@ impl Runtime {
         let filename = js_string_to_utf8(ctx, ToString(ctx, arg));
 
         if let Err(err) = (|| -> Result<()> {
-            let contents = VFS.lock().unwrap().read_file(&filename)?;
+            let contents = Host::from_context(ctx)
+                .vfs
+                .borrow_mut()
+                .read_file(names::from_guest(&filename))?;
 
             rooted!(in(ctx) let mut rval = ptr::null_mut::<JSObject>());
             ArrayBuffer::create(ctx, CreateWith::Slice(&contents), rval.handle_mut())
@@ -351,7 +572,12 @@ impl Runtime {
                 .map_err(|_| error::Error::Uint8ArrayToVecConversion)?
                 .to_vec();
 
-            VFS.lock().unwrap().write_file(&filename, &contents)?;
+            let path = names::from_guest(&filename);
+            let mut vfs = Host::from_context(ctx).vfs.borrow_mut();
+            if let Some(parent) = Path::new(&path).parent() {
+                vfs.create_dir_all(parent)?;
+            }
+            vfs.write_file(&path, &contents)?;
 
             Ok(())
         })() {
@@ -368,7 +594,142 @@ impl Runtime {
         true
     }
 
+    unsafe extern "C" fn read_device(ctx: *mut JSContext, argc: u32, vp: *mut Value) -> bool {
+        let args = CallArgs::from_vp(vp, argc);
+
+        if args.argc_ != 1 {
+            JS_ReportErrorASCII(
+                ctx,
+                b"readDevice(path) requires exactly 1 argument\0".as_ptr() as *const libc::c_char,
+            );
+            return false;
+        }
+
+        let arg = Handle::from_raw(args.get(0));
+        let path = js_string_to_utf8(ctx, ToString(ctx, arg));
+
+        match (|| -> Result<Option<u8>> {
+            let mut file = Host::from_context(ctx)
+                .vfs
+                .borrow_mut()
+                .open_file(names::from_guest(&path))?;
+            let mut buf = [0; 1];
+            let count = file.read(&mut buf)?;
+            Ok(if count == 0 { None } else { Some(buf[0]) })
+        })() {
+            Ok(Some(byte)) => args.rval().set(Int32Value(byte.into())),
+            // EOF
+            Ok(None) => args.rval().set(NullValue()),
+            Err(err) => {
+                JS_ReportErrorASCII(
+                    ctx,
+                    format!("failed to read device '{}' with error: {}\0", &path, err)
+                        .as_bytes()
+                        .as_ptr() as *const libc::c_char,
+                );
+                return false;
+            }
+        }
+
+        true
+    }
+
+    unsafe extern "C" fn write_device(ctx: *mut JSContext, argc: u32, vp: *mut Value) -> bool {
+        let args = CallArgs::from_vp(vp, argc);
+
+        if args.argc_ != 2 {
+            JS_ReportErrorASCII(
+                ctx,
+                b"writeDevice(path, byte) requires exactly 2 arguments\0".as_ptr()
+                    as *const libc::c_char,
+            );
+            return false;
+        }
+
+        let arg = Handle::from_raw(args.get(0));
+        let path = js_string_to_utf8(ctx, ToString(ctx, arg));
+
+        let arg = Handle::from_raw(args.get(1));
+        let byte = match ToUint64(ctx, arg) {
+            Ok(byte) => byte as u8,
+            Err(()) => {
+                JS_ReportErrorASCII(
+                    ctx,
+                    b"couldn't extract value from input arg 'byte'\0".as_ptr()
+                        as *const libc::c_char,
+                );
+                return false;
+            }
+        };
+
+        if let Err(err) = (|| -> Result<()> {
+            let mut file = Host::from_context(ctx)
+                .vfs
+                .borrow_mut()
+                .open_file(names::from_guest(&path))?;
+            file.write_all(&[byte])?;
+            Ok(())
+        })() {
+            JS_ReportErrorASCII(
+                ctx,
+                format!("failed to write device '{}' with error: {}\0", &path, err)
+                    .as_bytes()
+                    .as_ptr() as *const libc::c_char,
+            );
+            return false;
+        }
+
+        args.rval().set(UndefinedValue());
+        true
+    }
+
+    unsafe extern "C" fn read_stdin(ctx: *mut JSContext, argc: u32, vp: *mut Value) -> bool {
+        let args = CallArgs::from_vp(vp, argc);
+
+        match (|| -> Result<Option<u8>> {
+            let mut stdio = Host::from_context(ctx).stdio.borrow_mut();
+            let stdin = match stdio.stdin.as_mut() {
+                Some(stdin) => stdin,
+                None => return Ok(None),
+            };
+            let mut buf = [0; 1];
+            let count = stdin.read(&mut buf)?;
+            Ok(if count == 0 { None } else { Some(buf[0]) })
+        })() {
+            Ok(Some(byte)) => args.rval().set(Int32Value(byte.into())),
+            // EOF
+            Ok(None) => args.rval().set(NullValue()),
+            Err(err) => {
+                JS_ReportErrorASCII(
+                    ctx,
+                    format!("failed to read stdin with error: {}\0", err)
+                        .as_bytes()
+                        .as_ptr() as *const libc::c_char,
+                );
+                return false;
+            }
+        }
+
+        true
+    }
+
+    unsafe extern "C" fn rand_emu(ctx: *mut JSContext, argc: u32, vp: *mut Value) -> bool {
+        let args = CallArgs::from_vp(vp, argc);
+        let value = Host::from_context(ctx).rand_emu.lock().unwrap().next_f64();
+
+        args.rval().set(DoubleValue(value));
+        true
+    }
+
     unsafe extern "C" fn print(ctx: *mut JSContext, argc: u32, vp: *mut Value) -> bool {
+        Self::print_to(ctx, argc, vp, Fd::Stdout)
+    }
+
+    unsafe extern "C" fn print_err(ctx: *mut JSContext, argc: u32, vp: *mut Value) -> bool {
+        Self::print_to(ctx, argc, vp, Fd::Stderr)
+    }
+
+    unsafe fn print_to(ctx: *mut JSContext, argc: u32, vp: *mut Value, fd: Fd) -> bool {
         let args = CallArgs::from_vp(vp, argc);
 
         if args.argc_ > 1 {
@@ -379,14 +740,68 @@ impl Runtime {
             return false;
         }
 
-        let message = if args.argc_ == 0 {
+        let mut message = if args.argc_ == 0 {
             "".to_string()
         } else {
             let arg = Handle::from_raw(args.get(0));
             js_string_to_utf8(ctx, ToString(ctx, arg))
         };
+        message.push('\n');
+
+        let mut stdio = Host::from_context(ctx).stdio.borrow_mut();
+        if let Err(err) = stdio.stream(fd).write(message.as_bytes()) {
+            JS_ReportErrorASCII(
+                ctx,
+                format!("failed to print with error: {}\0", err)
+                    .as_bytes()
+                    .as_ptr() as *const libc::c_char,
+            );
+            return false;
+        }
+
+        args.rval().set(UndefinedValue());
+        true
+    }
+
+    unsafe extern "C" fn write_stdio(ctx: *mut JSContext, argc: u32, vp: *mut Value) -> bool {
+        let args = CallArgs::from_vp(vp, argc);
+
+        if args.argc_ != 2 {
+            JS_ReportErrorASCII(
+                ctx,
+                b"golem_writeStdio(fd, byte) requires exactly 2 arguments\0".as_ptr()
+                    as *const libc::c_char,
+            );
+            return false;
+        }
+
+        let (fd, byte) = match (
+            ToUint64(ctx, Handle::from_raw(args.get(0))),
+            ToUint64(ctx, Handle::from_raw(args.get(1))),
+        ) {
+            // bytes come from HEAP8, so they may be negative
+            (Ok(fd), Ok(byte)) => (fd, byte as u8),
+            _ => {
+                JS_ReportErrorASCII(
+                    ctx,
+                    b"couldn't extract value from input args 'fd' and 'byte'\0".as_ptr()
+                        as *const libc::c_char,
+                );
+                return false;
+            }
+        };
 
-        println!("{}", message);
+        let stream = if fd == 2 { Fd::Stderr } else { Fd::Stdout };
+        let mut stdio = Host::from_context(ctx).stdio.borrow_mut();
+        if let Err(err) = stdio.stream(stream).write(&[byte]) {
+            JS_ReportErrorASCII(
+                ctx,
+                format!("failed to write to fd {} with error: {}\0", fd, err)
+                    .as_bytes()
+                    .as_ptr() as *const libc::c_char,
+            );
+            return false;
+        }
 
         args.rval().set(UndefinedValue());
         true
@@ -418,15 +833,83 @@ impl Runtime {
             }
         };
 
-        // sleep
-        sleep(Duration::from_micros(useconds));
+        // sleep in slices, so a timeout still interrupts the guest on time
+        let timed_out = &Host::from_context(ctx).timed_out;
+        let mut remaining = Duration::from_micros(useconds);
+        while remaining > Duration::from_millis(0) {
+            if timed_out.load(Ordering::SeqCst) {
+                // terminates the script, as the interrupt callback would
+                return false;
+            }
+            let slice = std::cmp::min(remaining, USLEEP_SLICE);
+            sleep(slice);
+            remaining -= slice;
+        }
 
         args.rval().set(UndefinedValue());
         true
     }
 }
 
-unsafe fn js_string_to_utf8(ctx: *mut JSContext, js_string: *mut JSString) -> String {
+/// Result of a script as it is, for `Runtime::evaluate_script`.
+struct RawValue(Value);
+
+impl FromJsValue for RawValue {
+    unsafe fn from_js_value(_ctx: *mut JSContext, value: HandleValue) -> Result<Self> {
+        Ok(RawValue(value.get()))
+    }
+}
+
+/// Interrupts the script running in a context once `timeout` passes,
+/// unless dropped before.
+struct Watchdog {
+    timed_out: Arc<AtomicBool>,
+    done: Option<mpsc::Sender<()>>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+struct ContextPtr(*mut JSContext);
+
+// only used for `JS_RequestInterruptCallback`, which is thread-safe
+unsafe impl Send for ContextPtr {}
+
+impl Watchdog {
+    /// Sets `timed_out` before interrupting, and clears it when dropped.
+    fn start(ctx: NonNull<JSContext>, timeout: Duration, timed_out: &Arc<AtomicBool>) -> Self {
+        timed_out.store(false, Ordering::SeqCst);
+
+        let (done, rx) = mpsc::channel::<()>();
+        let ctx = ContextPtr(ctx.as_ptr());
+        let flag = Arc::clone(timed_out);
+        let thread = thread::spawn(move || {
+            if let Err(mpsc::RecvTimeoutError::Timeout) = rx.recv_timeout(timeout) {
+                log::warn!("Script timed out after {:?}, interrupting", timeout);
+                flag.store(true, Ordering::SeqCst);
+                unsafe { JS_RequestInterruptCallback(ctx.0) };
+            }
+        });
+
+        Self {
+            timed_out: Arc::clone(timed_out),
+            done: Some(done),
+            thread: Some(thread),
+        }
+    }
+}
+
+impl Drop for Watchdog {
+    fn drop(&mut self) {
+        // wakes up the thread, which then exits right away
+        self.done.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+        // in case it fired after the script was done anyway
+        self.timed_out.store(false, Ordering::SeqCst);
+    }
+}
+
+pub(super) unsafe fn js_string_to_utf8(ctx: *mut JSContext, js_string: *mut JSString) -> String {
     rooted!(in(ctx) let string_root = js_string);
     let string = JS_EncodeStringToUTF8(ctx, string_root.handle().into());
     let string = std::ffi::CStr::from_ptr(string);
@@ -437,14 +920,20 @@ pub mod error {
     use super::js_string_to_utf8;
     use super::JSContext;
     use super::UndefinedValue;
+    use crate::sandbox::host::Host;
     use mozjs::jsapi::JS_ClearPendingException;
+    use mozjs::jsapi::JS_GetContextPrivate;
     use mozjs::jsapi::JS_IsExceptionPending;
     use mozjs::rust::jsapi_wrapped::JS_Stringify;
-    use mozjs::rust::wrappers::{JS_ErrorFromException, JS_GetPendingException};
+    use mozjs::rust::wrappers::{JS_ErrorFromException, JS_GetPendingException, JS_GetProperty};
     use mozjs::rust::HandleObject;
     use mozjs::rust::HandleValue;
     use mozjs::rust::JSEngineError;
+    use serde::{Deserialize, Serialize};
+    use std::ffi::CStr;
+    use std::fmt;
     use std::slice;
+    use std::sync::atomic::Ordering;
 
     #[derive(Debug, thiserror::Error, PartialEq)]
     pub enum Error {
@@ -458,6 +947,10 @@ pub mod error {
         SMNullPtr,
         #[error("{0}")]
         SMJS(#[from] JSError),
+        #[error("couldn't convert JS value: {0}")]
+        Conversion(String),
+        #[error("invalid host function name '{0}'")]
+        InvalidFunctionName(String),
     }
 
     impl From<JSEngineError> for Error {
@@ -466,33 +959,192 @@ pub mod error {
         }
     }
 
-    #[derive(Debug, PartialEq, thiserror::Error)]
-    #[error("JavaScript error: {message}")]
+    /// Uncaught JavaScript exception.
+    #[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
     pub struct JSError {
+        /// Name of the error, e.g. `TypeError`, if an `Error` was thrown.
+        pub kind: Option<String>,
         pub message: String,
+        pub filename: Option<String>,
+        pub line: Option<u32>,
+        /// 1-based, as in `stack`.
+        pub column: Option<u32>,
+        pub stack: Option<String>,
+        #[serde(default)]
+        pub failure: Failure,
+    }
+
+    /// What made the guest fail, told apart by the error thrown.
+    #[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+    #[serde(rename_all = "snake_case")]
+    pub enum Failure {
+        /// Wasm trap, the guest's own fault.
+        Trap(Trap),
+        /// The engine ran out of memory.
+        OutOfMemory,
+        /// Interrupted after `Runtime::set_timeout`.
+        Timeout,
+        /// The guest called `abort`, failed an assertion, couldn't grow its
+        /// memory any further, or was terminated without an exception.
+        Abort,
+        /// Any other uncaught exception.
+        Exception,
+        /// The Wasm module failed to compile, validate or link.
+        Compile,
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+    #[serde(rename_all = "snake_case")]
+    pub enum Trap {
+        Unreachable,
+        OutOfBounds,
+        DivideByZero,
+        IntegerOverflow,
+        InvalidConversion,
+        IndirectCallMismatch,
+        StackOverflow,
+        Other,
+    }
+
+    impl Default for Failure {
+        fn default() -> Self {
+            Failure::Exception
+        }
+    }
+
+    impl Failure {
+        /// Classifies an exception by the name of its `Error`, if any, and
+        /// its message.
+        ///
+        /// The guest can throw any string it likes, so thrown strings are
+        /// never taken for more than `Abort`, when they come from
+        /// Emscripten's `abort()`, or `Exception`. Running out of memory
+        /// and timeouts are told by the `Runtime` itself, see `JSError`.
+        pub fn classify(kind: Option<&str>, message: &str) -> Self {
+            match kind {
+                Some("CompileError") | Some("LinkError") => Failure::Compile,
+                Some("RuntimeError") => Failure::Trap(Trap::from_message(message)),
+                Some("InternalError") if message.contains("too much recursion") => {
+                    Failure::Trap(Trap::StackOverflow)
+                }
+                Some(_) => Failure::Exception,
+                // failed instantiation, `assert`, or growing memory past its
+                // maximum all end up here
+                None if message.starts_with("abort(") => Failure::Abort,
+                None => Failure::Exception,
+            }
+        }
     }
 
+    impl Trap {
+        /// Maps SpiderMonkey's messages for `WebAssembly.RuntimeError`.
+        fn from_message(message: &str) -> Self {
+            if message.contains("unreachable") {
+                Trap::Unreachable
+            } else if message.contains("out of bounds") {
+                Trap::OutOfBounds
+            } else if message.contains("divide by zero") {
+                Trap::DivideByZero
+            } else if message.contains("integer overflow") {
+                Trap::IntegerOverflow
+            } else if message.contains("invalid conversion") {
+                Trap::InvalidConversion
+            } else if message.contains("signature mismatch") {
+                Trap::IndirectCallMismatch
+            } else if message.contains("too much recursion") {
+                Trap::StackOverflow
+            } else {
+                Trap::Other
+            }
+        }
+    }
+
+    impl fmt::Display for JSError {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "JavaScript error: ")?;
+            if let Some(ref kind) = self.kind {
+                write!(f, "{}: ", kind)?;
+            }
+            write!(f, "{}", self.message)?;
+            if let Some(ref filename) = self.filename {
+                write!(
+                    f,
+                    " (at {}:{}:{})",
+                    filename,
+                    self.line.unwrap_or_default(),
+                    self.column.unwrap_or_default()
+                )?;
+            }
+            match self.stack {
+                Some(ref stack) if !stack.trim().is_empty() => {
+                    write!(f, "\nstack:\n{}", stack.trim_end())
+                }
+                _ => Ok(()),
+            }
+        }
+    }
+
+    impl std::error::Error for JSError {}
+
     impl JSError {
-        const MAX_JSON_STRINGIFY: usize = 1024;
+        /// Default maximum length of the `message` of a thrown string or
+        /// object.
+        pub const DEFAULT_MAX_LEN: usize = 64 * 1024;
+        /// Appended to messages cut short at the maximum length.
+        pub const TRUNCATED: &'static str = "... (truncated)";
 
         pub unsafe fn new(ctx: *mut JSContext) -> Self {
-            Self::create_with(ctx)
+            Self::with_max_len(ctx, Self::DEFAULT_MAX_LEN)
         }
 
-        unsafe fn create_with(ctx: *mut JSContext) -> Self {
+        /// Takes the pending exception, keeping at most `max_len` bytes of
+        /// thrown strings and objects serialized to JSON.
+        pub unsafe fn with_max_len(ctx: *mut JSContext, max_len: usize) -> Self {
+            Self::create_with(ctx, max_len)
+        }
+
+        fn with_message<S: Into<String>>(message: S) -> Self {
+            Self {
+                message: message.into(),
+                ..Self::default()
+            }
+        }
+
+        unsafe fn create_with(ctx: *mut JSContext, max_len: usize) -> Self {
+            // null unless the context belongs to a `Runtime`
+            let host = (JS_GetContextPrivate(ctx) as *const Host).as_ref();
+            // uncatchable, as after the interrupt callback
             if !JS_IsExceptionPending(ctx) {
+                let timed_out = host.map_or(false, |host| host.timed_out.load(Ordering::SeqCst));
+                let (message, failure) = if timed_out {
+                    ("Script timed out", Failure::Timeout)
+                } else {
+                    (
+                        "Uncaught exception: exception reported but not pending",
+                        Failure::Abort,
+                    )
+                };
                 return Self {
-                    message: "Uncaught exception: exception reported but not pending".to_string(),
+                    failure,
+                    ..Self::with_message(message)
                 };
             }
 
+            let mut err = Self::take_exception(ctx, max_len);
+            err.failure = if host.map_or(false, |host| host.out_of_memory.replace(false)) {
+                Failure::OutOfMemory
+            } else {
+                Failure::classify(err.kind.as_ref().map(String::as_str), &err.message)
+            };
+            err
+        }
+
+        unsafe fn take_exception(ctx: *mut JSContext, max_len: usize) -> Self {
             rooted!(in(ctx) let mut value = UndefinedValue());
 
             if !JS_GetPendingException(ctx, value.handle_mut()) {
                 JS_ClearPendingException(ctx);
-                return Self {
-                    message: "Uncaught exception: JS_GetPendingException failed".to_string(),
-                };
+                return Self::with_message("Uncaught exception: JS_GetPendingException failed");
             }
 
             JS_ClearPendingException(ctx);
@@ -501,37 +1153,31 @@ pub mod error {
                 rooted!(in(ctx) let object = value.to_object());
                 Self::from_native_error(ctx, object.handle()).unwrap_or_else(|| {
                     // try serializing to JSON
-                    let mut data = vec![0; Self::MAX_JSON_STRINGIFY];
+                    let mut json = Utf16Buffer::new(max_len);
                     if !JS_Stringify(
                         ctx,
                         &mut value.handle_mut(),
                         HandleObject::null(),
                         HandleValue::null(),
-                        Some(Self::stringify_cb),
-                        data.as_mut_ptr() as *mut libc::c_void,
+                        Some(Utf16Buffer::push_cb),
+                        &mut json as *mut Utf16Buffer as *mut libc::c_void,
                     ) {
-                        return Self {
-                            message: "Uncaught exception: unknown (can't convert to string)"
-                                .to_string(),
-                        };
+                        JS_ClearPendingException(ctx);
+                        return Self::with_message(
+                            "Uncaught exception: unknown (can't convert to string)",
+                        );
                     }
 
-                    if let Ok(data) = std::ffi::CString::from_vec_unchecked(data).into_string() {
-                        Self { message: data }
-                    } else {
-                        Self {
-                            message: "Uncaught exception: unknown (can't convert to string)"
-                                .to_string(),
-                        }
-                    }
+                    Self::with_message(json.into_string())
                 })
             } else if value.is_string() {
-                let message = js_string_to_utf8(ctx, value.to_string());
-                Self { message }
+                Self::with_message(truncate(
+                    js_string_to_utf8(ctx, value.to_string()),
+                    max_len,
+                    false,
+                ))
             } else {
-                Self {
-                    message: "Uncaught exception: failed to stringify primitive".to_string(),
-                }
+                Self::with_message("Uncaught exception: failed to stringify primitive")
             }
         }
 
@@ -548,21 +1194,123 @@ pub mod error {
                 String::from_utf8_lossy(message).into_owned()
             };
 
-            Some(Self { message })
+            let filename = (*report)._base.filename;
+            let (filename, line, column) = if filename.is_null() {
+                (None, None, None)
+            } else {
+                (
+                    Some(CStr::from_ptr(filename).to_string_lossy().into_owned()),
+                    Some((*report)._base.lineno),
+                    // 0-based in reports
+                    Some((*report)._base.column + 1),
+                )
+            };
+
+            Some(Self {
+                kind: Self::string_property(ctx, obj, b"name\0"),
+                message,
+                filename,
+                line,
+                column,
+                stack: Self::string_property(ctx, obj, b"stack\0"),
+                // classified by `create_with`
+                failure: Failure::default(),
+            })
+        }
+
+        unsafe fn string_property(
+            ctx: *mut JSContext,
+            obj: HandleObject,
+            name: &[u8],
+        ) -> Option<String> {
+            rooted!(in(ctx) let mut value = UndefinedValue());
+            if !JS_GetProperty(
+                ctx,
+                obj,
+                name.as_ptr() as *const libc::c_char,
+                value.handle_mut(),
+            ) {
+                JS_ClearPendingException(ctx);
+                return None;
+            }
+
+            if value.is_string() {
+                Some(js_string_to_utf8(ctx, value.to_string()))
+            } else {
+                None
+            }
+        }
+    }
+
+    /// UTF-16 passed to `push_cb` in chunks, e.g. by `JS_Stringify`, kept
+    /// up to `max_len` code units, which is enough for `max_len` bytes of
+    /// UTF-8.
+    pub(crate) struct Utf16Buffer {
+        units: Vec<u16>,
+        max_len: usize,
+        truncated: bool,
+    }
+
+    impl Utf16Buffer {
+        pub(crate) fn new(max_len: usize) -> Self {
+            Self {
+                units: Vec::new(),
+                max_len,
+                truncated: false,
+            }
         }
 
-        unsafe extern "C" fn stringify_cb(
-            bytes: *const u16,
+        pub(crate) unsafe extern "C" fn push_cb(
+            chars: *const u16,
             len: u32,
             data: *mut libc::c_void,
         ) -> bool {
-            let data = std::slice::from_raw_parts_mut(data as *mut u8, Self::MAX_JSON_STRINGIFY);
-            let bytes = std::slice::from_raw_parts(bytes, len as usize);
-            for i in 0..len as usize {
-                // TODO substitute UTF16 chars with unknown symbol in UTF8
-                data[i] = bytes[i].to_le_bytes()[0];
-            }
+            let buffer = &mut *(data as *mut Utf16Buffer);
+            buffer.push(slice::from_raw_parts(chars, len as usize));
             true
         }
+
+        fn push(&mut self, units: &[u16]) {
+            let room = self.max_len.saturating_sub(self.units.len());
+            if units.len() > room {
+                self.truncated = true;
+            }
+            self.units
+                .extend_from_slice(&units[..std::cmp::min(room, units.len())]);
+        }
+
+        /// Decodes the units, replacing unpaired surrogates with U+FFFD.
+        pub(crate) fn into_string(mut self) -> String {
+            if self.truncated {
+                // don't let a pair cut in half show up as U+FFFD
+                if let Some(&(0xD800..=0xDBFF)) = self.units.last() {
+                    self.units.pop();
+                }
+            }
+            truncate(
+                String::from_utf16_lossy(&self.units),
+                self.max_len,
+                self.truncated,
+            )
+        }
+    }
+
+    /// Cuts `s` short at `max_len` bytes, on a char boundary, and marks it
+    /// with `JSError::TRUNCATED` if anything is missing.
+    fn truncate(mut s: String, max_len: usize, truncated: bool) -> String {
+        let mut truncated = truncated;
+        if s.len() > max_len {
+            let mut len = max_len;
+            while !s.is_char_boundary(len) {
+                len -= 1;
+            }
+            s.truncate(len);
+            truncated = true;
+        }
+        if truncated {
+            s.push_str(JSError::TRUNCATED);
+        }
+
+        s
     }
 }
@@ -0,0 +1,178 @@
+use super::host::Host;
+use crate::error::FileContext;
+use crate::{Error, Result};
+use mozjs::{
+    jsapi::{
+        CallArgs, JSContext, JS_DefineFunction, JS_NewUCStringCopyN, JS_ReportErrorASCII, Value,
+    },
+    jsval::StringValue,
+    rust::HandleObject,
+};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+/// Size of the pool Emscripten keeps `NAME=VALUE` strings in, as in its
+/// `___buildEnvironment`.
+const TOTAL_ENV_SIZE: usize = 1024;
+
+/// Number of pointers Emscripten sets aside for `environ`, the last of
+/// which ends the list.
+const MAX_ENV_VALUES: usize = 64;
+
+/// Environment every guest starts with, in place of Emscripten's own
+/// defaults.
+pub fn default_env() -> BTreeMap<String, String> {
+    [
+        ("HOME", "/"),
+        ("LANG", "C.UTF-8"),
+        ("PATH", "/"),
+        ("PWD", "/"),
+    ]
+    .iter()
+    .map(|&(name, value)| (name.to_owned(), value.to_owned()))
+    .collect()
+}
+
+/// Checks that `name` and `value` can be passed to the guest.
+pub fn check_var(name: &str, value: &str) -> Result<()> {
+    if name.is_empty() || name.contains('=') || name.contains('\0') {
+        return Err(Error::InvalidEnv(format!("invalid name '{}'", name)));
+    }
+    if value.contains('\0') {
+        return Err(Error::InvalidEnv(format!("value of {} contains NUL", name)));
+    }
+
+    Ok(())
+}
+
+/// Checks that `vars` fit in the space Emscripten sets aside for the
+/// environment, which it would otherwise fail on, or write past, at init.
+/// Every string counts with its terminating NUL.
+pub fn check_size(vars: &BTreeMap<String, String>) -> Result<()> {
+    if vars.len() >= MAX_ENV_VALUES {
+        return Err(Error::InvalidEnv(format!(
+            "{} variables set, at most {} allowed",
+            vars.len(),
+            MAX_ENV_VALUES - 1
+        )));
+    }
+
+    let size: usize = vars
+        .iter()
+        .map(|(name, value)| name.len() + value.len() + 2)
+        .sum();
+    if size > TOTAL_ENV_SIZE {
+        return Err(Error::InvalidEnv(format!(
+            "environment takes {} bytes, at most {} allowed",
+            size, TOTAL_ENV_SIZE
+        )));
+    }
+
+    Ok(())
+}
+
+/// Parses a `NAME=VALUE` pair; the value may contain `=` itself.
+pub fn parse_var(s: &str) -> Result<(String, String)> {
+    let mut parts = s.splitn(2, '=');
+    let name = parts.next().unwrap_or_default();
+    let value = parts
+        .next()
+        .ok_or_else(|| Error::InvalidEnv(format!("expected NAME=VALUE, got '{}'", s)))?;
+    check_var(name, value)?;
+
+    Ok((name.to_owned(), value.to_owned()))
+}
+
+/// Reads `NAME=VALUE` pairs from `path`, one per line. Empty lines and
+/// lines starting with `#` are skipped; values are taken as they are,
+/// without any quoting.
+pub fn read_env_file<P>(path: P) -> Result<Vec<(String, String)>>
+where
+    P: AsRef<Path>,
+{
+    let contents = fs::read_to_string(path.as_ref()).file_context(path.as_ref())?;
+    contents
+        .lines()
+        .filter(|line| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+        .map(parse_var)
+        .collect()
+}
+
+/// Replaces Emscripten's environment with that of the `Runtime`. Run from
+/// `Module['preRun']`, so the environment is already in place when the
+/// glue first builds it at init, before any global constructor.
+///
+/// The first `___buildEnvironment` would fill in Emscripten's defaults
+/// over `ENV`, so the pool in the guest's memory is allocated here
+/// instead, as it would be there, and the call at init only copies `ENV`
+/// into it. Values are passed on as UTF-8, as Emscripten copies them byte
+/// by byte.
+pub(super) const ENV_JS: &str = "
+golem_setupEnv = function() {
+    // only there if the guest uses its environment
+    if (typeof ___buildEnvironment === 'undefined' || typeof __get_environ === 'undefined') {
+        return;
+    }
+    for (var name in ENV) {
+        delete ENV[name];
+    }
+    var vars = golem_getEnv();
+    if (vars.length > 0) {
+        vars.split('\\0').forEach(function(nameValue) {
+            var i = nameValue.indexOf('=');
+            ENV[nameValue.slice(0, i)] = unescape(encodeURIComponent(nameValue.slice(i + 1)));
+        });
+    }
+    if (!___buildEnvironment.called) {
+        // `TOTAL_ENV_SIZE` bytes and `MAX_ENV_VALUES` pointers
+        var poolPtr = getMemory(1024);
+        var envPtr = getMemory(64 * 4);
+        HEAP32[envPtr >> 2] = poolPtr;
+        HEAP32[__get_environ() >> 2] = envPtr;
+        ___buildEnvironment.called = true;
+    }
+};
+";
+
+/// Defines `golem_getEnv`, which returns the environment of the `Runtime`
+/// as `NAME=VALUE` pairs joined with NUL, in `global`.
+pub(super) unsafe fn define_natives(ctx: *mut JSContext, global: HandleObject) {
+    JS_DefineFunction(
+        ctx,
+        global.into(),
+        b"golem_getEnv\0".as_ptr() as *const libc::c_char,
+        Some(get_env),
+        0,
+        0,
+    );
+}
+
+unsafe extern "C" fn get_env(ctx: *mut JSContext, argc: u32, vp: *mut Value) -> bool {
+    let args = CallArgs::from_vp(vp, argc);
+
+    if args.argc_ != 0 {
+        JS_ReportErrorASCII(
+            ctx,
+            b"golem_getEnv() requires no arguments\0".as_ptr() as *const libc::c_char,
+        );
+        return false;
+    }
+
+    let vars = Host::from_context(ctx)
+        .env
+        .borrow()
+        .iter()
+        .map(|(name, value)| format!("{}={}", name, value))
+        .collect::<Vec<_>>()
+        .join("\0");
+
+    let utf16: Vec<u16> = vars.encode_utf16().collect();
+    let string = JS_NewUCStringCopyN(ctx, utf16.as_ptr(), utf16.len());
+    if string.is_null() {
+        return false;
+    }
+
+    args.rval().set(StringValue(&*string));
+    true
+}
@@ -0,0 +1,249 @@
+use super::convert::{FromJsValue, ToJsValue};
+use super::engine::js_string_to_utf8;
+use super::env;
+use super::random::RandEmu;
+use super::stdio::Stdio;
+use super::vfs::VirtualFS;
+use mozjs::{
+    jsapi::{
+        CallArgs, JSContext, JS_GetContextPrivate, JS_GetFunctionId, JS_GetObjectFunction,
+        JS_ReportErrorUTF8, Value,
+    },
+    rust::{Handle, HandleValue, MutableHandleValue},
+};
+use std::any::{Any, TypeId};
+use std::cell::{Cell, Ref, RefCell, RefMut};
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::CString;
+use std::fmt;
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::rc::Rc;
+use std::sync::atomic::AtomicBool;
+use std::sync::{Arc, Mutex};
+
+type Callback = Rc<dyn Fn(&HostCall) -> Result<(), String>>;
+type StateValue = RefCell<Box<dyn Any>>;
+
+/// Everything of a `Runtime` its natives reach through the context: host
+/// functions and their state, and the guest's stdio, environment,
+/// filesystem and random generator.
+pub(super) struct Host {
+    functions: RefCell<HashMap<String, Callback>>,
+    pub(super) state: State,
+    pub(super) stdio: RefCell<Stdio>,
+    /// Environment of the guest, sorted by name.
+    pub(super) env: RefCell<BTreeMap<String, String>>,
+    pub(super) vfs: RefCell<VirtualFS>,
+    /// Shared with `/dev/urandom` in `vfs`.
+    pub(super) rand_emu: Arc<Mutex<RandEmu>>,
+    /// Set by the watchdog once the running script has to be interrupted.
+    pub(super) timed_out: Arc<AtomicBool>,
+    /// Set when the engine runs out of memory during a script.
+    pub(super) out_of_memory: Cell<bool>,
+}
+
+impl Default for Host {
+    fn default() -> Self {
+        let rand_emu = Arc::new(Mutex::new(RandEmu::new()));
+        Self {
+            functions: RefCell::default(),
+            state: State::default(),
+            stdio: RefCell::default(),
+            env: RefCell::new(env::default_env()),
+            vfs: RefCell::new(VirtualFS::with_rand_emu(Arc::clone(&rand_emu))),
+            rand_emu,
+            timed_out: Arc::default(),
+            out_of_memory: Cell::new(false),
+        }
+    }
+}
+
+impl Host {
+    /// The `Host` of the `Runtime` owning `ctx`.
+    pub(super) unsafe fn from_context<'a>(ctx: *mut JSContext) -> &'a Host {
+        &*(JS_GetContextPrivate(ctx) as *const Host)
+    }
+
+    pub(super) fn insert<F, Args>(&self, name: &str, function: F)
+    where
+        F: HostFunction<Args>,
+        Args: 'static,
+    {
+        let callback: Callback = Rc::new(move |call: &HostCall| unsafe { function.call(call) });
+        self.functions
+            .borrow_mut()
+            .insert(name.to_owned(), callback);
+    }
+}
+
+/// Values shared by the host functions of a `Runtime`, one per type.
+///
+/// Each value is borrowed on its own, so holding one while a host
+/// function reaches for a value of another type is fine.
+#[derive(Default)]
+pub struct State {
+    // boxed, so values stay put however the map changes
+    values: RefCell<HashMap<TypeId, Box<StateValue>>>,
+}
+
+impl State {
+    /// Stores `value`, returning the previous value of its type. Panics if
+    /// that is borrowed, as with `RefCell`.
+    pub fn insert<T: 'static>(&self, value: T) -> Option<T> {
+        let mut values = self.values.borrow_mut();
+        match values.get(&TypeId::of::<T>()) {
+            Some(cell) => {
+                let old = mem::replace(&mut *cell.borrow_mut(), Box::new(value));
+                Some(*old.downcast::<T>().unwrap())
+            }
+            None => {
+                values.insert(TypeId::of::<T>(), Box::new(RefCell::new(Box::new(value))));
+                None
+            }
+        }
+    }
+
+    /// Panics if the value is borrowed, as with `RefCell`.
+    pub fn remove<T: 'static>(&self) -> Option<T> {
+        let mut values = self.values.borrow_mut();
+        if let Some(cell) = values.get(&TypeId::of::<T>()) {
+            // not to be dropped while borrowed, see `cell`
+            drop(cell.borrow_mut());
+        }
+
+        values
+            .remove(&TypeId::of::<T>())
+            .map(|cell| *cell.into_inner().downcast::<T>().unwrap())
+    }
+
+    /// Panics if the value is borrowed mutably, as with `RefCell`.
+    pub fn get<T: 'static>(&self) -> Option<Ref<T>> {
+        self.cell::<T>()
+            .map(|cell| Ref::map(cell.borrow(), |value| value.downcast_ref::<T>().unwrap()))
+    }
+
+    /// Panics if the value is borrowed, as with `RefCell`.
+    pub fn get_mut<T: 'static>(&self) -> Option<RefMut<T>> {
+        self.cell::<T>().map(|cell| {
+            RefMut::map(cell.borrow_mut(), |value| {
+                value.downcast_mut::<T>().unwrap()
+            })
+        })
+    }
+
+    fn cell<T: 'static>(&self) -> Option<&StateValue> {
+        let values = self.values.borrow();
+        let cell = values.get(&TypeId::of::<T>())?;
+        // the box is only dropped by `insert` and `remove` once nothing
+        // borrows the value, and never moves before
+        Some(unsafe { &*(cell.as_ref() as *const StateValue) })
+    }
+}
+
+/// A call of a host function from JS.
+pub struct HostCall<'a> {
+    ctx: *mut JSContext,
+    args: &'a CallArgs,
+    state: &'a State,
+}
+
+impl<'a> HostCall<'a> {
+    /// `undefined` past the arguments actually passed.
+    fn arg(&self, i: u32) -> HandleValue {
+        unsafe { Handle::from_raw(self.args.get(i)) }
+    }
+
+    fn rval(&self) -> MutableHandleValue {
+        unsafe { MutableHandleValue::from_raw(self.args.rval()) }
+    }
+}
+
+/// Rust function callable from JS, see `Runtime::define_function`.
+///
+/// Implemented for closures taking the `State` of the runtime and up to
+/// four arguments which are `FromJsValue`, and returning a `Result` with a
+/// `ToJsValue` on success. Errors are thrown as JS exceptions.
+pub trait HostFunction<Args>: 'static {
+    /// Number of arguments, as reported by `length` in JS.
+    const ARGC: u32;
+
+    unsafe fn call(&self, call: &HostCall) -> Result<(), String>;
+}
+
+macro_rules! host_function {
+    ($argc:expr; $($arg:ident: $i:expr),*) => {
+        impl<F, R, E, $($arg,)*> HostFunction<($($arg,)*)> for F
+        where
+            F: Fn(&State, $($arg),*) -> Result<R, E> + 'static,
+            R: ToJsValue,
+            E: fmt::Display,
+            $($arg: FromJsValue,)*
+        {
+            const ARGC: u32 = $argc;
+
+            #[allow(non_snake_case)]
+            unsafe fn call(&self, call: &HostCall) -> Result<(), String> {
+                $(
+                    let $arg = <$arg as FromJsValue>::from_js_value(call.ctx, call.arg($i))
+                        .map_err(|err| format!("argument {}: {}", $i, err))?;
+                )*
+                let result = self(call.state, $($arg),*).map_err(|err| err.to_string())?;
+                result
+                    .to_js_value(call.ctx, call.rval())
+                    .map_err(|err| err.to_string())
+            }
+        }
+    };
+}
+
+host_function!(0;);
+host_function!(1; A1: 0);
+host_function!(2; A1: 0, A2: 1);
+host_function!(3; A1: 0, A2: 1, A3: 2);
+host_function!(4; A1: 0, A2: 1, A3: 2, A4: 3);
+
+/// Native behind every host function, which looks the function up by the
+/// name it was defined with.
+pub(super) unsafe extern "C" fn call_host(ctx: *mut JSContext, argc: u32, vp: *mut Value) -> bool {
+    let args = CallArgs::from_vp(vp, argc);
+    let host = Host::from_context(ctx);
+
+    let function = JS_GetObjectFunction(args.callee());
+    let name = js_string_to_utf8(ctx, JS_GetFunctionId(function));
+    // not borrowed during the call, which may define further functions
+    let callback = host.functions.borrow().get(&name).cloned();
+    let callback = match callback {
+        Some(callback) => callback,
+        None => {
+            report(ctx, &format!("{}: no such host function", name));
+            return false;
+        }
+    };
+
+    let call = HostCall {
+        ctx,
+        args: &args,
+        state: &host.state,
+    };
+    match panic::catch_unwind(AssertUnwindSafe(|| callback(&call))) {
+        Ok(Ok(())) => true,
+        Ok(Err(message)) => {
+            report(ctx, &format!("{}: {}", name, message));
+            false
+        }
+        Err(_) => {
+            report(ctx, &format!("{}: host function panicked", name));
+            false
+        }
+    }
+}
+
+unsafe fn report(ctx: *mut JSContext, message: &str) {
+    let message = CString::new(message.replace('\0', "\\0")).unwrap();
+    JS_ReportErrorUTF8(
+        ctx,
+        b"%s\0".as_ptr() as *const libc::c_char,
+        message.as_ptr(),
+    );
+}
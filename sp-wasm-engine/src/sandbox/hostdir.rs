@@ -0,0 +1,509 @@
+use super::vfs::{FileType, FsBackend, FsFile, MemFSError, Metadata};
+use crate::error::FileContext;
+use std::ffi::{CStr, CString, OsStr, OsString};
+use std::fs;
+use std::io::{self, Read, Seek, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::path::{Component, Path, PathBuf};
+
+type Result<T> = std::result::Result<T, MemFSError>;
+
+/// Host directory exposed to the guest without copying its contents.
+///
+/// Every lookup walks the path one component at a time with `openat`
+/// relative to the root directory, refusing to follow symlinks, so
+/// neither `..` nor a symlink (even one pointing back inside the root)
+/// can reach anything outside of it. Symlinks and special files are
+/// invisible to the guest.
+#[derive(Debug)]
+pub struct HostDir {
+    path: PathBuf,
+    root: fs::File,
+    read_only: bool,
+}
+
+impl HostDir {
+    pub fn open<P>(path: P, read_only: bool) -> crate::Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let root = fs::OpenOptions::new()
+            .read(true)
+            .open(path.as_ref())
+            .file_context(&path)?;
+
+        if !root.metadata().file_context(&path)?.is_dir() {
+            return Err(
+                MemFSError::NotADirectory(path.as_ref().to_string_lossy().to_string()).into(),
+            );
+        }
+
+        Ok(Self {
+            path: path.as_ref().to_owned(),
+            root,
+            read_only,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    fn check_writable(&self) -> Result<()> {
+        if self.read_only {
+            return Err(io::Error::from_raw_os_error(libc::EROFS).into());
+        }
+
+        Ok(())
+    }
+
+    /// Opens the parent dir of `path`, returning it together with the
+    /// name of the last component.
+    fn open_parent(&self, path: &Path) -> Result<(Fd, OsString)> {
+        let mut names = Vec::new();
+        for component in path.components() {
+            match component {
+                Component::RootDir => {}
+                Component::Normal(name) => names.push(name),
+                _ => return Err(MemFSError::InvalidPath(path.to_string_lossy().to_string())),
+            }
+        }
+
+        let name = names.pop().ok_or(MemFSError::IsRoot)?.to_owned();
+        let mut dir = Fd::dup(self.root.as_raw_fd())?;
+        for dir_name in names {
+            dir = dir.open_dir(dir_name)?;
+        }
+
+        Ok((dir, name))
+    }
+
+    fn open_dir(&self, path: &Path) -> Result<Fd> {
+        match self.open_parent(path) {
+            Ok((parent, name)) => parent.open_dir(&name),
+            // a fresh fd, as dup'd ones share the offset with the root and
+            // every listing after the first would come out empty
+            Err(MemFSError::IsRoot) => Fd::dup(self.root.as_raw_fd())?.open_dir(OsStr::new(".")),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+impl FsBackend for HostDir {
+    fn open(&self, path: &Path) -> Result<Box<dyn FsFile>> {
+        let (parent, name) = self.open_parent(path)?;
+        if self.read_only {
+            let fd = parent.open_file(&name, libc::O_RDONLY, 0)?;
+            return Ok(Box::new(fd.into_file()));
+        }
+
+        // files the host user can't write to can still be read
+        match parent.open_file(&name, libc::O_RDWR, 0) {
+            Ok(fd) => Ok(Box::new(fd.into_file())),
+            Err(MemFSError::Io(ref err))
+                if err.raw_os_error() == Some(libc::EACCES)
+                    || err.raw_os_error() == Some(libc::EROFS) =>
+            {
+                let errno = err.raw_os_error().unwrap();
+                let fd = parent.open_file(&name, libc::O_RDONLY, 0)?;
+                Ok(Box::new(Unwritable(fd.into_file(), errno)))
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    fn create(&self, path: &Path) -> Result<Box<dyn FsFile>> {
+        self.check_writable()?;
+
+        let (parent, name) = self.open_parent(path)?;
+        let fd = parent.open_file(&name, libc::O_RDWR | libc::O_CREAT | libc::O_TRUNC, 0o644)?;
+
+        Ok(Box::new(fd.into_file()))
+    }
+
+    fn create_dir(&self, path: &Path) -> Result<()> {
+        self.check_writable()?;
+
+        let (parent, name) = self.open_parent(path)?;
+        let name = c_name(&name)?;
+        if unsafe { libc::mkdirat(parent.0, name.as_ptr(), 0o755) } != 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+
+        Ok(())
+    }
+
+    fn read_dir(&self, path: &Path) -> Result<Vec<OsString>> {
+        let dir = self.open_dir(path)?;
+        let mut names = Vec::new();
+        for name in dir.entries()? {
+            // skip symlinks and special files
+            if dir.file_type(&name).is_ok() {
+                names.push(name);
+            }
+        }
+
+        Ok(names)
+    }
+
+    fn metadata(&self, path: &Path) -> Result<Metadata> {
+        let stat = match self.open_parent(path) {
+            Ok((parent, name)) => parent.stat_at(&name)?,
+            Err(MemFSError::IsRoot) => Fd::dup(self.root.as_raw_fd())?.stat()?,
+            Err(err) => return Err(err),
+        };
+
+        Ok(Metadata {
+            file_type: to_file_type(&stat).ok_or_else(|| not_found(path))?,
+            len: stat.st_size as u64,
+        })
+    }
+
+    fn remove(&self, path: &Path) -> Result<()> {
+        self.check_writable()?;
+
+        let (parent, name) = self.open_parent(path)?;
+        parent.file_type(&name)?;
+        parent.remove_at(&name)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        self.check_writable()?;
+
+        let (from_parent, from_name) = self.open_parent(from)?;
+        let (to_parent, to_name) = self.open_parent(to)?;
+        // makes sure a symlink is never moved under a visible name
+        from_parent.file_type(&from_name)?;
+
+        let from_name = c_name(&from_name)?;
+        let to_name = c_name(&to_name)?;
+        if unsafe {
+            libc::renameat(
+                from_parent.0,
+                from_name.as_ptr(),
+                to_parent.0,
+                to_name.as_ptr(),
+            )
+        } != 0
+        {
+            return Err(io::Error::last_os_error().into());
+        }
+
+        Ok(())
+    }
+}
+
+/// Host file only opened for reading, failing writes with the `errno`
+/// opening it for writing failed with.
+struct Unwritable(fs::File, i32);
+
+impl Read for Unwritable {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl Write for Unwritable {
+    fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+        Err(io::Error::from_raw_os_error(self.1))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for Unwritable {
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        self.0.seek(pos)
+    }
+}
+
+impl FsFile for Unwritable {
+    fn set_len(&mut self, _len: u64) -> io::Result<()> {
+        Err(io::Error::from_raw_os_error(self.1))
+    }
+}
+
+/// Owned file descriptor, closed on drop.
+#[derive(Debug)]
+struct Fd(RawFd);
+
+impl Fd {
+    fn dup(fd: RawFd) -> Result<Self> {
+        let fd = unsafe { libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, 0) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+
+        Ok(Fd(fd))
+    }
+
+    fn open_at(&self, name: &OsStr, flags: libc::c_int, mode: libc::c_uint) -> Result<Self> {
+        let c_name = c_name(name)?;
+        let fd = unsafe {
+            libc::openat(
+                self.0,
+                c_name.as_ptr(),
+                flags | libc::O_NOFOLLOW | libc::O_CLOEXEC,
+                mode,
+            )
+        };
+        if fd < 0 {
+            return Err(lookup_error(io::Error::last_os_error(), name));
+        }
+
+        Ok(Fd(fd))
+    }
+
+    fn open_dir(&self, name: &OsStr) -> Result<Self> {
+        self.open_at(name, libc::O_RDONLY | libc::O_DIRECTORY, 0)
+    }
+
+    /// Opens a regular file. Whatever is found under `name` is only
+    /// checked once opened, as it could be swapped between a lookup and
+    /// `openat`, so opening must not block on a FIFO or a device.
+    fn open_file(&self, name: &OsStr, flags: libc::c_int, mode: libc::c_uint) -> Result<Self> {
+        let fd = self.open_at(name, flags | libc::O_NONBLOCK, mode)?;
+        if fd.stat()?.st_mode & libc::S_IFMT != libc::S_IFREG {
+            return Err(not_found(name));
+        }
+
+        let flags = unsafe { libc::fcntl(fd.0, libc::F_GETFL) };
+        if flags < 0 || unsafe { libc::fcntl(fd.0, libc::F_SETFL, flags & !libc::O_NONBLOCK) } < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+
+        Ok(fd)
+    }
+
+    fn stat(&self) -> Result<libc::stat> {
+        let mut stat = unsafe { std::mem::zeroed() };
+        if unsafe { libc::fstat(self.0, &mut stat) } != 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+
+        Ok(stat)
+    }
+
+    fn stat_at(&self, name: &OsStr) -> Result<libc::stat> {
+        let c_name = c_name(name)?;
+        let mut stat = unsafe { std::mem::zeroed() };
+        if unsafe {
+            libc::fstatat(
+                self.0,
+                c_name.as_ptr(),
+                &mut stat,
+                libc::AT_SYMLINK_NOFOLLOW,
+            )
+        } != 0
+        {
+            return Err(lookup_error(io::Error::last_os_error(), name));
+        }
+
+        Ok(stat)
+    }
+
+    fn file_type(&self, name: &OsStr) -> Result<FileType> {
+        to_file_type(&self.stat_at(name)?).ok_or_else(|| not_found(name))
+    }
+
+    fn entries(&self) -> Result<Vec<OsString>> {
+        let dir = Fd::dup(self.0)?;
+        let stream = unsafe { libc::fdopendir(dir.0) };
+        if stream.is_null() {
+            return Err(io::Error::last_os_error().into());
+        }
+        // now owned by the stream
+        std::mem::forget(dir);
+
+        let mut names = Vec::new();
+        loop {
+            let entry = unsafe { libc::readdir(stream) };
+            if entry.is_null() {
+                break;
+            }
+
+            let name = unsafe { CStr::from_ptr((*entry).d_name.as_ptr()) };
+            let name = OsStr::from_bytes(name.to_bytes());
+            if name != "." && name != ".." {
+                names.push(name.to_owned());
+            }
+        }
+        unsafe { libc::closedir(stream) };
+
+        names.sort();
+        Ok(names)
+    }
+
+    fn remove_at(&self, name: &OsStr) -> Result<()> {
+        let mut flags = 0;
+        if let Ok(FileType::Dir) = self.file_type(name) {
+            let dir = self.open_dir(name)?;
+            for entry in dir.entries()? {
+                dir.remove_at(&entry)?;
+            }
+            flags = libc::AT_REMOVEDIR;
+        }
+
+        let c_name = c_name(name)?;
+        if unsafe { libc::unlinkat(self.0, c_name.as_ptr(), flags) } != 0 {
+            return Err(lookup_error(io::Error::last_os_error(), name));
+        }
+
+        Ok(())
+    }
+
+    fn into_file(self) -> fs::File {
+        let file = unsafe { fs::File::from_raw_fd(self.0) };
+        std::mem::forget(self);
+        file
+    }
+}
+
+impl Drop for Fd {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.0) };
+    }
+}
+
+fn c_name(name: &OsStr) -> Result<CString> {
+    CString::new(name.as_bytes())
+        .map_err(|_| MemFSError::InvalidPath(name.to_string_lossy().to_string()))
+}
+
+fn not_found<S>(name: S) -> MemFSError
+where
+    S: AsRef<OsStr>,
+{
+    MemFSError::NotFound(name.as_ref().to_string_lossy().to_string())
+}
+
+/// Reports components that are missing, symlinks (`ELOOP`) or not dirs
+/// as not found, so they look the same as in `MemFS`.
+fn lookup_error(err: io::Error, name: &OsStr) -> MemFSError {
+    match err.raw_os_error() {
+        Some(libc::ENOENT) | Some(libc::ELOOP) | Some(libc::ENOTDIR) => not_found(name),
+        _ => err.into(),
+    }
+}
+
+fn to_file_type(stat: &libc::stat) -> Option<FileType> {
+    match stat.st_mode & libc::S_IFMT {
+        libc::S_IFDIR => Some(FileType::Dir),
+        libc::S_IFREG => Some(FileType::File),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::os::unix::fs::{symlink, PermissionsExt};
+
+    #[test]
+    fn confinement() -> Result<()> {
+        let outside = tempfile::tempdir()?;
+        fs::write(outside.path().join("secret.txt"), b"secret")?;
+
+        let root = tempfile::tempdir()?;
+        fs::create_dir(root.path().join("a"))?;
+        fs::write(root.path().join("a/b.txt"), b"bbb")?;
+        symlink(outside.path(), root.path().join("out"))?;
+        symlink(root.path().join("a/b.txt"), root.path().join("c.txt"))?;
+
+        let fs = HostDir::open(root.path(), true).unwrap();
+        assert_eq!(fs.read_dir(Path::new("/"))?, vec![OsString::from("a")]);
+        assert_eq!(fs.read_dir(Path::new("/"))?, vec![OsString::from("a")]);
+
+        let mut contents = Vec::new();
+        fs.open(Path::new("/a/b.txt"))?.read_to_end(&mut contents)?;
+        assert_eq!(contents, b"bbb");
+
+        for path in &["/out/secret.txt", "/c.txt", "/out"] {
+            assert!(!fs.exists(Path::new(path))?);
+            assert!(fs.open(Path::new(path)).is_err());
+        }
+        assert_eq!(
+            fs.open(Path::new("/a/../../secret.txt")).err(),
+            Some(MemFSError::InvalidPath("/a/../../secret.txt".to_owned()))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn special_files() -> Result<()> {
+        let root = tempfile::tempdir()?;
+        let fifo = CString::new(root.path().join("fifo").as_os_str().as_bytes()).unwrap();
+        assert_eq!(unsafe { libc::mkfifo(fifo.as_ptr(), 0o644) }, 0);
+
+        // neither blocks waiting for a writer
+        let fs = HostDir::open(root.path(), false).unwrap();
+        assert!(fs.open(Path::new("/fifo")).is_err());
+        assert!(fs.create(Path::new("/fifo")).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn read_only() -> Result<()> {
+        let root = tempfile::tempdir()?;
+        fs::write(root.path().join("a.txt"), b"aaa")?;
+
+        let fs = HostDir::open(root.path(), true).unwrap();
+        assert!(fs.create(Path::new("/b.txt")).is_err());
+        assert!(fs.create_dir(Path::new("/b")).is_err());
+        assert!(fs.remove(Path::new("/a.txt")).is_err());
+        assert!(fs.rename(Path::new("/a.txt"), Path::new("/b.txt")).is_err());
+        assert!(fs.open(Path::new("/a.txt"))?.write_all(b"a").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn write() -> Result<()> {
+        let root = tempfile::tempdir()?;
+        let fs = HostDir::open(root.path(), false).unwrap();
+
+        fs.create_dir_all(Path::new("/a/b"))?;
+        fs.create(Path::new("/a/b/c.txt"))?.write_all(b"ccc")?;
+        assert_eq!(fs::read(root.path().join("a/b/c.txt"))?, b"ccc");
+        assert_eq!(fs.metadata(Path::new("/a/b/c.txt"))?.len, 3);
+
+        fs.rename(Path::new("/a/b/c.txt"), Path::new("/d.txt"))?;
+        assert!(root.path().join("d.txt").is_file());
+
+        fs.remove(Path::new("/a"))?;
+        assert!(!root.path().join("a").exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn unwritable_file() -> Result<()> {
+        let root = tempfile::tempdir()?;
+        fs::write(root.path().join("a.txt"), b"aaa")?;
+        fs::set_permissions(root.path().join("a.txt"), fs::Permissions::from_mode(0o444))?;
+
+        let fs = HostDir::open(root.path(), false).unwrap();
+        let mut file = fs.open(Path::new("/a.txt"))?;
+        let mut contents = Vec::new();
+        file.read_to_end(&mut contents)?;
+        assert_eq!(contents, b"aaa");
+
+        // root can write to anything
+        if unsafe { libc::geteuid() } != 0 {
+            let err = file.write_all(b"a").unwrap_err();
+            assert_eq!(err.raw_os_error(), Some(libc::EACCES));
+            assert!(file.set_len(0).is_err());
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,140 @@
+use super::vfs::{hostfs, names, VirtualFS};
+use crate::error::FileContext;
+use crate::Result;
+
+use std::io::{self, Read, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::path;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Machine-readable record of a run, saved as `manifest.json`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub args: Vec<String>,
+    /// SHA-256 of the Emscripten JS glue code.
+    pub wasm_js_sha256: String,
+    /// SHA-256 of the Wasm binary.
+    pub wasm_bin_sha256: String,
+    /// Digest of the input tree, see `VirtualFS::digest`.
+    pub input_digest: String,
+    pub outputs: Vec<ManifestOutput>,
+}
+
+/// Output file listed in a `Manifest`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ManifestOutput {
+    pub guest_path: String,
+    /// Path of the saved file on the host, or of the entry within the
+    /// output archive.
+    pub host_path: String,
+    pub size: u64,
+    pub sha256: String,
+}
+
+impl Manifest {
+    /// Writes the manifest to `path` as pretty-printed JSON, replacing
+    /// any existing file atomically.
+    pub fn save<P>(&self, path: P) -> Result<()>
+    where
+        P: AsRef<path::Path>,
+    {
+        let mut transaction = hostfs::Transaction::new(true);
+        self.save_with(&mut transaction, path)?;
+        transaction.commit()
+    }
+
+    /// Writes the manifest to `path` as part of `transaction`.
+    pub fn save_with<P>(&self, transaction: &mut hostfs::Transaction, path: P) -> Result<()>
+    where
+        P: AsRef<path::Path>,
+    {
+        log::info!("Saving manifest at {}", path.as_ref().display());
+
+        let mut file = transaction.create(path.as_ref())?;
+        serde_json::to_writer_pretty(&mut file, self)
+            .map_err(io::Error::from)
+            .file_context(&path)?;
+        file.write_all(b"\n").file_context(&path)?;
+
+        Ok(())
+    }
+}
+
+/// Returns the size and the hex-encoded SHA-256 of everything read from
+/// `reader`.
+pub fn sha256<R>(reader: &mut R) -> io::Result<(u64, String)>
+where
+    R: Read + ?Sized,
+{
+    let mut hasher = Sha256::new();
+    let mut buf = [0; 64 * 1024];
+    let mut size = 0;
+    loop {
+        let n = reader.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.input(&buf[..n]);
+        size += n as u64;
+    }
+
+    Ok((size, hex::encode(hasher.result())))
+}
+
+impl VirtualFS {
+    /// Computes a SHA-256 digest of the tree at `path`, covering the names,
+    /// kinds and contents of all dirs, files and symlinks in it, visited
+    /// in sorted order. Devices and mounted backends are left out, the
+    /// latter being live views of host dirs rather than inputs. Lazily
+    /// mapped files are hashed straight from the host, without loading
+    /// them.
+    pub fn digest<P>(&mut self, path: P) -> Result<String>
+    where
+        P: AsRef<path::Path>,
+    {
+        let mut hasher = Sha256::new();
+        self.digest_dir(path.as_ref(), &mut hasher)?;
+
+        Ok(hex::encode(hasher.result()))
+    }
+
+    fn digest_dir(&mut self, dir: &path::Path, hasher: &mut Sha256) -> Result<()> {
+        let mut names = self.read_dir(dir)?;
+        names.sort();
+
+        for name in names {
+            let path = dir.join(&name);
+            if self.mount_points().any(|mount_point| mount_point == path) {
+                continue;
+            }
+            let metadata = self.metadata(&path)?;
+
+            if metadata.is_dir() {
+                digest_entry(hasher, b'D', &path, &[]);
+                self.digest_dir(&path, hasher)?;
+            } else if metadata.is_file() {
+                let mut file_hasher = Sha256::new();
+                self.write_to(&path, &mut file_hasher)?;
+                let sha256 = hex::encode(file_hasher.result());
+                digest_entry(hasher, b'F', &path, sha256.as_bytes());
+            } else if metadata.is_symlink() {
+                let target = self.read_link(&path)?;
+                digest_entry(hasher, b'L', &path, target.as_os_str().as_bytes());
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// each entry as `kind path \0 data \0`, with the path as seen by the guest
+fn digest_entry(hasher: &mut Sha256, kind: u8, path: &path::Path, data: &[u8]) {
+    let guest_path = names::to_guest(path);
+    hasher.input([kind]);
+    hasher.input(guest_path.as_bytes());
+    hasher.input([0]);
+    hasher.input(data);
+    hasher.input([0]);
+}
@@ -1,43 +1,228 @@
+pub mod archive;
+pub mod convert;
 pub mod engine;
+pub mod env;
+pub mod host;
+pub mod hostdir;
+pub mod manifest;
+pub mod random;
+mod spfs;
+pub mod stdio;
 pub mod vfs;
 
+use self::archive::ArchiveEntry;
 use self::engine::*;
+use self::hostdir::HostDir;
+use self::manifest::{Manifest, ManifestOutput};
+use self::stdio::{Sink, Stdin, Stdio};
 use self::vfs::*;
-use super::Result;
+use super::{Error, Result};
 
+use std::ffi::OsStr;
+use std::fs;
+use std::os::unix::ffi::OsStrExt;
 use std::path::{self, Path};
-use std::sync::Mutex;
+use std::time::Duration;
 
 use itertools::Itertools;
-use lazy_static::lazy_static;
 
-lazy_static! {
-    static ref VFS: Mutex<VirtualFS> = Mutex::new(VirtualFS::default());
-}
+// `r-xr-xr-x` and `r--r--r--`
+const READ_ONLY_DIR: u32 = 0o555;
+const READ_ONLY_FILE: u32 = 0o444;
 
 pub struct Sandbox {
     runtime: Runtime,
+    // body of Module['preRun'], evaluated just before the glue code
+    pre_run: String,
+    map_options: MapOptions,
+    // of all inputs mapped so far, checked against the limits as a whole
+    usage: Usage,
+    manifest: Manifest,
+    manifest_path: Option<path::PathBuf>,
+    overwrite: bool,
+    spfs: bool,
+    cwd: path::PathBuf,
+    // guest paths of read-only mounts, enforced from `run` on
+    read_only: Vec<path::PathBuf>,
 }
 
 impl Sandbox {
     pub fn new(engine: &Engine) -> Result<Self> {
         let runtime = Runtime::new(engine)?;
-        Ok(Self { runtime })
+        // Include our version of '_usleep' function, and the guest's
+        // environment, see `env::ENV_JS`
+        let pre_run = "\n\t_usleep = usleep;\n\tgolem_setupEnv();".to_string();
+        Ok(Self {
+            runtime,
+            pre_run,
+            map_options: MapOptions::default(),
+            usage: Usage::default(),
+            manifest: Manifest::default(),
+            manifest_path: None,
+            overwrite: false,
+            spfs: false,
+            cwd: path::PathBuf::from("/"),
+            read_only: Vec::new(),
+        })
+    }
+
+    /// Sets the options used to map input files into the `VirtualFS` in
+    /// subsequent calls to `load_input_files`, `load_input_archive` and
+    /// `mount`. Limits apply to all inputs together, however many calls
+    /// they are loaded with; passthrough mounts don't count.
+    pub fn set_map_options(mut self, map_options: MapOptions) -> Result<Self> {
+        log::info!("Setting map options {:?}", map_options);
+        self.map_options = map_options;
+
+        Ok(self)
+    }
+
+    /// Makes `save_output_files` and `save_outputs_to_archive` write a
+    /// `Manifest` of the run to `manifest_path`.
+    pub fn set_manifest_path<S>(mut self, manifest_path: S) -> Result<Self>
+    where
+        S: AsRef<Path>,
+    {
+        log::info!("Setting manifest path {}", manifest_path.as_ref().display());
+        self.manifest_path = Some(manifest_path.as_ref().to_owned());
+
+        Ok(self)
+    }
+
+    /// Allows `save_output_files` and `save_outputs_to_archive` to replace
+    /// existing files on the host.
+    pub fn set_overwrite(mut self, overwrite: bool) -> Result<Self> {
+        log::info!("Setting overwrite {}", overwrite);
+        self.overwrite = overwrite;
+
+        Ok(self)
+    }
+
+    /// Makes the guest work on the `VirtualFS` directly through `SPFS`,
+    /// instead of on copies of the inputs in Emscripten's MEMFS. Has to be
+    /// set before any inputs are loaded.
+    pub fn set_spfs(mut self, spfs: bool) -> Result<Self> {
+        log::info!("Setting SPFS {}", spfs);
+        self.spfs = spfs;
+
+        Ok(self)
+    }
+
+    /// Sets the working dir of the guest, which is created if it doesn't
+    /// exist. Relative output paths are taken from it as well. It is passed
+    /// on as `PWD`, so it has to fit in the environment, see `set_env`.
+    pub fn set_cwd<S>(mut self, cwd: S) -> Result<Self>
+    where
+        S: AsRef<Path>,
+    {
+        log::info!("Setting cwd {}", cwd.as_ref().display());
+
+        if !cwd.as_ref().has_root() {
+            return Err(Error::InvalidPath(
+                cwd.as_ref().to_string_lossy().to_string(),
+            ));
+        }
+        self.cwd = Path::new("/").join(hostfs::sanitize_path(cwd.as_ref())?);
+        self.runtime
+            .env()
+            .insert("PWD".to_owned(), names::to_guest(&self.cwd));
+        env::check_size(&self.runtime.env())?;
+
+        Ok(self)
+    }
+
+    /// Fails the run with `Failure::Timeout` if it takes longer than
+    /// `timeout`.
+    pub fn set_timeout(self, timeout: Duration) -> Result<Self> {
+        log::info!("Setting timeout {:?}", timeout);
+        self.runtime.set_timeout(Some(timeout));
+
+        Ok(self)
+    }
+
+    /// Feeds `stdin`, either bytes or the path of a host file, to the
+    /// guest's standard input. Reads past its end return EOF.
+    pub fn set_stdin<S>(self, stdin: S) -> Result<Self>
+    where
+        S: Into<Stdin>,
+    {
+        let stdin = stdin.into();
+        match stdin {
+            Stdin::Bytes(ref bytes) => log::info!("Setting stdin to {} byte(s)", bytes.len()),
+            Stdin::File(ref path) => log::info!("Setting stdin to {}", path.display()),
+        }
+
+        self.runtime.stdio().stdin = Some(stdin.open()?);
+        self.runtime
+            .evaluate::<(), _>("Module['stdin'] = golem_readStdin;")?;
+
+        Ok(self)
+    }
+
+    /// Sends the guest's standard output to `sink`.
+    pub fn set_stdout(self, sink: Sink) -> Result<Self> {
+        log::info!("Setting stdout to {:?}", sink);
+        self.runtime.stdio().stdout.sink = sink;
+
+        Ok(self)
+    }
+
+    /// Sends the guest's standard error to `sink`.
+    pub fn set_stderr(self, sink: Sink) -> Result<Self> {
+        log::info!("Setting stderr to {:?}", sink);
+        self.runtime.stdio().stderr.sink = sink;
+
+        Ok(self)
+    }
+
+    /// Caps both stdout and stderr of the guest at `limit` bytes each;
+    /// anything written past it is dropped.
+    pub fn set_max_stdio_size(self, limit: u64) -> Result<Self> {
+        log::info!("Setting max stdio size {}", limit);
+        {
+            let mut stdio = self.runtime.stdio();
+            stdio.stdout.limit = Some(limit);
+            stdio.stderr.limit = Some(limit);
+        }
+
+        Ok(self)
+    }
+
+    /// Sets environment variables of the guest, on top of
+    /// `env::default_env` and any set before. The whole environment has to
+    /// fit in what Emscripten allows, see `env::check_size`.
+    pub fn set_env<It, K, V>(self, vars: It) -> Result<Self>
+    where
+        It: IntoIterator<Item = (K, V)>,
+        K: Into<String>,
+        V: Into<String>,
+    {
+        for (name, value) in vars {
+            let (name, value) = (name.into(), value.into());
+            env::check_var(&name, &value)?;
+            // values may well be secrets
+            log::info!("Setting env {}", name);
+            self.runtime.env().insert(name, value);
+        }
+        env::check_size(&self.runtime.env())?;
+
+        Ok(self)
     }
 
-    pub fn set_exec_args<It>(self, exec_args: It) -> Result<Self>
+    pub fn set_exec_args<It>(mut self, exec_args: It) -> Result<Self>
     where
         It: IntoIterator,
         It::Item: AsRef<str>,
     {
-        let exec_args = exec_args
+        self.manifest.args = exec_args
             .into_iter()
-            .map(|s| format!("'{}'", s.as_ref()))
-            .join(", ");
+            .map(|s| s.as_ref().to_owned())
+            .collect();
+        let exec_args = self.manifest.args.iter().map(|s| js_string(s)).join(", ");
         log::info!("Setting exec args [ {} ]", exec_args);
 
         let js = format!("Module['arguments'] = [ {} ];", exec_args);
-        self.runtime.evaluate_script(&js)?;
+        self.runtime.evaluate::<(), _>(&js)?;
 
         Ok(self)
     }
@@ -48,93 +233,310 @@ impl Sandbox {
     {
         log::info!("Loading input files at {}", input_path.as_ref().display());
 
-        // Include our version of '_usleep' function
-        let mut js = "
-        Module['preRun'] = function() {
-            _usleep = usleep;
-        "
-        .to_string();
-
-        VFS.lock()
-            .unwrap()
-            .map_path(input_path.as_ref(), "/", &mut |source_path, dest_path| {
-                let dest_path_s: String = dest_path.to_string_lossy().into();
-                if source_path.is_dir() {
-                    // create dir
-                    js += &format!("FS.mkdir('{}');", dest_path_s);
-                } else {
-                    // create file
-                    js += &format!(
-                        "\n\tFS.writeFile('{}', new Uint8Array(readFile('{}')));",
-                        dest_path_s, dest_path_s
-                    );
+        self.mount(input_path, "/", false, false)
+    }
+
+    /// Loads the contents of a `.tar`, `.tar.gz` or `.zip` archive as the
+    /// input tree, without unpacking it on the host.
+    pub fn load_input_archive<S>(mut self, archive_path: S) -> Result<Self>
+    where
+        S: AsRef<Path>,
+    {
+        log::info!("Loading input archive {}", archive_path.as_ref().display());
+
+        if !self.map_options.limits.is_unlimited() {
+            let usage = Usage::measure_archive(archive_path.as_ref(), &self.map_options)?;
+            self.add_usage(Path::new("/"), &usage)?;
+        }
+
+        let options = self.unlimited_map_options();
+        let js = &mut self.pre_run;
+        let spfs = self.spfs;
+        self.runtime.vfs().map_archive(
+            archive_path.as_ref(),
+            "/",
+            &options,
+            &mut |dest_path, entry| {
+                if spfs {
+                    // already visible to the guest
+                    return;
+                }
+
+                let dest_path_js = js_string(&names::to_guest(dest_path));
+                match entry {
+                    ArchiveEntry::Dir => {
+                        // may clash with one of Emscripten's default dirs
+                        *js += &format!("\n\tFS.mkdirTree({});", dest_path_js);
+                    }
+                    ArchiveEntry::Symlink(target) => {
+                        *js += &format!(
+                            "\n\tFS.symlink({}, {});",
+                            js_string(&names::to_guest(target)),
+                            dest_path_js
+                        );
+                    }
+                    ArchiveEntry::File(_) => {
+                        *js += &format!(
+                            "\n\tFS.writeFile({}, new Uint8Array(readFile({})));",
+                            dest_path_js, dest_path_js
+                        );
+                    }
                 }
-            })?;
+            },
+        )?;
+
+        Ok(self)
+    }
+
+    /// Maps the contents of the host dir `host_path` onto `guest_path`,
+    /// optionally making them read-only for the guest.
+    ///
+    /// With `passthrough`, the host dir is exposed to the guest as it is,
+    /// see `HostDir`, instead of being mapped into memory. With SPFS, its
+    /// contents are only read when the guest uses them, and anything the
+    /// guest writes there goes straight to the host. Without it, they are
+    /// copied into Emscripten's MEMFS when the guest starts, and what the
+    /// guest writes there only reaches the host once outputs are saved.
+    /// Such a mount can't take over `/`.
+    pub fn mount<S1, S2>(
+        mut self,
+        host_path: S1,
+        guest_path: S2,
+        read_only: bool,
+        passthrough: bool,
+    ) -> Result<Self>
+    where
+        S1: AsRef<Path>,
+        S2: AsRef<Path>,
+    {
+        log::info!(
+            "Mounting {} at {}{}{}",
+            host_path.as_ref().display(),
+            guest_path.as_ref().display(),
+            if read_only { " (read-only)" } else { "" },
+            if passthrough { " (passthrough)" } else { "" }
+        );
+
+        if !guest_path.as_ref().has_root() || (passthrough && guest_path.as_ref() == Path::new("/"))
+        {
+            return Err(Error::InvalidPath(
+                guest_path.as_ref().to_string_lossy().to_string(),
+            ));
+        }
+
+        if !passthrough && !self.map_options.limits.is_unlimited() {
+            let usage = Usage::measure(host_path.as_ref(), &self.map_options)?;
+            self.add_usage(guest_path.as_ref(), &usage)?;
+        }
+
+        let mut vfs = self.runtime.vfs();
+        vfs.create_dir_all(guest_path.as_ref())?;
 
-        js += "\n};";
-        self.runtime.evaluate_script(&js)?;
+        let options = self.unlimited_map_options();
+        let js = &mut self.pre_run;
+        let spfs = self.spfs;
+        let guest_path_js = js_string(&names::to_guest(guest_path.as_ref()));
+        if !spfs {
+            *js += &format!("\n\tFS.mkdirTree({});", guest_path_js);
+        }
+
+        if passthrough {
+            let host_dir = HostDir::open(host_path.as_ref(), read_only)?;
+            vfs.mount(guest_path.as_ref(), host_dir)?;
+            if !spfs {
+                passthrough_js(&vfs, guest_path.as_ref(), read_only, js)?;
+            }
+        } else {
+            vfs.map_path_with(
+                host_path.as_ref(),
+                guest_path.as_ref(),
+                &options,
+                &mut |source_path, dest_path| {
+                    let dest_path_js = js_string(&names::to_guest(dest_path));
+                    if spfs {
+                        // already visible to the guest, only permissions are
+                        // kept in JS
+                        if read_only && fs::read_link(source_path).is_err() {
+                            let mode = if source_path.is_dir() {
+                                READ_ONLY_DIR
+                            } else {
+                                READ_ONLY_FILE
+                            };
+                            *js += &format!("\n\tFS.chmod({}, {});", dest_path_js, mode);
+                        }
+                    } else if let Ok(target) = fs::read_link(source_path) {
+                        // preserved symlink
+                        *js += &format!(
+                            "\n\tFS.symlink({}, {});",
+                            js_string(&names::to_guest(target)),
+                            dest_path_js
+                        );
+                    } else if source_path.is_dir() {
+                        // create dir
+                        *js += &format!("\n\tFS.mkdir({});", dest_path_js);
+                        if read_only {
+                            *js += &format!("\n\tFS.chmod({}, {});", dest_path_js, READ_ONLY_DIR);
+                        }
+                    } else {
+                        // create file
+                        *js += &format!(
+                            "\n\tFS.writeFile({}, new Uint8Array(readFile({})));",
+                            dest_path_js, dest_path_js
+                        );
+                        if read_only {
+                            *js += &format!("\n\tFS.chmod({}, {});", dest_path_js, READ_ONLY_FILE);
+                        }
+                    }
+                },
+            )?;
+        }
+        drop(vfs);
+
+        // Emscripten only enforces permissions once `main` is entered, so
+        // none of the above is affected. The guest could change them back,
+        // the `VirtualFS` is made read-only there as well once it runs.
+        if read_only {
+            *js += &format!("\n\tFS.chmod({}, {});", guest_path_js, READ_ONLY_DIR);
+            self.read_only.push(guest_path.as_ref().to_owned());
+        }
 
         Ok(self)
     }
 
-    pub fn run<S>(self, wasm_js: S, wasm_bin: S) -> Result<Self>
+    /// Adds `usage` of an input tree mapped onto `dest_path` to that of
+    /// all inputs so far, failing if the total exceeds the limits.
+    fn add_usage(&mut self, dest_path: &Path, usage: &Usage) -> Result<()> {
+        self.usage.add_tree(dest_path, usage);
+        log::debug!("input usage = {:?}", self.usage);
+        self.map_options.limits.check(&self.usage)
+    }
+
+    /// Map options without limits, as those are already checked by
+    /// `add_usage`.
+    fn unlimited_map_options(&self) -> MapOptions {
+        MapOptions {
+            limits: Limits::default(),
+            ..self.map_options.clone()
+        }
+    }
+
+    pub fn run<S>(mut self, wasm_js: S, wasm_bin: S) -> Result<Self>
     where
         S: AsRef<Path>,
     {
         log::info!("Running WASM {}", wasm_bin.as_ref().display());
 
-        VFS.lock()
-            .unwrap()
-            .map_file(wasm_bin.as_ref(), Path::new("/main.wasm"))?;
-
-        let mut js = "Module['wasmBinary'] = readFile('/main.wasm');".to_string();
         let wasm_js = hostfs::read_file(wasm_js.as_ref())?;
+
+        {
+            let mut vfs = self.runtime.vfs();
+            if self.manifest_path.is_some() {
+                self.manifest.input_digest = vfs.digest("/")?;
+                self.manifest.wasm_js_sha256 = manifest::sha256(&mut &wasm_js[..])?.1;
+            }
+
+            vfs.create_dir_all(&self.cwd)?;
+            vfs.map_file(wasm_bin.as_ref(), Path::new("/main.wasm"))?;
+            if self.manifest_path.is_some() {
+                let mut file = vfs.open_file("/main.wasm")?;
+                self.manifest.wasm_bin_sha256 = manifest::sha256(&mut file)?.1;
+            }
+            // inputs become read-only, everything written from now on
+            // ends up in the upper layer
+            vfs.seal();
+            for path in &self.read_only {
+                vfs.set_read_only(path)?;
+            }
+
+            // replace Emscripten's own devices with the ones from VirtualFS
+            for name in vfs.read_dir("/dev")? {
+                if vfs.is_device(Path::new("/dev").join(&name))? {
+                    self.pre_run += &format!(
+                        "\n\tgolem_createDevice('/dev', {});",
+                        js_string(&names::to_guest(&name))
+                    );
+                }
+            }
+        }
+
+        let cwd_js = js_string(&names::to_guest(&self.cwd));
+        self.pre_run += &format!("\n\tFS.mkdirTree({0});\n\tFS.chdir({0});", cwd_js);
+
+        let mount_spfs = if self.spfs {
+            "\n\tgolem_mountSPFS();"
+        } else {
+            ""
+        };
+        let mut js = format!(
+            "Module['preRun'] = function() {{{}{}\n}};",
+            mount_spfs, self.pre_run
+        );
+        js += "Module['wasmBinary'] = readFile('/main.wasm');";
         let wasm_js = String::from_utf8(wasm_js)?;
         js += &wasm_js;
-        self.runtime.evaluate_script(&js)?;
+        let result = self.runtime.evaluate::<(), _>(&js);
+
+        // whatever the guest printed before failing is still of interest
+        {
+            let mut stdio = self.runtime.stdio();
+            stdio.stdout.flush()?;
+            stdio.stderr.flush()?;
+        }
+        result?;
 
         Ok(self)
     }
 
-    pub fn save_output_files<S, It>(self, output_path: S, output_files: It) -> Result<()>
+    /// Standard output of the guest, if its sink is `Sink::Memory` or
+    /// `Sink::File`.
+    pub fn stdout(&self) -> Vec<u8> {
+        self.runtime.stdio().stdout.contents().to_vec()
+    }
+
+    /// Standard error of the guest, if its sink is `Sink::Memory` or
+    /// `Sink::File`.
+    pub fn stderr(&self) -> Vec<u8> {
+        self.runtime.stdio().stderr.contents().to_vec()
+    }
+
+    /// Saves the output files under `output_path`. Nothing is saved unless
+    /// all of them can be, and existing files are only replaced if
+    /// `set_overwrite` allows it.
+    ///
+    /// Outputs marked as optional with a trailing `?`, e.g. `log.txt?`, are
+    /// skipped if the guest didn't create them; all other missing outputs
+    /// are reported together with `Error::MissingOutputs`.
+    ///
+    /// Relative output paths are looked up in the guest's cwd, see
+    /// `set_cwd`, but saved under `output_path` just as given.
+    pub fn save_output_files<S, It>(mut self, output_path: S, output_files: It) -> Result<()>
     where
         S: AsRef<Path>,
         It: IntoIterator,
         It::Item: AsRef<Path>,
     {
+        let mut transaction = hostfs::Transaction::new(self.overwrite);
+        let mut missing = Vec::new();
         for output_file in output_files {
             // sanitize output file path (may contain subdirs)
-            let output_file = hostfs::sanitize_path(output_file.as_ref())?;
+            let (output_file, output_vfs_path, optional) =
+                parse_output_file(output_file.as_ref(), &self.cwd)?;
 
-            // create subdirs if they don't exist
-            let mut output_vfs_path = path::PathBuf::from("/");
-            output_vfs_path.push(output_file.as_path());
-
-            if let Some(p) = output_vfs_path.parent() {
-                VFS.lock().unwrap().create_dir_all(p)?;
+            if !self.copy_output_file(&output_vfs_path)? {
+                if optional {
+                    log::warn!("Skipping missing optional output {}", output_file.display());
+                } else {
+                    missing.push(output_file);
+                }
+                continue;
             }
-
-            // copy files from JS_FS to MemFS
-            let output_vfs_path_str: String = output_vfs_path.as_path().to_string_lossy().into();
-            self.runtime.evaluate_script(&format!(
-                "
-                try {{
-                    writeFile('{}', FS.readFile('{}'));
-                }}
-                catch(error) {{
-                    throw new Error(\"Error writing to file '{}': \" + error);
-                }}",
-                output_vfs_path_str, output_vfs_path_str, output_vfs_path_str
-            ))?;
-
-            // create files on the host
-            let mut output_hostfs_path = path::PathBuf::from(output_path.as_ref());
-
-            if let Some(p) = output_file.parent() {
-                log::debug!("Creating subdirs={:?}", output_hostfs_path.join(p));
-                hostfs::create_dir_all(output_hostfs_path.join(p))?;
+            if !missing.is_empty() {
+                // bound to fail, only look for other missing outputs
+                continue;
             }
 
+            // create files on the host, subdirs come with the transaction
+            let mut output_hostfs_path = path::PathBuf::from(output_path.as_ref());
             output_hostfs_path.push(output_file.as_path());
 
             log::info!(
@@ -142,14 +544,273 @@ impl Sandbox {
                 output_hostfs_path.as_path().to_string_lossy()
             );
 
-            let contents = VFS.lock().unwrap().read_file(output_vfs_path)?;
-            hostfs::write_file(output_hostfs_path, &contents)?;
+            let mut file = transaction.create(&output_hostfs_path)?;
+            self.runtime.vfs().write_to(&output_vfs_path, &mut file)?;
+            self.record_output(&output_vfs_path, &output_hostfs_path)?;
+        }
+
+        if !missing.is_empty() {
+            return Err(Error::MissingOutputs(missing));
+        }
+
+        save_stdio(
+            &self.runtime.stdio(),
+            output_path.as_ref(),
+            &mut transaction,
+        )?;
+        self.save_manifest(&mut transaction)?;
+        transaction.commit()
+    }
+
+    /// Packs the output files into a single `.tar` or `.tar.gz` archive at
+    /// `archive_path`. If no output files are given, all files created or
+    /// modified by the run are packed instead. Missing and optional outputs
+    /// are handled as in `save_output_files`.
+    pub fn save_outputs_to_archive<S, It>(mut self, archive_path: S, output_files: It) -> Result<()>
+    where
+        S: AsRef<Path>,
+        It: IntoIterator,
+        It::Item: AsRef<Path>,
+    {
+        let mut output_vfs_paths = Vec::new();
+        let mut missing = Vec::new();
+        let mut any_requested = false;
+        for output_file in output_files {
+            any_requested = true;
+            let (output_file, output_vfs_path, optional) =
+                parse_output_file(output_file.as_ref(), &self.cwd)?;
+            if self.copy_output_file(&output_vfs_path)? {
+                output_vfs_paths.push(output_vfs_path);
+            } else if optional {
+                log::warn!("Skipping missing optional output {}", output_file.display());
+            } else {
+                missing.push(output_file);
+            }
+        }
+
+        if !missing.is_empty() {
+            return Err(Error::MissingOutputs(missing));
+        }
+
+        if !any_requested {
+            output_vfs_paths = self.copy_changed_files()?;
+        }
+        // in the order they end up in the archive
+        output_vfs_paths.sort();
+        output_vfs_paths.dedup();
+
+        log::info!(
+            "Saving {} output(s) to archive {}",
+            output_vfs_paths.len(),
+            archive_path.as_ref().display()
+        );
+
+        // the archive is only kept if stdio and the manifest are saved too
+        let mut transaction = hostfs::Transaction::new(self.overwrite);
+        self.runtime.vfs().save_archive(
+            &mut transaction,
+            archive_path.as_ref(),
+            &output_vfs_paths,
+        )?;
+
+        for output_vfs_path in &output_vfs_paths {
+            self.record_output(output_vfs_path, output_vfs_path.strip_prefix("/")?)?;
         }
 
+        if let Some(dir) = archive_path.as_ref().parent() {
+            save_stdio(&self.runtime.stdio(), dir, &mut transaction)?;
+        }
+        self.save_manifest(&mut transaction)?;
+        transaction.commit()
+    }
+
+    /// Adds a saved output file to the manifest, if there is one.
+    fn record_output(&mut self, output_vfs_path: &Path, host_path: &Path) -> Result<()> {
+        if self.manifest_path.is_none() {
+            return Ok(());
+        }
+
+        let mut file = self.runtime.vfs().open_file(output_vfs_path)?;
+        let (size, sha256) = manifest::sha256(&mut file)?;
+        self.manifest.outputs.push(ManifestOutput {
+            guest_path: names::to_guest(output_vfs_path),
+            host_path: host_path.to_string_lossy().to_string(),
+            size,
+            sha256,
+        });
+
         Ok(())
     }
 
+    fn save_manifest(&self, transaction: &mut hostfs::Transaction) -> Result<()> {
+        match self.manifest_path {
+            Some(ref manifest_path) => self.manifest.save_with(transaction, manifest_path),
+            None => Ok(()),
+        }
+    }
+
+    /// Copies a file from JS_FS to the `VirtualFS`, returning `false` if
+    /// the guest didn't create it.
+    fn copy_output_file(&self, output_vfs_path: &Path) -> Result<bool> {
+        if self.spfs {
+            return self.runtime.vfs().is_file(output_vfs_path);
+        }
+
+        let output_vfs_path_js = js_string(&names::to_guest(output_vfs_path));
+        self.runtime.evaluate(&format!(
+            "
+            (function() {{
+                var info = FS.analyzePath({0});
+                if (!info.exists || !FS.isFile(info.object.mode)) {{
+                    return false;
+                }}
+                writeFile({0}, FS.readFile({0}));
+                return true;
+            }})();",
+            output_vfs_path_js
+        ))
+    }
+
+    /// Copies all files in JS_FS which are missing from the `VirtualFS` or
+    /// differ from their inputs there, and returns their paths.
+    fn copy_changed_files(&self) -> Result<Vec<path::PathBuf>> {
+        if self.spfs {
+            return self.runtime.vfs().changed_files();
+        }
+
+        self.runtime.evaluate::<(), _>(
+            "
+            (function() {
+                function equal(left, right) {
+                    if (left.length !== right.length) {
+                        return false;
+                    }
+                    for (var i = 0; i < left.length; i++) {
+                        if (left[i] !== right[i]) {
+                            return false;
+                        }
+                    }
+                    return true;
+                }
+
+                function copyChanged(dir) {
+                    FS.readdir(dir).forEach(function(name) {
+                        var path = (dir === '/' ? '' : dir) + '/' + name;
+                        if (name === '.' || name === '..' || path === '/dev' || path === '/proc') {
+                            return;
+                        }
+
+                        var mode = FS.lstat(path).mode;
+                        if (FS.isDir(mode)) {
+                            copyChanged(path);
+                        } else if (FS.isFile(mode)) {
+                            var contents = FS.readFile(path);
+                            var original = null;
+                            try {
+                                original = new Uint8Array(readFile(path));
+                            } catch (error) {}
+
+                            if (original === null || !equal(original, contents)) {
+                                writeFile(path, contents);
+                            }
+                        }
+                    });
+                }
+
+                copyChanged('/');
+            })();",
+        )?;
+
+        self.runtime.vfs().changed_files()
+    }
+
     pub fn runtime(&self) -> &Runtime {
         &self.runtime
     }
 }
+
+/// Recreates the passthrough mount at `dir` in JS_FS, with files read
+/// from the `VirtualFS` as the guest starts.
+fn passthrough_js(vfs: &VirtualFS, dir: &Path, read_only: bool, js: &mut String) -> Result<()> {
+    for name in vfs.read_dir(dir)? {
+        let path = dir.join(&name);
+        let path_js = js_string(&names::to_guest(&path));
+        if vfs.is_dir(&path)? {
+            *js += &format!("\n\tFS.mkdir({});", path_js);
+            passthrough_js(vfs, &path, read_only, js)?;
+            if read_only {
+                *js += &format!("\n\tFS.chmod({}, {});", path_js, READ_ONLY_DIR);
+            }
+        } else {
+            *js += &format!(
+                "\n\tFS.writeFile({}, new Uint8Array(readFile({})));",
+                path_js, path_js
+            );
+            if read_only {
+                *js += &format!("\n\tFS.chmod({}, {});", path_js, READ_ONLY_FILE);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Splits the `?` marking an optional output off `output_file` and
+/// sanitizes the rest, which is also returned as a path in the guest with
+/// relative paths taken from `cwd`.
+fn parse_output_file(
+    output_file: &Path,
+    cwd: &Path,
+) -> Result<(path::PathBuf, path::PathBuf, bool)> {
+    let bytes = output_file.as_os_str().as_bytes();
+    let (output_file, optional) = match bytes.split_last() {
+        Some((b'?', rest)) => (Path::new(OsStr::from_bytes(rest)), true),
+        _ => (output_file, false),
+    };
+
+    let output_vfs_path = Path::new("/").join(hostfs::sanitize_path(cwd.join(output_file))?);
+    Ok((
+        hostfs::sanitize_path(output_file)?,
+        output_vfs_path,
+        optional,
+    ))
+}
+
+/// Saves stdout and stderr of the guest under `output_path`, for those
+/// with a `Sink::File`.
+fn save_stdio(
+    stdio: &Stdio,
+    output_path: &Path,
+    transaction: &mut hostfs::Transaction,
+) -> Result<()> {
+    for stream in &[&stdio.stdout, &stdio.stderr] {
+        if let Sink::File(ref path) = stream.sink {
+            let path = output_path.join(hostfs::sanitize_path(path)?);
+            log::info!("Saving guest output at {}", path.display());
+            transaction.write_file(&path, &mut stream.contents())?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Quotes `s` as a JavaScript string literal.
+fn js_string(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('\'');
+    for c in s.chars() {
+        match c {
+            '\'' => quoted.push_str("\\'"),
+            '\\' => quoted.push_str("\\\\"),
+            ' '..='~' => quoted.push(c),
+            _ => {
+                let mut buf = [0; 2];
+                for unit in c.encode_utf16(&mut buf) {
+                    quoted.push_str(&format!("\\u{:04x}", unit));
+                }
+            }
+        }
+    }
+    quoted.push('\'');
+    quoted
+}
@@ -0,0 +1,58 @@
+use sp_wasm_memfs::prelude::*;
+use std::io;
+use std::sync::{Arc, Mutex};
+
+/// Fully deterministic replacement for the random device.
+///
+/// Every `Runtime` has one of its own, starting from the same state, which
+/// backs both `crypto.getRandomValues` on the JS side and the
+/// `/dev/urandom` device in its `VirtualFS`.
+#[derive(Debug, Default)]
+pub struct RandEmu {
+    state: f64,
+}
+
+impl RandEmu {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn reset(&mut self) {
+        self.state = 0.0;
+    }
+
+    pub fn next_f64(&mut self) -> f64 {
+        // computed the same way SpiderMonkey evaluates `Math.pow(x, 3)`
+        let x = self.state + 1.8912;
+        self.state = (x * (x * x)) % 1.0;
+        self.state
+    }
+
+    pub fn next_u8(&mut self) -> u8 {
+        (self.next_f64() * 256.0) as u8
+    }
+}
+
+/// `/dev/urandom` backed by a `RandEmu` generator, possibly shared.
+#[derive(Debug, Default)]
+pub struct Urandom(Arc<Mutex<RandEmu>>);
+
+impl Urandom {
+    pub fn new(rand_emu: Arc<Mutex<RandEmu>>) -> Self {
+        Urandom(rand_emu)
+    }
+}
+
+impl Device for Urandom {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut rand_emu = self.0.lock().unwrap();
+        for byte in buf.iter_mut() {
+            *byte = rand_emu.next_u8();
+        }
+        Ok(buf.len())
+    }
+
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        Ok(buf.len())
+    }
+}
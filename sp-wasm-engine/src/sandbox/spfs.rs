@@ -0,0 +1,677 @@
+use super::engine::js_string_to_utf8;
+use super::host::Host;
+use super::vfs::{names, FileType, MemFSError};
+use crate::{Error, Result};
+use mozjs::{
+    jsapi::{
+        CallArgs, JSContext, JSNative, JS_DefineFunction, JS_NewUCStringCopyN, JS_ReportErrorASCII,
+        Value,
+    },
+    jsval::{DoubleValue, Int32Value, StringValue},
+    rust::{Handle, HandleObject, ToString, ToUint64},
+};
+use std::cmp;
+use std::convert::TryFrom;
+use std::ffi::OsString;
+use std::io;
+use std::ops::Range;
+
+// file type bits of `st_mode`, as used by Emscripten
+const S_IFDIR: i32 = 0o040000;
+const S_IFREG: i32 = 0o100000;
+const S_IFLNK: i32 = 0o120000;
+
+/// Emscripten filesystem backed by the `VirtualFS`.
+///
+/// Every node and stream operation calls one of the `golem_fs*` natives,
+/// which return a negative index into `SPFS.errnos` on failure, as
+/// Emscripten numbers errors differently than the host does. Reads and
+/// writes go straight between the Wasm heap and the `VirtualFS`, so
+/// nothing is copied into JS and outputs don't need to be copied out
+/// after the run. Devices outside of `/dev` show up as regular files.
+///
+/// `golem_mountSPFS` replaces Emscripten's root with `SPFS`, keeping
+/// MEMFS at `/dev` for Emscripten's own devices.
+pub(super) const SPFS_JS: &str = "
+var SPFS = {
+    // same order as `ERRNOS`
+    errnos: ['EIO', 'EEXIST', 'ENOENT', 'EINVAL', 'ENOTDIR', 'EISDIR', 'ENOTEMPTY', 'EBUSY', 'ELOOP', 'EACCES', 'EPERM', 'EROFS', 'ENOSPC', 'EFBIG', 'EXDEV', 'ENAMETOOLONG', 'EFAULT'],
+    mount: function(mount) {
+        return SPFS.createNode(null, '/', SPFS.getMode(mount.opts.root || '/'), 0);
+    },
+    createNode: function(parent, name, mode, dev) {
+        if (!FS.isDir(mode) && !FS.isFile(mode) && !FS.isLink(mode)) {
+            throw new FS.ErrnoError(ERRNO_CODES.EINVAL);
+        }
+        var node = FS.createNode(parent, name, mode, dev);
+        node.node_ops = SPFS.node_ops;
+        node.stream_ops = SPFS.stream_ops;
+        return node;
+    },
+    check: function(result) {
+        if (typeof result === 'number' && result < 0) {
+            throw new FS.ErrnoError(ERRNO_CODES[SPFS.errnos[-result - 1]]);
+        }
+        return result;
+    },
+    getMode: function(path) {
+        var type = SPFS.check(golem_fsType(path));
+        return type | (FS.isFile(type) ? 438 : 511);
+    },
+    realPath: function(node) {
+        var parts = [];
+        while (node.parent !== node) {
+            parts.push(node.name);
+            node = node.parent;
+        }
+        parts.push(node.mount.opts.root || '/');
+        parts.reverse();
+        return PATH.join.apply(null, parts);
+    },
+    node_ops: {
+        getattr: function(node) {
+            var path = SPFS.realPath(node);
+            var size = 4096;
+            if (FS.isFile(node.mode)) {
+                size = SPFS.check(golem_fsSize(path));
+            } else if (FS.isLink(node.mode)) {
+                size = SPFS.check(golem_fsReadLink(path)).length;
+            }
+            return {
+                dev: 1,
+                ino: node.id,
+                mode: node.mode,
+                nlink: 1,
+                uid: 0,
+                gid: 0,
+                rdev: node.rdev,
+                size: size,
+                atime: new Date(node.timestamp),
+                mtime: new Date(node.timestamp),
+                ctime: new Date(node.timestamp),
+                blksize: 4096,
+                blocks: Math.ceil(size / 4096)
+            };
+        },
+        setattr: function(node, attr) {
+            if (attr.size !== undefined) {
+                SPFS.check(golem_fsTruncate(SPFS.realPath(node), attr.size));
+            }
+            // permissions only live in JS
+            if (attr.mode !== undefined) {
+                node.mode = attr.mode;
+            }
+            if (attr.timestamp !== undefined) {
+                node.timestamp = attr.timestamp;
+            }
+        },
+        lookup: function(parent, name) {
+            var path = PATH.join2(SPFS.realPath(parent), name);
+            return SPFS.createNode(parent, name, SPFS.getMode(path));
+        },
+        mknod: function(parent, name, mode, dev) {
+            var path = PATH.join2(SPFS.realPath(parent), name);
+            if (FS.isDir(mode)) {
+                SPFS.check(golem_fsMkdir(path));
+            } else if (FS.isFile(mode)) {
+                SPFS.check(golem_fsCreate(path));
+            } else {
+                throw new FS.ErrnoError(ERRNO_CODES.EINVAL);
+            }
+            return SPFS.createNode(parent, name, mode, dev);
+        },
+        rename: function(old_node, new_dir, new_name) {
+            // an empty dir at `new_path` is replaced by `golem_fsRename`
+            var new_path = PATH.join2(SPFS.realPath(new_dir), new_name);
+            SPFS.check(golem_fsRename(SPFS.realPath(old_node), new_path));
+            old_node.name = new_name;
+            old_node.parent = new_dir;
+        },
+        unlink: function(parent, name) {
+            SPFS.check(golem_fsRemove(PATH.join2(SPFS.realPath(parent), name)));
+        },
+        rmdir: function(parent, name) {
+            var path = PATH.join2(SPFS.realPath(parent), name);
+            if (SPFS.check(golem_fsReadDir(path)) !== '') {
+                throw new FS.ErrnoError(ERRNO_CODES.ENOTEMPTY);
+            }
+            SPFS.check(golem_fsRemove(path));
+        },
+        readdir: function(node) {
+            var names = SPFS.check(golem_fsReadDir(SPFS.realPath(node)));
+            return ['.', '..'].concat(names === '' ? [] : names.split('/'));
+        },
+        symlink: function(parent, new_name, old_path) {
+            var path = PATH.join2(SPFS.realPath(parent), new_name);
+            SPFS.check(golem_fsSymlink(path, old_path));
+            return SPFS.createNode(parent, new_name, 511 | 40960, 0);
+        },
+        readlink: function(node) {
+            return SPFS.check(golem_fsReadLink(SPFS.realPath(node)));
+        }
+    },
+    stream_ops: {
+        read: function(stream, buffer, offset, length, position) {
+            if (length === 0) {
+                return 0;
+            }
+            var path = SPFS.realPath(stream.node);
+            return SPFS.check(golem_fsRead(path, position, buffer, offset, length));
+        },
+        write: function(stream, buffer, offset, length, position, canOwn) {
+            if (length === 0) {
+                return 0;
+            }
+            var path = SPFS.realPath(stream.node);
+            var written = SPFS.check(golem_fsWrite(path, position, buffer, offset, length));
+            stream.node.timestamp = Date.now();
+            return written;
+        },
+        llseek: function(stream, offset, whence) {
+            var position = offset;
+            if (whence === 1) {
+                position += stream.position;
+            } else if (whence === 2 && FS.isFile(stream.node.mode)) {
+                position += SPFS.check(golem_fsSize(SPFS.realPath(stream.node)));
+            }
+            if (position < 0) {
+                throw new FS.ErrnoError(ERRNO_CODES.EINVAL);
+            }
+            return position;
+        }
+    }
+};
+
+golem_mountSPFS = function() {
+    // Emscripten's default dirs and devices are recreated on top of
+    // SPFS, where some of the dirs may already exist
+    var mkdir = FS.mkdir;
+    FS.mkdir = function(path, mode) {
+        try {
+            return mkdir(path, mode);
+        } catch (e) {
+            if (!(e instanceof FS.ErrnoError) || e.errno !== ERRNO_CODES.EEXIST) {
+                throw e;
+            }
+        }
+    };
+    try {
+        FS.root = null;
+        FS.mount(SPFS, {}, '/');
+        FS.mkdir('/dev');
+        FS.mount(MEMFS, {}, '/dev');
+        FS.createDefaultDirectories();
+        FS.createDefaultDevices();
+        FS.createSpecialDirectories();
+    } finally {
+        FS.mkdir = mkdir;
+    }
+};
+";
+
+/// Defines the natives `SPFS` is built on in `global`.
+pub(super) unsafe fn define_natives(ctx: *mut JSContext, global: HandleObject) {
+    let natives: [(&[u8], JSNative); 12] = [
+        (b"golem_fsType\0", Some(fs_type)),
+        (b"golem_fsSize\0", Some(fs_size)),
+        (b"golem_fsReadDir\0", Some(fs_read_dir)),
+        (b"golem_fsMkdir\0", Some(fs_mkdir)),
+        (b"golem_fsCreate\0", Some(fs_create)),
+        (b"golem_fsRemove\0", Some(fs_remove)),
+        (b"golem_fsRename\0", Some(fs_rename)),
+        (b"golem_fsSymlink\0", Some(fs_symlink)),
+        (b"golem_fsReadLink\0", Some(fs_read_link)),
+        (b"golem_fsTruncate\0", Some(fs_truncate)),
+        (b"golem_fsRead\0", Some(fs_read)),
+        (b"golem_fsWrite\0", Some(fs_write)),
+    ];
+
+    for (name, native) in natives.iter() {
+        JS_DefineFunction(
+            ctx,
+            global.into(),
+            name.as_ptr() as *const libc::c_char,
+            *native,
+            0,
+            0,
+        );
+    }
+}
+
+unsafe extern "C" fn fs_type(ctx: *mut JSContext, argc: u32, vp: *mut Value) -> bool {
+    let args = CallArgs::from_vp(vp, argc);
+    if !check_argc(ctx, &args, "golem_fsType(path)", 1) {
+        return false;
+    }
+
+    let path = path_arg(ctx, &args, 0);
+    let metadata = Host::from_context(ctx).vfs.borrow_mut().metadata(&path);
+    set_int(
+        &args,
+        metadata.map(|metadata| match metadata.file_type {
+            FileType::Dir => S_IFDIR,
+            FileType::Symlink => S_IFLNK,
+            FileType::File | FileType::Device => S_IFREG,
+        }),
+    );
+    true
+}
+
+unsafe extern "C" fn fs_size(ctx: *mut JSContext, argc: u32, vp: *mut Value) -> bool {
+    let args = CallArgs::from_vp(vp, argc);
+    if !check_argc(ctx, &args, "golem_fsSize(path)", 1) {
+        return false;
+    }
+
+    let path = path_arg(ctx, &args, 0);
+    match Host::from_context(ctx).vfs.borrow_mut().metadata(&path) {
+        Ok(metadata) => args.rval().set(DoubleValue(metadata.len as f64)),
+        Err(err) => args.rval().set(Int32Value(error_code(&err))),
+    }
+    true
+}
+
+unsafe extern "C" fn fs_read_dir(ctx: *mut JSContext, argc: u32, vp: *mut Value) -> bool {
+    let args = CallArgs::from_vp(vp, argc);
+    if !check_argc(ctx, &args, "golem_fsReadDir(path)", 1) {
+        return false;
+    }
+
+    // names can't contain '/', so they are passed as a single string
+    let path = path_arg(ctx, &args, 0);
+    let entries = Host::from_context(ctx)
+        .vfs
+        .borrow_mut()
+        .read_dir(&path)
+        .map(|entries| {
+            entries
+                .iter()
+                .map(names::to_guest)
+                .collect::<Vec<_>>()
+                .join("/")
+        });
+    set_string(ctx, &args, entries)
+}
+
+unsafe extern "C" fn fs_mkdir(ctx: *mut JSContext, argc: u32, vp: *mut Value) -> bool {
+    let args = CallArgs::from_vp(vp, argc);
+    if !check_argc(ctx, &args, "golem_fsMkdir(path)", 1) {
+        return false;
+    }
+
+    let path = path_arg(ctx, &args, 0);
+    let mut vfs = Host::from_context(ctx).vfs.borrow_mut();
+    let result = match vfs.metadata(&path) {
+        Ok(_) => Err(MemFSError::AlreadyExists(path.to_string_lossy().to_string()).into()),
+        Err(_) => vfs.create_dir_all(&path),
+    };
+    set_int(&args, result.map(|()| 0));
+    true
+}
+
+unsafe extern "C" fn fs_create(ctx: *mut JSContext, argc: u32, vp: *mut Value) -> bool {
+    let args = CallArgs::from_vp(vp, argc);
+    if !check_argc(ctx, &args, "golem_fsCreate(path)", 1) {
+        return false;
+    }
+
+    let path = path_arg(ctx, &args, 0);
+    let result = Host::from_context(ctx)
+        .vfs
+        .borrow_mut()
+        .write_file(&path, &[]);
+    set_int(&args, result.map(|()| 0));
+    true
+}
+
+unsafe extern "C" fn fs_remove(ctx: *mut JSContext, argc: u32, vp: *mut Value) -> bool {
+    let args = CallArgs::from_vp(vp, argc);
+    if !check_argc(ctx, &args, "golem_fsRemove(path)", 1) {
+        return false;
+    }
+
+    let path = path_arg(ctx, &args, 0);
+    let result = Host::from_context(ctx).vfs.borrow_mut().remove(&path);
+    set_int(&args, result.map(|()| 0));
+    true
+}
+
+unsafe extern "C" fn fs_rename(ctx: *mut JSContext, argc: u32, vp: *mut Value) -> bool {
+    let args = CallArgs::from_vp(vp, argc);
+    if !check_argc(ctx, &args, "golem_fsRename(from, to)", 2) {
+        return false;
+    }
+
+    let from = path_arg(ctx, &args, 0);
+    let to = path_arg(ctx, &args, 1);
+    let result = Host::from_context(ctx).vfs.borrow_mut().rename(&from, &to);
+    set_int(&args, result.map(|()| 0));
+    true
+}
+
+unsafe extern "C" fn fs_symlink(ctx: *mut JSContext, argc: u32, vp: *mut Value) -> bool {
+    let args = CallArgs::from_vp(vp, argc);
+    if !check_argc(ctx, &args, "golem_fsSymlink(path, target)", 2) {
+        return false;
+    }
+
+    let path = path_arg(ctx, &args, 0);
+    let target = path_arg(ctx, &args, 1);
+    let result = Host::from_context(ctx)
+        .vfs
+        .borrow_mut()
+        .create_symlink(&path, &target);
+    set_int(&args, result.map(|()| 0));
+    true
+}
+
+unsafe extern "C" fn fs_read_link(ctx: *mut JSContext, argc: u32, vp: *mut Value) -> bool {
+    let args = CallArgs::from_vp(vp, argc);
+    if !check_argc(ctx, &args, "golem_fsReadLink(path)", 1) {
+        return false;
+    }
+
+    let path = path_arg(ctx, &args, 0);
+    let target = Host::from_context(ctx).vfs.borrow_mut().read_link(&path);
+    set_string(ctx, &args, target.map(names::to_guest))
+}
+
+unsafe extern "C" fn fs_truncate(ctx: *mut JSContext, argc: u32, vp: *mut Value) -> bool {
+    let args = CallArgs::from_vp(vp, argc);
+    if !check_argc(ctx, &args, "golem_fsTruncate(path, len)", 2) {
+        return false;
+    }
+
+    let path = path_arg(ctx, &args, 0);
+    let len = match u64_arg(ctx, &args, 1, "len") {
+        Some(len) => len,
+        None => return false,
+    };
+    let result = Host::from_context(ctx).vfs.borrow_mut().set_len(&path, len);
+    set_int(&args, result.map(|()| 0));
+    true
+}
+
+unsafe extern "C" fn fs_read(ctx: *mut JSContext, argc: u32, vp: *mut Value) -> bool {
+    let args = CallArgs::from_vp(vp, argc);
+    if !check_argc(
+        ctx,
+        &args,
+        "golem_fsRead(path, pos, buffer, offset, length)",
+        5,
+    ) {
+        return false;
+    }
+
+    let path = path_arg(ctx, &args, 0);
+    let (pos, offset, length) = match (
+        u64_arg(ctx, &args, 1, "pos"),
+        u64_arg(ctx, &args, 3, "offset"),
+        u64_arg(ctx, &args, 4, "length"),
+    ) {
+        (Some(pos), Some(offset), Some(length)) => (pos, offset, length),
+        _ => return false,
+    };
+    // the count is returned as an `i32`, longer requests end up partial
+    let length = cmp::min(length, i32::MAX as u64);
+
+    if !args.get(2).is_object() {
+        report(ctx, "golem_fsRead: buffer must be a typed array");
+        return false;
+    }
+    typedarray!(in(ctx) let buffer: ArrayBufferView = args.get(2).to_object());
+    let mut buffer = match buffer {
+        Ok(buffer) => buffer,
+        Err(()) => {
+            report(ctx, "golem_fsRead: buffer must be a typed array");
+            return false;
+        }
+    };
+    let range = buffer_range(offset, length);
+    let buf = match range.and_then(|range| buffer.as_mut_slice().get_mut(range)) {
+        Some(buf) => buf,
+        None => {
+            set_int(&args, Err(bad_address()));
+            return true;
+        }
+    };
+
+    let count = Host::from_context(ctx)
+        .vfs
+        .borrow_mut()
+        .read_at(&path, pos, buf);
+    set_int(&args, count.map(|count| count as i32));
+    true
+}
+
+unsafe extern "C" fn fs_write(ctx: *mut JSContext, argc: u32, vp: *mut Value) -> bool {
+    let args = CallArgs::from_vp(vp, argc);
+    if !check_argc(
+        ctx,
+        &args,
+        "golem_fsWrite(path, pos, buffer, offset, length)",
+        5,
+    ) {
+        return false;
+    }
+
+    let path = path_arg(ctx, &args, 0);
+    let (pos, offset, length) = match (
+        u64_arg(ctx, &args, 1, "pos"),
+        u64_arg(ctx, &args, 3, "offset"),
+        u64_arg(ctx, &args, 4, "length"),
+    ) {
+        (Some(pos), Some(offset), Some(length)) => (pos, offset, length),
+        _ => return false,
+    };
+    // the count is returned as an `i32`, longer requests end up partial
+    let length = cmp::min(length, i32::MAX as u64);
+
+    if !args.get(2).is_object() {
+        report(ctx, "golem_fsWrite: buffer must be a typed array");
+        return false;
+    }
+    typedarray!(in(ctx) let buffer: ArrayBufferView = args.get(2).to_object());
+    let buffer = match buffer {
+        Ok(buffer) => buffer,
+        Err(()) => {
+            report(ctx, "golem_fsWrite: buffer must be a typed array");
+            return false;
+        }
+    };
+    let range = buffer_range(offset, length);
+    let buf = match range.and_then(|range| buffer.as_slice().get(range)) {
+        Some(buf) => buf,
+        None => {
+            set_int(&args, Err(bad_address()));
+            return true;
+        }
+    };
+
+    let result = Host::from_context(ctx)
+        .vfs
+        .borrow_mut()
+        .write_at(&path, pos, buf);
+    set_int(&args, result.map(|()| buf.len() as i32));
+    true
+}
+
+/// Range of `length` bytes at `offset` into a buffer, `None` if it
+/// doesn't fit in a `usize`.
+fn buffer_range(offset: u64, length: u64) -> Option<Range<usize>> {
+    let start = usize::try_from(offset).ok()?;
+    let end = start.checked_add(usize::try_from(length).ok()?)?;
+    Some(start..end)
+}
+
+/// Reported for ranges out of the buffer's bounds, like a bad pointer
+/// passed to `read` or `write`.
+fn bad_address() -> Error {
+    io::Error::from_raw_os_error(libc::EFAULT).into()
+}
+
+unsafe fn report(ctx: *mut JSContext, message: &str) {
+    JS_ReportErrorASCII(
+        ctx,
+        format!("{}\0", message).as_bytes().as_ptr() as *const libc::c_char,
+    );
+}
+
+unsafe fn check_argc(ctx: *mut JSContext, args: &CallArgs, usage: &str, argc: u32) -> bool {
+    if args.argc_ != argc {
+        report(
+            ctx,
+            &format!(
+                "{} requires exactly {} argument{}",
+                usage,
+                argc,
+                if argc == 1 { "" } else { "s" }
+            ),
+        );
+        return false;
+    }
+
+    true
+}
+
+unsafe fn path_arg(ctx: *mut JSContext, args: &CallArgs, index: u32) -> OsString {
+    let arg = Handle::from_raw(args.get(index));
+    names::from_guest(js_string_to_utf8(ctx, ToString(ctx, arg)))
+}
+
+unsafe fn u64_arg(ctx: *mut JSContext, args: &CallArgs, index: u32, name: &str) -> Option<u64> {
+    let arg = Handle::from_raw(args.get(index));
+    match ToUint64(ctx, arg) {
+        Ok(value) => Some(value),
+        Err(()) => {
+            report(
+                ctx,
+                &format!("couldn't extract value from input arg '{}'", name),
+            );
+            None
+        }
+    }
+}
+
+unsafe fn set_int(args: &CallArgs, result: Result<i32>) {
+    match result {
+        Ok(value) => args.rval().set(Int32Value(value)),
+        Err(err) => args.rval().set(Int32Value(error_code(&err))),
+    }
+}
+
+unsafe fn set_string(ctx: *mut JSContext, args: &CallArgs, result: Result<String>) -> bool {
+    let string = match result {
+        Ok(string) => string,
+        Err(err) => {
+            args.rval().set(Int32Value(error_code(&err)));
+            return true;
+        }
+    };
+
+    let utf16: Vec<u16> = string.encode_utf16().collect();
+    let string = JS_NewUCStringCopyN(ctx, utf16.as_ptr(), utf16.len());
+    if string.is_null() {
+        return false;
+    }
+
+    args.rval().set(StringValue(&*string));
+    true
+}
+
+/// Names of the errors reported to the guest, as in Emscripten's
+/// `ERRNO_CODES`. Must match `SPFS.errnos`.
+const ERRNOS: [&str; 17] = [
+    "EIO",
+    "EEXIST",
+    "ENOENT",
+    "EINVAL",
+    "ENOTDIR",
+    "EISDIR",
+    "ENOTEMPTY",
+    "EBUSY",
+    "ELOOP",
+    "EACCES",
+    "EPERM",
+    "EROFS",
+    "ENOSPC",
+    "EFBIG",
+    "EXDEV",
+    "ENAMETOOLONG",
+    "EFAULT",
+];
+
+/// Maps `err` to the value natives return on failure.
+fn error_code(err: &Error) -> i32 {
+    let name = errno(err);
+    let index = ERRNOS.iter().position(|known| *known == name).unwrap_or(0);
+    -(index as i32 + 1)
+}
+
+/// Maps `err` to the name of the errno reported to the guest.
+fn errno(err: &Error) -> &'static str {
+    match err {
+        Error::MemFS(err) => match err {
+            MemFSError::AlreadyExists(_) => "EEXIST",
+            MemFSError::NotFound(_) => "ENOENT",
+            MemFSError::InvalidPath(_) | MemFSError::NotASymlink(_) => "EINVAL",
+            MemFSError::NotADirectory(_) => "ENOTDIR",
+            MemFSError::IsRoot => "EBUSY",
+            MemFSError::IsSymlink(_) => "ELOOP",
+            MemFSError::HostFileChanged(_) => "EIO",
+            MemFSError::Io(err) => io_errno(err),
+        },
+        Error::InvalidPath(_) => "EINVAL",
+        Error::Io(err) | Error::FileError(err, _) => io_errno(err),
+        _ => "EIO",
+    }
+}
+
+/// Maps a host error by the name of its errno, Emscripten numbers them
+/// differently.
+fn io_errno(err: &io::Error) -> &'static str {
+    match err.raw_os_error() {
+        Some(libc::EEXIST) => "EEXIST",
+        Some(libc::ENOENT) => "ENOENT",
+        Some(libc::EINVAL) => "EINVAL",
+        Some(libc::ENOTDIR) => "ENOTDIR",
+        Some(libc::EISDIR) => "EISDIR",
+        Some(libc::ENOTEMPTY) => "ENOTEMPTY",
+        Some(libc::EBUSY) => "EBUSY",
+        Some(libc::ELOOP) => "ELOOP",
+        Some(libc::EACCES) => "EACCES",
+        Some(libc::EPERM) => "EPERM",
+        Some(libc::EROFS) => "EROFS",
+        Some(libc::ENOSPC) => "ENOSPC",
+        Some(libc::EFBIG) => "EFBIG",
+        Some(libc::EXDEV) => "EXDEV",
+        Some(libc::ENAMETOOLONG) => "ENAMETOOLONG",
+        Some(libc::EFAULT) => "EFAULT",
+        Some(_) => "EIO",
+        None => match err.kind() {
+            io::ErrorKind::NotFound => "ENOENT",
+            io::ErrorKind::PermissionDenied => "EACCES",
+            io::ErrorKind::AlreadyExists => "EEXIST",
+            io::ErrorKind::InvalidInput => "EINVAL",
+            _ => "EIO",
+        },
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn errnos_match_js() {
+        let names: Vec<_> = ERRNOS.iter().map(|name| format!("'{}'", name)).collect();
+        assert!(SPFS_JS.contains(&format!("errnos: [{}]", names.join(", "))));
+    }
+
+    #[test]
+    fn buffer_ranges() {
+        assert_eq!(buffer_range(4, 8), Some(4..12));
+        assert_eq!(buffer_range(u64::MAX, 1), None);
+        assert_eq!(errno(&bad_address()), "EFAULT");
+    }
+}
@@ -0,0 +1,227 @@
+use crate::error::FileContext;
+use crate::Result;
+
+use std::cmp;
+use std::fs;
+use std::io::{self, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Source of the guest's standard input.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Stdin {
+    Bytes(Vec<u8>),
+    /// Host file, read as the guest consumes it.
+    File(PathBuf),
+}
+
+impl Stdin {
+    pub fn open(&self) -> Result<Box<dyn Read + Send>> {
+        match self {
+            Stdin::Bytes(bytes) => Ok(Box::new(io::Cursor::new(bytes.clone()))),
+            Stdin::File(path) => {
+                let file = fs::File::open(path).file_context(path)?;
+                Ok(Box::new(BufReader::new(file)))
+            }
+        }
+    }
+}
+
+impl From<Vec<u8>> for Stdin {
+    fn from(bytes: Vec<u8>) -> Self {
+        Stdin::Bytes(bytes)
+    }
+}
+
+impl<'a> From<&'a [u8]> for Stdin {
+    fn from(bytes: &'a [u8]) -> Self {
+        Stdin::Bytes(bytes.to_vec())
+    }
+}
+
+impl From<PathBuf> for Stdin {
+    fn from(path: PathBuf) -> Self {
+        Stdin::File(path)
+    }
+}
+
+impl<'a> From<&'a Path> for Stdin {
+    fn from(path: &'a Path) -> Self {
+        Stdin::File(path.to_owned())
+    }
+}
+
+/// Where the guest's stdout or stderr goes.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Sink {
+    /// The host's own stdout or stderr.
+    Host,
+    /// Kept in memory, see `Sandbox::stdout` and `Sandbox::stderr`.
+    Memory,
+    /// Saved together with the outputs, at this path relative to the
+    /// output dir.
+    File(PathBuf),
+}
+
+impl Default for Sink {
+    fn default() -> Self {
+        Sink::Host
+    }
+}
+
+impl FromStr for Sink {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "host" => Ok(Sink::Host),
+            "memory" => Ok(Sink::Memory),
+            "" => Err("empty output path".to_owned()),
+            path => Ok(Sink::File(PathBuf::from(path))),
+        }
+    }
+}
+
+/// Standard streams of a guest, kept by its `Runtime`.
+pub(crate) struct Stdio {
+    /// Reader backing `Module['stdin']`; the guest is at EOF right away
+    /// if there is none.
+    pub stdin: Option<Box<dyn Read + Send>>,
+    pub stdout: Stream,
+    pub stderr: Stream,
+}
+
+impl Default for Stdio {
+    fn default() -> Self {
+        Self {
+            stdin: None,
+            stdout: Stream::new(Fd::Stdout),
+            stderr: Stream::new(Fd::Stderr),
+        }
+    }
+}
+
+impl Stdio {
+    pub fn stream(&mut self, fd: Fd) -> &mut Stream {
+        match fd {
+            Fd::Stdout => &mut self.stdout,
+            Fd::Stderr => &mut self.stderr,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Fd {
+    Stdout,
+    Stderr,
+}
+
+/// Output stream of the guest, written byte-exact to its `Sink`.
+#[derive(Debug)]
+pub(crate) struct Stream {
+    fd: Fd,
+    pub sink: Sink,
+    /// Maximum number of bytes kept, anything past it is dropped.
+    pub limit: Option<u64>,
+    // written but not yet passed on to the host, or everything for
+    // other sinks
+    buffer: Vec<u8>,
+    written: u64,
+    truncated: bool,
+}
+
+impl Stream {
+    pub fn new(fd: Fd) -> Self {
+        Self {
+            fd,
+            sink: Sink::default(),
+            limit: None,
+            buffer: Vec::new(),
+            written: 0,
+            truncated: false,
+        }
+    }
+
+    pub fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        let len = match self.limit {
+            Some(limit) => {
+                cmp::min(bytes.len() as u64, limit.saturating_sub(self.written)) as usize
+            }
+            None => bytes.len(),
+        };
+        if len < bytes.len() && !self.truncated {
+            log::warn!(
+                "Guest {:?} exceeds {} bytes, dropping the rest",
+                self.fd,
+                self.limit.unwrap_or_default()
+            );
+            self.truncated = true;
+        }
+
+        self.buffer.extend_from_slice(&bytes[..len]);
+        self.written += len as u64;
+
+        // line buffered, like the host's stdout
+        if self.sink == Sink::Host && bytes[..len].contains(&b'\n') {
+            self.flush()?;
+        }
+
+        Ok(())
+    }
+
+    /// Passes anything buffered on to the host, if that's the sink.
+    pub fn flush(&mut self) -> io::Result<()> {
+        if self.sink != Sink::Host || self.buffer.is_empty() {
+            return Ok(());
+        }
+
+        match self.fd {
+            Fd::Stdout => {
+                let stdout = io::stdout();
+                let mut stdout = stdout.lock();
+                stdout.write_all(&self.buffer)?;
+                stdout.flush()?;
+            }
+            Fd::Stderr => io::stderr().write_all(&self.buffer)?,
+        }
+        self.buffer.clear();
+
+        Ok(())
+    }
+
+    /// Everything written so far, unless it went to the host.
+    pub fn contents(&self) -> &[u8] {
+        match self.sink {
+            Sink::Host => &[],
+            _ => &self.buffer,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn stream_limit() {
+        let mut stream = Stream::new(Fd::Stdout);
+        stream.sink = Sink::Memory;
+        stream.limit = Some(8);
+
+        stream.write(b"abc").unwrap();
+        stream.write(b"no newline").unwrap();
+        stream.write(b"\n").unwrap();
+        stream.flush().unwrap();
+        assert_eq!(stream.contents(), b"abcno ne");
+    }
+
+    #[test]
+    fn stream_host() {
+        let mut stream = Stream::new(Fd::Stderr);
+        stream.write(b"partial").unwrap();
+        assert_eq!(stream.buffer, b"partial");
+        stream.write(b" line\n").unwrap();
+        assert!(stream.buffer.is_empty());
+        assert!(stream.contents().is_empty());
+    }
+}
@@ -1,58 +1,979 @@
-use crate::error::FileContext;
+use super::random::{RandEmu, Urandom};
+use crate::error::{FileContext, Limit};
 use crate::{Error, Result};
-use sp_wasm_memfs::prelude::*;
-use std::collections::VecDeque;
+use sp_wasm_memfs::device::{Full, Null, Zero};
+use std::cmp;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::ffi;
 use std::fs;
-use std::io::{Read, Write};
+use std::io::{self, Read, Seek, Write};
+use std::mem;
+use std::os::unix::fs::OpenOptionsExt;
 use std::path;
+use std::rc::Rc;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
 
+pub use sp_wasm_memfs::backend::{FileType, FsBackend, FsFile, Metadata};
+pub use sp_wasm_memfs::contents::MAX_LEN;
+pub use sp_wasm_memfs::error::Error as MemFSError;
+pub use sp_wasm_memfs::memfs::MemFS;
+
+/// What to do with symlinks found while mapping host paths.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SymlinkPolicy {
+    /// Fail with `Error::Symlink`.
+    Reject,
+    /// Leave symlinks out.
+    Skip,
+    /// Map whatever a symlink points to, as long as it is inside the
+    /// mapped dir.
+    FollowWithinRoot,
+    /// Recreate symlinks in the guest with their targets unchanged.
+    PreserveAsLink,
+}
+
+impl Default for SymlinkPolicy {
+    fn default() -> Self {
+        SymlinkPolicy::Reject
+    }
+}
+
+impl FromStr for SymlinkPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "reject" => Ok(SymlinkPolicy::Reject),
+            "skip" => Ok(SymlinkPolicy::Skip),
+            "follow_within_root" => Ok(SymlinkPolicy::FollowWithinRoot),
+            "preserve_as_link" => Ok(SymlinkPolicy::PreserveAsLink),
+            _ => Err(format!("unknown symlink policy '{}'", s)),
+        }
+    }
+}
+
+/// What to do with FIFOs, sockets and device files found while mapping
+/// host paths. They are never read, as that could block forever.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SpecialFilePolicy {
+    /// Fail with `Error::SpecialFile`.
+    Reject,
+    /// Leave special files out.
+    Skip,
+}
+
+impl Default for SpecialFilePolicy {
+    fn default() -> Self {
+        SpecialFilePolicy::Reject
+    }
+}
+
+impl FromStr for SpecialFilePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "reject" => Ok(SpecialFilePolicy::Reject),
+            "skip" => Ok(SpecialFilePolicy::Skip),
+            _ => Err(format!("unknown special file policy '{}'", s)),
+        }
+    }
+}
+
+/// Limits on the size of an input tree; `None` means unlimited.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Limits {
+    /// Total size of all files in bytes.
+    pub max_bytes: Option<u64>,
+    /// Number of files and symlinks.
+    pub max_files: Option<u64>,
+    /// Nesting depth, with entries directly in the input dir at depth 1.
+    pub max_depth: Option<u64>,
+}
+
+impl Limits {
+    pub fn is_unlimited(&self) -> bool {
+        self.max_bytes.is_none() && self.max_files.is_none() && self.max_depth.is_none()
+    }
+
+    pub fn check(&self, usage: &Usage) -> Result<()> {
+        let checks = [
+            (Limit::Bytes, usage.bytes, self.max_bytes),
+            (Limit::Files, usage.files, self.max_files),
+            (Limit::Depth, usage.depth, self.max_depth),
+        ];
+
+        for &(kind, actual, limit) in checks.iter() {
+            if let Some(limit) = limit {
+                if actual > limit {
+                    return Err(Error::LimitExceeded {
+                        kind,
+                        actual,
+                        limit,
+                    });
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Measured size of an input tree.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Usage {
+    pub bytes: u64,
+    pub files: u64,
+    pub depth: u64,
+}
+
+impl Usage {
+    /// Adds an entry at `path`, relative to the root of the input tree.
+    pub fn add<P>(&mut self, path: P, len: Option<u64>)
+    where
+        P: AsRef<path::Path>,
+    {
+        let depth = path.as_ref().components().count() as u64;
+        self.depth = cmp::max(self.depth, depth);
+
+        if let Some(len) = len {
+            self.bytes += len;
+            self.files += 1;
+        }
+    }
+
+    /// Adds `usage` of a tree mapped onto `dest_path`, with its depth
+    /// counted from the root.
+    pub fn add_tree<P>(&mut self, dest_path: P, usage: &Usage)
+    where
+        P: AsRef<path::Path>,
+    {
+        self.bytes += usage.bytes;
+        self.files += usage.files;
+
+        if usage.depth > 0 {
+            let base = dest_path
+                .as_ref()
+                .components()
+                .filter(|component| match component {
+                    path::Component::Normal(_) => true,
+                    _ => false,
+                })
+                .count() as u64;
+            self.depth = cmp::max(self.depth, base + usage.depth);
+        }
+    }
+
+    /// Measures the host dir `source_path` the way `map_path_with` would
+    /// map it, without reading any files.
+    pub fn measure<P>(source_path: P, options: &MapOptions) -> Result<Self>
+    where
+        P: AsRef<path::Path>,
+    {
+        let mut usage = Self::default();
+        walk_host(
+            source_path.as_ref(),
+            path::Path::new(""),
+            options,
+            &mut |_, dest_path, entry| {
+                match entry {
+                    HostEntry::Dir => usage.add(dest_path, None),
+                    HostEntry::File(_, len) => usage.add(dest_path, Some(*len)),
+                    HostEntry::Symlink(_) => usage.add(dest_path, Some(0)),
+                }
+                Ok(())
+            },
+        )?;
+
+        Ok(usage)
+    }
+}
+
+/// Options controlling how host paths are mapped into the `VirtualFS`.
+#[derive(Debug, Clone, Default)]
+pub struct MapOptions {
+    /// Record host files instead of copying them, deferring reading
+    /// their contents until they are first accessed.
+    pub lazy: bool,
+    pub symlinks: SymlinkPolicy,
+    pub special_files: SpecialFilePolicy,
+    pub limits: Limits,
+}
+
+/// Read-only layer of an overlay `VirtualFS`.
+///
+/// Cloning a layer is cheap and shares the underlying `MemFS`, so the
+/// same inputs can be reused as the lower layer of several `VirtualFS`
+/// instances.
+#[derive(Debug, Clone)]
+pub struct Layer {
+    fs: Arc<dyn FsBackend>,
+    whiteouts: BTreeSet<path::PathBuf>,
+}
+
+impl Layer {
+    /// Creates a layer without whiteouts on top of `fs`.
+    pub fn new<B>(fs: B) -> Self
+    where
+        B: FsBackend + 'static,
+    {
+        Self {
+            fs: Arc::new(fs),
+            whiteouts: BTreeSet::new(),
+        }
+    }
+
+    fn resolves<P>(&self, path: P) -> Result<bool>
+    where
+        P: AsRef<path::Path>,
+    {
+        Ok(self.fs.exists(path.as_ref())?)
+    }
+
+    fn hides<P>(&self, path: P) -> bool
+    where
+        P: AsRef<path::Path>,
+    {
+        path.as_ref()
+            .ancestors()
+            .any(|path| self.whiteouts.contains(path))
+    }
+}
+
+/// File from a lower layer, failing every write with `EROFS`.
+struct ReadOnlyFile(Box<dyn FsFile>);
+
+impl Read for ReadOnlyFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl Write for ReadOnlyFile {
+    fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+        Err(io::Error::from_raw_os_error(libc::EROFS))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for ReadOnlyFile {
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        self.0.seek(pos)
+    }
+}
+
+impl FsFile for ReadOnlyFile {
+    fn set_len(&mut self, _len: u64) -> io::Result<()> {
+        Err(io::Error::from_raw_os_error(libc::EROFS))
+    }
+}
+
+/// Virtual filesystem exposed to the guest.
+///
+/// All writes go to the upper layer, stored in any `FsBackend` (by
+/// default a `MemFS`). Once sealed, everything written so far becomes an
+/// immutable lower layer: files from lower layers are shadowed by files
+/// written to the upper one, and removing them only records a whiteout
+/// in the upper layer.
+///
+/// Backends mounted at a path take over everything below it, bypassing
+/// the layers altogether.
+///
+/// Paths made read-only with `set_read_only` can't be changed at all,
+/// whichever layer or backend they are found in.
 pub struct VirtualFS {
-    backend: MemFS,
+    backend: Box<dyn FsBackend>,
+    whiteouts: BTreeSet<path::PathBuf>,
+    lower: Vec<Layer>,
+    mounts: BTreeMap<path::PathBuf, Box<dyn FsBackend>>,
+    read_only: BTreeSet<path::PathBuf>,
 }
 
-impl VirtualFS {
-    pub fn new() -> Self {
-        Self::default()
+impl VirtualFS {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a `VirtualFS` whose `/dev/urandom` reads from `rand_emu`,
+    /// e.g. the generator of a `Runtime`.
+    pub fn with_rand_emu(rand_emu: Arc<Mutex<RandEmu>>) -> Self {
+        Self {
+            backend: Box::new(devices(rand_emu)),
+            whiteouts: BTreeSet::new(),
+            lower: Vec::new(),
+            mounts: BTreeMap::new(),
+            read_only: BTreeSet::new(),
+        }
+    }
+
+    /// Creates a `VirtualFS` storing its upper layer in `backend`. As
+    /// backends can't hold devices in general, those are kept in a
+    /// `MemFS` layer below it.
+    pub fn with_backend<B>(backend: B) -> Self
+    where
+        B: FsBackend + 'static,
+    {
+        Self {
+            backend: Box::new(backend),
+            whiteouts: BTreeSet::new(),
+            lower: vec![Layer::new(devices(Arc::default()))],
+            mounts: BTreeMap::new(),
+            read_only: BTreeSet::new(),
+        }
+    }
+
+    /// Creates a `VirtualFS` with an empty upper layer on top of `lower`,
+    /// topmost layer first.
+    pub fn with_lower(lower: Vec<Layer>) -> Self {
+        Self {
+            lower,
+            ..Self::default()
+        }
+    }
+
+    /// Turns the upper layer into the topmost lower layer and starts a
+    /// new, empty upper layer.
+    pub fn seal(&mut self) {
+        let layer = Layer {
+            fs: Arc::from(mem::replace(&mut self.backend, Box::new(MemFS::new()))),
+            whiteouts: mem::replace(&mut self.whiteouts, BTreeSet::new()),
+        };
+        self.lower.insert(0, layer);
+    }
+
+    pub fn lower(&self) -> &[Layer] {
+        &self.lower
+    }
+
+    /// Mounts `backend` at `path`, creating the parent dirs if needed.
+    pub fn mount<P, B>(&mut self, path: P, backend: B) -> Result<()>
+    where
+        P: AsRef<path::Path>,
+        B: FsBackend + 'static,
+    {
+        let path = Self::normalize_path(path)?;
+        if self.mounts.contains_key(&path) {
+            return Err(MemFSError::AlreadyExists(path.to_string_lossy().to_string()).into());
+        }
+
+        if let Some(parent) = path.parent() {
+            self.create_dir_all(parent)?;
+        }
+
+        log::debug!("Mounting {:?} at {:?}", backend, path);
+        self.mounts.insert(path, Box::new(backend));
+
+        Ok(())
+    }
+
+    /// Paths backends are mounted at.
+    pub fn mount_points(&self) -> impl Iterator<Item = &path::Path> {
+        self.mounts.keys().map(|path| path.as_path())
+    }
+
+    /// Makes `path` and everything below it read-only: creating, changing
+    /// or removing anything there fails with `EROFS`, and files are handed
+    /// out as from lower layers.
+    pub fn set_read_only<P>(&mut self, path: P) -> Result<()>
+    where
+        P: AsRef<path::Path>,
+    {
+        let path = Self::normalize_path(path)?;
+        log::debug!("Making {:?} read-only", path);
+        self.read_only.insert(path);
+
+        Ok(())
+    }
+
+    fn is_read_only(&self, path: &path::Path) -> bool {
+        path.ancestors().any(|path| self.read_only.contains(path))
+    }
+
+    fn check_writable(&self, path: &path::Path) -> Result<()> {
+        if self.is_read_only(path) {
+            return Err(io::Error::from_raw_os_error(libc::EROFS)).file_context(path);
+        }
+
+        Ok(())
+    }
+
+    /// Finds the innermost mount point containing `path`.
+    fn mount_point<'a>(&self, path: &'a path::Path) -> Option<&'a path::Path> {
+        path.ancestors()
+            .find(|mount_point| self.mounts.contains_key(*mount_point))
+    }
+
+    /// Finds the innermost mount containing `path`, returning it together
+    /// with `path` relative to its root.
+    fn resolve_mount(&self, path: &path::Path) -> Option<(&dyn FsBackend, path::PathBuf)> {
+        for mount_point in path.ancestors() {
+            if let Some(backend) = self.mounts.get(mount_point) {
+                let relative = path.strip_prefix(mount_point).unwrap();
+                return Some((backend.as_ref(), path::Path::new("/").join(relative)));
+            }
+        }
+
+        None
+    }
+
+    /// Paths of all files written to the upper layer.
+    pub fn changed_files(&self) -> Result<Vec<path::PathBuf>> {
+        let mut files = Vec::new();
+        let mut fifo = VecDeque::new();
+        fifo.push_back(path::PathBuf::from("/"));
+
+        while let Some(dir) = fifo.pop_front() {
+            for name in self.backend.read_dir(&dir)? {
+                let path = dir.join(name);
+                let metadata = self.backend.metadata(&path)?;
+                if metadata.is_dir() {
+                    fifo.push_back(path);
+                } else if metadata.is_file() {
+                    files.push(path);
+                }
+            }
+        }
+
+        Ok(files)
+    }
+
+    /// Paths from lower layers removed through the upper layer.
+    pub fn whiteouts(&self) -> impl Iterator<Item = &path::Path> {
+        self.whiteouts.iter().map(|path| path.as_path())
+    }
+
+    pub(crate) fn normalize_path<P>(path: P) -> Result<path::PathBuf>
+    where
+        P: AsRef<path::Path>,
+    {
+        if !path.as_ref().has_root() {
+            return Err(
+                MemFSError::InvalidPath(path.as_ref().to_string_lossy().to_string()).into(),
+            );
+        }
+
+        Ok(clean_path(path))
+    }
+
+    fn not_found<P>(path: P) -> Error
+    where
+        P: AsRef<path::Path>,
+    {
+        let name = path
+            .as_ref()
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+
+        MemFSError::NotFound(name).into()
+    }
+
+    /// Finds the topmost lower layer `path` resolves to, unless it is
+    /// hidden by a whiteout.
+    fn resolve_lower(&self, path: &path::Path) -> Result<Option<&dyn FsBackend>> {
+        if self.lower.is_empty() || path.ancestors().any(|p| self.whiteouts.contains(p)) {
+            return Ok(None);
+        }
+
+        for layer in &self.lower {
+            if layer.resolves(path)? {
+                return Ok(Some(layer.fs.as_ref()));
+            }
+
+            if layer.hides(path) {
+                break;
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Finds the backend `path` resolves to, together with the path to
+    /// look up in it.
+    fn resolve(&self, path: &path::Path) -> Result<Option<(&dyn FsBackend, path::PathBuf)>> {
+        if let Some((fs, path)) = self.resolve_mount(path) {
+            return Ok(Some((fs, path)));
+        }
+
+        if self.backend.exists(path)? {
+            return Ok(Some((self.backend.as_ref(), path.to_owned())));
+        }
+
+        Ok(self.resolve_lower(path)?.map(|fs| (fs, path.to_owned())))
+    }
+
+    /// Copies up parent dirs of `path` which only exist in lower layers.
+    fn copy_up_parent(&mut self, path: &path::Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            if !self.backend.exists(parent)? && self.is_dir(parent)? {
+                self.backend.create_dir_all(parent)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn create_file<P>(&mut self, path: P) -> Result<Box<dyn FsFile>>
+    where
+        P: AsRef<path::Path>,
+    {
+        let path = Self::normalize_path(path)?;
+        self.check_writable(&path)?;
+        if let Some((fs, path)) = self.resolve_mount(&path) {
+            return Ok(fs.create(&path)?);
+        }
+
+        self.copy_up_parent(&path)?;
+        let file = self.backend.create(&path)?;
+
+        Ok(file)
+    }
+
+    pub fn open_file<P>(&mut self, path: P) -> Result<Box<dyn FsFile>>
+    where
+        P: AsRef<path::Path>,
+    {
+        let path = Self::normalize_path(path)?;
+        if self.is_read_only(&path) && !self.is_device(&path)? {
+            let file = match self.resolve(&path)? {
+                Some((fs, path)) => fs.open(&path)?,
+                None => return Err(Self::not_found(&path)),
+            };
+            return Ok(Box::new(ReadOnlyFile(file)));
+        }
+
+        if let Some((fs, path)) = self.resolve_mount(&path) {
+            return Ok(fs.open(&path)?);
+        }
+
+        if self.backend.exists(&path)? {
+            return Ok(self.backend.open(&path)?);
+        }
+
+        // lower layers are shared, so their files must not be changed
+        // through the handle; `write_at` copies them up instead. Devices
+        // have no contents to change.
+        match self.resolve_lower(&path)? {
+            Some(fs) if fs.metadata(&path)?.file_type == FileType::Device => Ok(fs.open(&path)?),
+            Some(fs) => Ok(Box::new(ReadOnlyFile(fs.open(&path)?))),
+            None => Err(Self::not_found(&path)),
+        }
+    }
+
+    /// Writes the contents of the file at `path` to `writer`, straight
+    /// from where the backend keeps them, see `FsBackend::write_to`.
+    pub fn write_to<P>(&self, path: P, writer: &mut dyn Write) -> Result<u64>
+    where
+        P: AsRef<path::Path>,
+    {
+        let path = Self::normalize_path(path)?;
+        match self.resolve(&path)? {
+            Some((fs, path)) => Ok(fs.write_to(&path, writer)?),
+            None => Err(Self::not_found(&path)),
+        }
+    }
+
+    pub fn read_file<P>(&mut self, path: P) -> Result<Vec<u8>>
+    where
+        P: AsRef<path::Path>,
+    {
+        let len = self.metadata(path.as_ref())?.len;
+        let mut file = self.open_file(path.as_ref())?;
+        let mut contents = Vec::with_capacity(len as usize);
+        file.read_to_end(&mut contents).file_context(&path)?;
+
+        Ok(contents)
+    }
+
+    pub fn write_file<P>(&mut self, path: P, contents: &[u8]) -> Result<()>
+    where
+        P: AsRef<path::Path>,
+    {
+        log::debug!("Writing file={:?}", path.as_ref());
+        let mut file = self.create_file(path.as_ref())?;
+        file.write_all(contents).file_context(&path)?;
+
+        Ok(())
+    }
+
+    pub fn create_dir_all<P>(&mut self, path: P) -> Result<()>
+    where
+        P: AsRef<path::Path>,
+    {
+        log::debug!("Creating subdirs={:?}", path.as_ref());
+        let path = Self::normalize_path(path)?;
+        if self.is_dir(&path)? {
+            return Ok(());
+        }
+
+        self.check_writable(&path)?;
+        if let Some((fs, path)) = self.resolve_mount(&path) {
+            return Ok(fs.create_dir_all(&path)?);
+        }
+        self.backend.create_dir_all(&path)?;
+
+        Ok(())
+    }
+
+    /// Removes a file or a directory, recording a whiteout if it exists
+    /// in any of the lower layers.
+    pub fn remove<P>(&mut self, path: P) -> Result<()>
+    where
+        P: AsRef<path::Path>,
+    {
+        let path = Self::normalize_path(path)?;
+        if self.mounts.contains_key(&path) {
+            return Err(MemFSError::InvalidPath(path.to_string_lossy().to_string()).into());
+        }
+        self.check_writable(&path)?;
+        if let Some((fs, path)) = self.resolve_mount(&path) {
+            return Ok(fs.remove(&path)?);
+        }
+
+        let in_upper = self.backend.exists(&path)?;
+        let in_lower = self.resolve_lower(&path)?.is_some();
+
+        if !in_upper && !in_lower {
+            return Err(Self::not_found(&path));
+        }
+
+        if in_upper {
+            self.backend.remove(&path)?;
+        }
+
+        if in_lower {
+            self.whiteouts.insert(path);
+        }
+
+        Ok(())
+    }
+
+    /// Moves a file, symlink or directory, replacing an existing file at
+    /// `to`, or an empty dir if a dir is moved. Dirs can't be moved out of
+    /// or into a mounted backend, nor if they exist in any of the lower
+    /// layers; like renames across filesystems, these fail with `EXDEV`,
+    /// and leave `to` as it was.
+    pub fn rename<P1, P2>(&mut self, from: P1, to: P2) -> Result<()>
+    where
+        P1: AsRef<path::Path>,
+        P2: AsRef<path::Path>,
+    {
+        let from = Self::normalize_path(from)?;
+        let to = Self::normalize_path(to)?;
+        for path in &[&from, &to] {
+            if self.mounts.contains_key(*path) {
+                return Err(MemFSError::InvalidPath(path.to_string_lossy().to_string()).into());
+            }
+            self.check_writable(path)?;
+        }
+
+        let file_type = self.metadata(&from)?.file_type;
+        if from == to {
+            return Ok(());
+        }
+        let replace_dir = self.check_rename_target(&from, &to, file_type)?;
+
+        let from_mount = self.mount_point(&from);
+        if from_mount.is_some() && from_mount == self.mount_point(&to) {
+            if replace_dir {
+                self.remove(&to)?;
+            }
+            let (fs, from) = self.resolve_mount(&from).unwrap();
+            let (_, to) = self.resolve_mount(&to).unwrap();
+            return Ok(fs.rename(&from, &to)?);
+        }
+
+        let in_upper = from_mount.is_none()
+            && self.mount_point(&to).is_none()
+            && self.backend.exists(&from)?;
+        let in_lower = self.resolve_lower(&from)?.is_some();
+
+        if in_upper && (file_type != FileType::Dir || !in_lower) {
+            self.copy_up_parent(&to)?;
+            if replace_dir {
+                self.remove(&to)?;
+            }
+            self.backend.rename(&from, &to)?;
+            if in_lower {
+                self.whiteouts.insert(from);
+            }
+            return Ok(());
+        }
+
+        match file_type {
+            FileType::File => {
+                let mut source = self.open_file(&from)?;
+                let mut file = self.create_file(&to)?;
+                io::copy(&mut source, &mut file).file_context(&from)?;
+            }
+            FileType::Symlink => {
+                let target = self.read_link(&from)?;
+                if self.resolve(&to)?.is_some() && !self.is_dir(&to)? {
+                    self.remove(&to)?;
+                }
+                self.create_symlink(&to, target)?;
+            }
+            _ => return Err(io::Error::from_raw_os_error(libc::EXDEV).into()),
+        }
+
+        self.remove(&from)
+    }
+
+    /// Checks that whatever is at `to` can be replaced by what is at `from`,
+    /// returning whether it is a dir, which has to be removed first.
+    fn check_rename_target(
+        &self,
+        from: &path::Path,
+        to: &path::Path,
+        file_type: FileType,
+    ) -> Result<bool> {
+        if to.starts_with(from) {
+            return Err(MemFSError::InvalidPath(to.to_string_lossy().to_string()).into());
+        }
+
+        let errno = match self.file_type(to)? {
+            Some(FileType::Dir) if file_type != FileType::Dir => libc::EISDIR,
+            Some(FileType::Dir) if !self.read_dir(to)?.is_empty() => libc::ENOTEMPTY,
+            Some(FileType::Dir) => return Ok(true),
+            Some(_) if file_type == FileType::Dir => libc::ENOTDIR,
+            _ => return Ok(false),
+        };
+
+        Err(io::Error::from_raw_os_error(errno)).file_context(to)
+    }
+
+    /// Reads from the file at `path` starting at `pos`, returning the number
+    /// of bytes read.
+    pub fn read_at<P>(&mut self, path: P, pos: u64, buf: &mut [u8]) -> Result<usize>
+    where
+        P: AsRef<path::Path>,
+    {
+        let mut file = self.open_file(path.as_ref())?;
+        file.seek(io::SeekFrom::Start(pos)).file_context(&path)?;
+
+        let mut count = 0;
+        while count < buf.len() {
+            match file.read(&mut buf[count..]) {
+                Ok(0) => break,
+                Ok(n) => count += n,
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err).file_context(&path),
+            }
+        }
+
+        Ok(count)
+    }
+
+    /// Writes `buf` to the file at `path` starting at `pos`, extending the
+    /// file if needed. Files from lower layers are copied up first.
+    pub fn write_at<P>(&mut self, path: P, pos: u64, buf: &[u8]) -> Result<()>
+    where
+        P: AsRef<path::Path>,
+    {
+        let path = Self::normalize_path(path)?;
+        if !self.is_device(&path)? {
+            self.check_writable(&path)?;
+        }
+        self.copy_up(&path)?;
+
+        let mut file = self.open_file(&path)?;
+        file.seek(io::SeekFrom::Start(pos)).file_context(&path)?;
+        file.write_all(buf).file_context(&path)?;
+
+        Ok(())
+    }
+
+    /// Truncates or zero-extends the file at `path` to `len` bytes, failing
+    /// with `EFBIG` past `MAX_LEN`. Files from lower layers are copied up
+    /// first.
+    pub fn set_len<P>(&mut self, path: P, len: u64) -> Result<()>
+    where
+        P: AsRef<path::Path>,
+    {
+        let path = Self::normalize_path(path)?;
+        if !self.is_file(&path)? {
+            return Err(Self::not_found(&path));
+        }
+        self.check_writable(&path)?;
+
+        if len > MAX_LEN {
+            return Err(io::Error::from_raw_os_error(libc::EFBIG)).file_context(&path);
+        }
+
+        if len == 0 {
+            self.create_file(&path)?;
+            return Ok(());
+        }
+
+        self.copy_up(&path)?;
+        let mut file = self.open_file(&path)?;
+        file.set_len(len).file_context(&path)?;
+
+        Ok(())
+    }
+
+    /// Copies a file only found in lower layers to the upper one.
+    fn copy_up(&mut self, path: &path::Path) -> Result<()> {
+        if self.mount_point(path).is_some() || self.backend.exists(path)? {
+            return Ok(());
+        }
+
+        let mut source = match self.resolve_lower(path)? {
+            Some(fs) => fs.open(path)?,
+            None => return Err(Self::not_found(path)),
+        };
+        self.copy_up_parent(path)?;
+        let mut file = self.backend.create(path)?;
+        io::copy(&mut source, &mut file).file_context(path)?;
+
+        Ok(())
+    }
+
+    pub fn metadata<P>(&self, path: P) -> Result<Metadata>
+    where
+        P: AsRef<path::Path>,
+    {
+        let path = Self::normalize_path(path)?;
+        match self.resolve(&path)? {
+            Some((fs, path)) => Ok(fs.metadata(&path)?),
+            None => Err(Self::not_found(&path)),
+        }
+    }
+
+    fn file_type<P>(&self, path: P) -> Result<Option<FileType>>
+    where
+        P: AsRef<path::Path>,
+    {
+        let path = Self::normalize_path(path)?;
+        match self.resolve(&path)? {
+            Some((fs, path)) => Ok(Some(fs.metadata(&path)?.file_type)),
+            None => Ok(None),
+        }
     }
 
-    pub fn read_file<P>(&mut self, path: P) -> Result<Vec<u8>>
+    pub fn is_dir<P>(&self, path: P) -> Result<bool>
     where
         P: AsRef<path::Path>,
     {
-        let mut file = self.backend.open_file(path.as_ref())?;
-        let mut contents = Vec::new();
-        file.read_to_end(&mut contents).file_context(&path)?;
+        Ok(self.file_type(path)? == Some(FileType::Dir))
+    }
 
-        Ok(contents)
+    /// Lists the names of entries in the dir at `path` across all layers.
+    pub fn read_dir<P>(&self, path: P) -> Result<Vec<ffi::OsString>>
+    where
+        P: AsRef<path::Path>,
+    {
+        let path = Self::normalize_path(path)?;
+        let mut names = BTreeSet::new();
+
+        if let Some((fs, fs_path)) = self.resolve_mount(&path) {
+            names.extend(fs.read_dir(&fs_path)?);
+        } else {
+            match self.resolve(&path)? {
+                Some((fs, _)) => names.extend(fs.read_dir(&path)?),
+                None => return Err(Self::not_found(&path)),
+            }
+
+            if !path.ancestors().any(|p| self.whiteouts.contains(p)) {
+                for layer in &self.lower {
+                    if layer.resolves(&path)? && layer.fs.metadata(&path)?.is_dir() {
+                        names.extend(layer.fs.read_dir(&path)?);
+                    }
+
+                    if layer.hides(&path) {
+                        break;
+                    }
+                }
+            }
+        }
+
+        for mount_point in self.mounts.keys() {
+            if mount_point.parent() == Some(&path) {
+                names.extend(mount_point.file_name().map(|name| name.to_owned()));
+            }
+        }
+
+        let mut entries = Vec::with_capacity(names.len());
+        for name in names {
+            if self.resolve(&path.join(&name))?.is_some() {
+                entries.push(name);
+            }
+        }
+
+        Ok(entries)
     }
 
-    pub fn write_file<P>(&mut self, path: P, contents: &[u8]) -> Result<()>
+    pub fn is_device<P>(&self, path: P) -> Result<bool>
     where
         P: AsRef<path::Path>,
     {
-        log::debug!("Writing file={:?}", path.as_ref());
-        let mut file = self.backend.create_file(path.as_ref())?;
-        file.write_all(contents).file_context(&path)?;
+        Ok(self.file_type(path)? == Some(FileType::Device))
+    }
 
-        Ok(())
+    pub fn is_file<P>(&self, path: P) -> Result<bool>
+    where
+        P: AsRef<path::Path>,
+    {
+        Ok(self.file_type(path)? == Some(FileType::File))
     }
 
-    pub fn create_dir_all<P>(&mut self, path: P) -> Result<()>
+    pub fn is_symlink<P>(&self, path: P) -> Result<bool>
     where
         P: AsRef<path::Path>,
     {
-        log::debug!("Creating subdirs={:?}", path.as_ref());
-        self.backend.create_dir_all(path)?;
+        Ok(self.file_type(path)? == Some(FileType::Symlink))
+    }
+
+    pub fn create_symlink<P1, P2>(&mut self, path: P1, target: P2) -> Result<()>
+    where
+        P1: AsRef<path::Path>,
+        P2: AsRef<path::Path>,
+    {
+        let path = Self::normalize_path(path)?;
+        self.check_writable(&path)?;
+        if let Some((fs, path)) = self.resolve_mount(&path) {
+            return Ok(fs.symlink(&path, target.as_ref())?);
+        }
+
+        self.copy_up_parent(&path)?;
+        self.backend.symlink(&path, target.as_ref())?;
 
         Ok(())
     }
 
+    pub fn read_link<P>(&self, path: P) -> Result<path::PathBuf>
+    where
+        P: AsRef<path::Path>,
+    {
+        let path = Self::normalize_path(path)?;
+        match self.resolve(&path)? {
+            Some((fs, path)) => Ok(fs.read_link(&path)?),
+            None => Err(Self::not_found(&path)),
+        }
+    }
+
+    /// Copies the host file at `source_path`, opened as given, to
+    /// `dest_path`.
     pub fn map_file<P>(&mut self, source_path: P, dest_path: P) -> Result<()>
     where
         P: AsRef<path::Path>,
     {
-        let contents = hostfs::read_file(source_path.as_ref())?;
-        self.write_file(dest_path.as_ref(), &contents)?;
+        let source = fs::File::open(source_path.as_ref()).file_context(&source_path)?;
+        self.copy_host_file(&source, source_path.as_ref(), dest_path.as_ref())
+    }
+
+    fn copy_host_file(
+        &mut self,
+        mut source: &fs::File,
+        source_path: &path::Path,
+        dest_path: &path::Path,
+    ) -> Result<()> {
+        let mut file = self.create_file(dest_path)?;
+        io::copy(&mut source, &mut file).file_context(source_path)?;
 
         Ok(())
     }
@@ -67,74 +988,261 @@ impl VirtualFS {
         P1: AsRef<path::Path>,
         P2: AsRef<path::Path>,
     {
-        let dest_path = dest_path.as_ref().to_owned();
-        let source_path = source_path.as_ref().to_owned();
-        let mut fifo = VecDeque::new();
+        self.map_path_with(source_path, dest_path, &MapOptions::default(), cb)
+    }
 
-        for entry in fs::read_dir(&source_path).file_context(&source_path)? {
-            let entry = entry.file_context(&source_path)?;
-            let source_path = entry.path();
+    /// Recursively maps the contents of the host dir `source_path` onto
+    /// `dest_path`, calling `cb` with the host and guest paths of every
+    /// entry before it is mapped. Symlinks followed with
+    /// `SymlinkPolicy::FollowWithinRoot` are reported with the path they
+    /// resolve to.
+    ///
+    /// If any limits are set, the whole tree is measured first and nothing
+    /// is mapped when it exceeds them.
+    pub fn map_path_with<P1, P2>(
+        &mut self,
+        source_path: P1,
+        dest_path: P2,
+        options: &MapOptions,
+        cb: &mut dyn FnMut(&path::Path, &path::Path),
+    ) -> Result<()>
+    where
+        P1: AsRef<path::Path>,
+        P2: AsRef<path::Path>,
+    {
+        if !options.limits.is_unlimited() {
+            let usage = Usage::measure(source_path.as_ref(), options)?;
+            log::debug!("input usage = {:?}", usage);
+            options.limits.check(&usage)?;
+        }
 
-            let mut dest_path = dest_path.clone();
-            dest_path.push(
-                source_path
-                    .file_name()
-                    .ok_or_else(|| Error::InvalidPath(source_path.to_string_lossy().to_string()))?,
-            );
+        walk_host(
+            source_path.as_ref(),
+            dest_path.as_ref(),
+            options,
+            &mut |source_path, dest_path, entry| {
+                cb(source_path, dest_path);
+
+                match entry {
+                    HostEntry::Dir => {
+                        self.create_dir_all(dest_path)?;
+                        log::debug!("mapped dir = {:?}", dest_path);
+                    }
+                    HostEntry::Symlink(target) => {
+                        self.create_symlink(dest_path, target)?;
+                        log::debug!("mapped symlink {:?} => {:?}", dest_path, target);
+                    }
+                    HostEntry::File(_, len) if options.lazy => {
+                        let dest_path = Self::normalize_path(dest_path)?;
+                        let mapped_len = if let Some((fs, path)) = self.resolve_mount(&dest_path) {
+                            fs.map_host_file(&path, source_path)?;
+                            fs.metadata(&path)?.len
+                        } else {
+                            self.copy_up_parent(&dest_path)?;
+                            self.backend.map_host_file(&dest_path, source_path)?;
+                            self.backend.metadata(&dest_path)?.len
+                        };
+                        // the length is what any limits were checked against,
+                        // and all the file is ever allowed to be
+                        if mapped_len != *len {
+                            let source_path = source_path.to_string_lossy().to_string();
+                            return Err(MemFSError::HostFileChanged(source_path).into());
+                        }
+                        log::debug!("lazily mapped file {:?} => {:?}", source_path, dest_path);
+                    }
+                    HostEntry::File(source, _) => {
+                        self.copy_host_file(source, source_path, dest_path)?;
+                        log::debug!("mapped file {:?} => {:?}", source_path, dest_path);
+                    }
+                }
+
+                Ok(())
+            },
+        )
+    }
+}
+
+/// Entry of a host dir tree, as seen by the guest. Files are passed on
+/// already open, together with their length.
+enum HostEntry {
+    Dir,
+    File(fs::File, u64),
+    Symlink(path::PathBuf),
+}
 
-            fifo.push_back((source_path, dest_path));
+/// Opens a host file for reading without following a symlink or blocking
+/// on a FIFO, as whatever was looked up under `path` could have been
+/// swapped since; the type of the opened file still has to be checked.
+/// `O_NONBLOCK` has no effect on reading regular files, so it is kept.
+fn open_host_file(path: &path::Path) -> Result<fs::File> {
+    fs::OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_NOFOLLOW | libc::O_NONBLOCK)
+        .open(path)
+        .file_context(path)
+}
+
+/// Walks the host dir `source_path` breadth-first, applying the symlink
+/// and special file policies from `options`, and calls `visit` with the
+/// host path, guest path and kind of every entry that should be mapped.
+fn walk_host(
+    source_path: &path::Path,
+    dest_path: &path::Path,
+    options: &MapOptions,
+    visit: &mut dyn FnMut(&path::Path, &path::Path, &HostEntry) -> Result<()>,
+) -> Result<()> {
+    // host path, guest path and the real locations of all dirs above it
+    type Queue = VecDeque<(path::PathBuf, path::PathBuf, Rc<Vec<path::PathBuf>>)>;
+
+    fn push_entries(
+        fifo: &mut Queue,
+        source_path: &path::Path,
+        dest_path: &path::Path,
+        ancestors: &Rc<Vec<path::PathBuf>>,
+    ) -> Result<()> {
+        for entry in fs::read_dir(source_path).file_context(source_path)? {
+            let entry = entry.file_context(source_path)?;
+            let source_path = entry.path();
+            let dest_path = dest_path.join(entry.file_name());
+
+            fifo.push_back((source_path, dest_path, Rc::clone(ancestors)));
         }
 
-        while let Some((source_path, dest_path)) = fifo.pop_front() {
-            log::debug!(
-                "source_path = {:?}, dest_path = {:?}",
-                source_path,
-                dest_path
-            );
-            cb(&source_path, &dest_path);
+        Ok(())
+    }
 
-            if source_path.is_dir() {
-                self.backend.create_dir(dest_path.as_path())?;
-                log::debug!("mapped dir = {:?}", dest_path);
+    let root = fs::canonicalize(source_path).file_context(source_path)?;
+    let mut fifo = VecDeque::new();
+    push_entries(
+        &mut fifo,
+        source_path,
+        dest_path,
+        &Rc::new(vec![root.clone()]),
+    )?;
 
-                for entry in fs::read_dir(&source_path).file_context(&source_path)? {
-                    let entry = entry.file_context(&source_path)?;
-                    let source_path = entry.path();
+    while let Some((source_path, dest_path, ancestors)) = fifo.pop_front() {
+        log::debug!(
+            "source_path = {:?}, dest_path = {:?}",
+            source_path,
+            dest_path
+        );
 
-                    let mut dest_path = dest_path.clone();
-                    dest_path.push(source_path.file_name().ok_or_else(|| {
-                        Error::InvalidPath(source_path.to_string_lossy().to_string())
-                    })?);
+        let mut source_path = source_path;
+        let mut metadata = fs::symlink_metadata(&source_path).file_context(&source_path)?;
+        // real location of the entry, used to detect symlink cycles
+        let mut real_path = ancestors
+            .last()
+            .unwrap()
+            .join(source_path.file_name().unwrap());
 
-                    fifo.push_back((source_path, dest_path));
+        if metadata.file_type().is_symlink() {
+            match options.symlinks {
+                SymlinkPolicy::Reject => return Err(Error::Symlink(source_path)),
+                SymlinkPolicy::Skip => {
+                    log::warn!("Skipping symlink {:?}", source_path);
+                    continue;
+                }
+                SymlinkPolicy::PreserveAsLink => {
+                    let target = fs::read_link(&source_path).file_context(&source_path)?;
+                    visit(&source_path, &dest_path, &HostEntry::Symlink(target))?;
+                    continue;
+                }
+                SymlinkPolicy::FollowWithinRoot => {
+                    let target = fs::canonicalize(&source_path).file_context(&source_path)?;
+                    if !target.starts_with(&root) {
+                        return Err(Error::SymlinkEscape(source_path));
+                    }
+                    if ancestors.iter().any(|dir| dir.starts_with(&target)) {
+                        return Err(Error::SymlinkCycle(source_path));
+                    }
+
+                    metadata = fs::metadata(&target).file_context(&target)?;
+                    real_path = target.clone();
+                    source_path = target;
                 }
-            } else {
-                self.map_file(source_path.as_path(), dest_path.as_path())?;
-                log::debug!("mapped file {:?} => {:?}", source_path, dest_path);
             }
         }
 
-        Ok(())
+        if metadata.is_dir() {
+            visit(&source_path, &dest_path, &HostEntry::Dir)?;
+
+            let mut ancestors = (*ancestors).clone();
+            ancestors.push(real_path);
+            push_entries(&mut fifo, &source_path, &dest_path, &Rc::new(ancestors))?;
+            continue;
+        }
+
+        // only the type of what actually got opened counts
+        let file = if metadata.is_file() {
+            let file = open_host_file(&source_path)?;
+            metadata = file.metadata().file_context(&source_path)?;
+            Some(file)
+        } else {
+            None
+        };
+
+        match file {
+            Some(file) if metadata.is_file() => {
+                let entry = HostEntry::File(file, metadata.len());
+                visit(&source_path, &dest_path, &entry)?;
+            }
+            _ => match options.special_files {
+                SpecialFilePolicy::Reject => return Err(Error::SpecialFile(source_path)),
+                SpecialFilePolicy::Skip => log::warn!("Skipping special file {:?}", source_path),
+            },
+        }
+    }
+
+    Ok(())
+}
+
+/// Lexically resolves `.` and `..` components of an absolute path,
+/// keeping non-UTF-8 components intact.
+fn clean_path<P>(path: P) -> path::PathBuf
+where
+    P: AsRef<path::Path>,
+{
+    let mut cleaned = path::PathBuf::from("/");
+    for component in path.as_ref().components() {
+        match component {
+            path::Component::Normal(name) => cleaned.push(name),
+            path::Component::ParentDir => {
+                cleaned.pop();
+            }
+            _ => {}
+        }
     }
+
+    cleaned
+}
+
+/// `MemFS` with nothing but the devices in `/dev`, reading `/dev/urandom`
+/// from `rand_emu`.
+fn devices(rand_emu: Arc<Mutex<RandEmu>>) -> MemFS {
+    let fs = MemFS::default();
+    fs.create_dir("/dev").unwrap();
+    fs.create_device("/dev/null", Null).unwrap();
+    fs.create_device("/dev/zero", Zero).unwrap();
+    fs.create_device("/dev/full", Full).unwrap();
+    fs.create_device("/dev/urandom", Urandom::new(rand_emu))
+        .unwrap();
+    fs
 }
 
 impl Default for VirtualFS {
     fn default() -> Self {
-        Self {
-            backend: MemFS::default(),
-        }
+        Self::with_rand_emu(Arc::default())
     }
 }
 
 pub mod hostfs {
     use crate::error::FileContext;
-    use crate::Result;
+    use crate::{Error, Result};
 
     use std::fs;
-    use std::io::{Read, Write};
+    use std::io::{self, Read};
     use std::path;
-
-    use path_clean::PathClean;
+    use std::process;
 
     pub fn read_file<P>(path: P) -> Result<Vec<u8>>
     where
@@ -147,14 +1255,289 @@ pub mod hostfs {
         Ok(contents)
     }
 
-    pub fn write_file<P>(path: P, contents: &[u8]) -> Result<()>
+    /// Streams `contents` to a file at `path` without first reading them
+    /// into a single buffer. The file is replaced atomically, see
+    /// `Transaction`.
+    pub fn write_file<P, R>(path: P, contents: &mut R) -> Result<()>
     where
         P: AsRef<path::Path>,
+        R: Read + ?Sized,
     {
-        let mut file = fs::File::create(path.as_ref()).file_context(&path)?;
-        file.write_all(contents).file_context(&path)?;
+        let mut transaction = Transaction::new(true);
+        transaction.write_file(path, contents)?;
+        transaction.commit()
+    }
 
-        Ok(())
+    /// Set of host files written all-or-nothing.
+    ///
+    /// Each file is first written to a temp file next to it, and only
+    /// `commit` moves the temp files into place, after syncing them to
+    /// disk. Dirs missing on the way to a file are created along with its
+    /// temp file. Temp files and dirs of a transaction which is dropped
+    /// without being committed are removed.
+    ///
+    /// Unless created with `overwrite` set, existing files are never
+    /// replaced: both `create` and `commit` fail with
+    /// `Error::OutputExists` instead. With it, replaced files are moved
+    /// aside until the commit is done, and put back if it fails.
+    #[derive(Debug)]
+    pub struct Transaction {
+        overwrite: bool,
+        // temp file and final path
+        pending: Vec<(path::PathBuf, path::PathBuf)>,
+        // in the order they were created
+        dirs: Vec<path::PathBuf>,
+    }
+
+    impl Transaction {
+        pub fn new(overwrite: bool) -> Self {
+            Self {
+                overwrite,
+                pending: Vec::new(),
+                dirs: Vec::new(),
+            }
+        }
+
+        /// Creates a temp file which becomes the file at `path` once the
+        /// transaction is committed.
+        pub fn create<P>(&mut self, path: P) -> Result<fs::File>
+        where
+            P: AsRef<path::Path>,
+        {
+            let path = path.as_ref();
+            if !self.overwrite && path.exists() {
+                return Err(Error::OutputExists(path.to_owned()));
+            }
+            if let Some(dir) = path.parent() {
+                self.create_dir_all(dir)?;
+            }
+
+            for n in 0.. {
+                let temp_path = sibling(path, n, "tmp")?;
+                match fs::OpenOptions::new()
+                    .write(true)
+                    .create_new(true)
+                    .open(&temp_path)
+                {
+                    Ok(file) => {
+                        log::debug!("Writing {:?} via temp file {:?}", path, temp_path);
+                        self.pending.push((temp_path, path.to_owned()));
+                        return Ok(file);
+                    }
+                    Err(ref err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+                    Err(err) => return Err(Error::FileError(err, temp_path)),
+                }
+            }
+
+            unreachable!()
+        }
+
+        /// Streams `contents` to a temp file which becomes the file at
+        /// `path` once the transaction is committed.
+        pub fn write_file<P, R>(&mut self, path: P, contents: &mut R) -> Result<()>
+        where
+            P: AsRef<path::Path>,
+            R: Read + ?Sized,
+        {
+            let mut file = self.create(path.as_ref())?;
+            io::copy(contents, &mut file).file_context(&path)?;
+
+            Ok(())
+        }
+
+        /// Moves all files into place. If any of them fails, the files
+        /// moved so far are removed again, or replaced back with the files
+        /// they replaced.
+        pub fn commit(mut self) -> Result<()> {
+            for (temp_path, _) in &self.pending {
+                fs::File::open(temp_path)
+                    .and_then(|file| file.sync_all())
+                    .file_context(temp_path)?;
+            }
+
+            let mut replaced = Vec::new();
+            let mut dirs = Vec::new();
+            for (temp_path, path) in &self.pending {
+                match self.replace(temp_path, path) {
+                    Ok(backup) => replaced.push((path, backup)),
+                    Err(err) => {
+                        for (path, backup) in replaced.into_iter().rev() {
+                            let _ = match backup {
+                                Some(backup) => fs::rename(backup, path),
+                                None => fs::remove_file(path),
+                            };
+                        }
+                        return Err(err);
+                    }
+                }
+
+                if let Some(dir) = path.parent() {
+                    if !dirs.contains(&dir) {
+                        dirs.push(dir);
+                    }
+                }
+            }
+
+            for (_, backup) in replaced {
+                if let Some(backup) = backup {
+                    let _ = fs::remove_file(backup);
+                }
+            }
+
+            // make the renames themselves durable
+            for dir in dirs {
+                let dir = if dir.as_os_str().is_empty() {
+                    path::Path::new(".")
+                } else {
+                    dir
+                };
+                fs::File::open(dir)
+                    .and_then(|dir| dir.sync_all())
+                    .file_context(dir)?;
+            }
+
+            self.pending.clear();
+            self.dirs.clear();
+
+            Ok(())
+        }
+
+        fn create_dir_all(&mut self, dir: &path::Path) -> Result<()> {
+            let missing: Vec<_> = dir
+                .ancestors()
+                .take_while(|dir| !dir.as_os_str().is_empty() && !dir.exists())
+                .collect();
+
+            for dir in missing.into_iter().rev() {
+                match fs::create_dir(dir) {
+                    Ok(()) => self.dirs.push(dir.to_owned()),
+                    Err(ref err) if err.kind() == io::ErrorKind::AlreadyExists => {}
+                    Err(err) => return Err(Error::FileError(err, dir.to_owned())),
+                }
+            }
+
+            Ok(())
+        }
+
+        /// Moves the temp file into place, returning where the file it
+        /// replaced, if any, has been moved aside to.
+        fn replace(
+            &self,
+            temp_path: &path::Path,
+            path: &path::Path,
+        ) -> Result<Option<path::PathBuf>> {
+            if !self.overwrite {
+                return self.persist(temp_path, path).map(|()| None);
+            }
+
+            let backup = match fs::symlink_metadata(path) {
+                Ok(_) => {
+                    let backup = backup_path(path)?;
+                    fs::rename(path, &backup).file_context(path)?;
+                    Some(backup)
+                }
+                Err(ref err) if err.kind() == io::ErrorKind::NotFound => None,
+                Err(err) => return Err(Error::FileError(err, path.to_owned())),
+            };
+
+            if let Err(err) = self.persist(temp_path, path) {
+                if let Some(backup) = backup {
+                    let _ = fs::rename(backup, path);
+                }
+                return Err(err);
+            }
+
+            Ok(backup)
+        }
+
+        fn persist(&self, temp_path: &path::Path, path: &path::Path) -> Result<()> {
+            if self.overwrite {
+                return fs::rename(temp_path, path).file_context(path);
+            }
+
+            // unlike `rename`, fails if `path` has been created meanwhile
+            match fs::hard_link(temp_path, path) {
+                Ok(()) => fs::remove_file(temp_path).file_context(temp_path),
+                Err(ref err) if err.kind() == io::ErrorKind::AlreadyExists => {
+                    Err(Error::OutputExists(path.to_owned()))
+                }
+                Err(ref err) if is_link_unsupported(err) => {
+                    log::debug!("Can't link {:?}, copying it instead: {}", path, err);
+                    copy_new(temp_path, path)
+                }
+                Err(err) => Err(Error::FileError(err, path.to_owned())),
+            }
+        }
+    }
+
+    /// Hidden file next to `path` for this process, numbered `n`.
+    fn sibling(path: &path::Path, n: u32, extension: &str) -> Result<path::PathBuf> {
+        let name = path
+            .file_name()
+            .ok_or_else(|| Error::InvalidPath(path.to_string_lossy().to_string()))?;
+        let mut sibling_name = std::ffi::OsString::from(".");
+        sibling_name.push(name);
+        sibling_name.push(format!(".{}.{}.{}", process::id(), n, extension));
+
+        Ok(path.with_file_name(sibling_name))
+    }
+
+    /// First free name to move the file at `path` aside to.
+    fn backup_path(path: &path::Path) -> Result<path::PathBuf> {
+        for n in 0.. {
+            let backup = sibling(path, n, "bak")?;
+            if fs::symlink_metadata(&backup).is_err() {
+                return Ok(backup);
+            }
+        }
+
+        unreachable!()
+    }
+
+    /// Copies `temp_path` to `path` where hard links aren't available,
+    /// still failing if `path` already exists.
+    pub(super) fn copy_new(temp_path: &path::Path, path: &path::Path) -> Result<()> {
+        let mut file = match fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(path)
+        {
+            Ok(file) => file,
+            Err(ref err) if err.kind() == io::ErrorKind::AlreadyExists => {
+                return Err(Error::OutputExists(path.to_owned()));
+            }
+            Err(err) => return Err(Error::FileError(err, path.to_owned())),
+        };
+
+        let copied = fs::File::open(temp_path)
+            .and_then(|mut temp_file| io::copy(&mut temp_file, &mut file))
+            .and_then(|_| file.sync_all());
+        if let Err(err) = copied {
+            let _ = fs::remove_file(path);
+            return Err(Error::FileError(err, path.to_owned()));
+        }
+
+        fs::remove_file(temp_path).file_context(temp_path)
+    }
+
+    // what linking fails with on filesystems without hard links, or with
+    // a file bind-mounted over the output
+    fn is_link_unsupported(err: &io::Error) -> bool {
+        err.raw_os_error().map_or(false, |code| {
+            [libc::EPERM, libc::ENOTSUP, libc::EOPNOTSUPP, libc::EXDEV].contains(&code)
+        })
+    }
+
+    impl Drop for Transaction {
+        fn drop(&mut self) {
+            for (temp_path, _) in &self.pending {
+                let _ = fs::remove_file(temp_path);
+            }
+            // only empty ones, in case something else ended up there
+            for dir in self.dirs.iter().rev() {
+                let _ = fs::remove_dir(dir);
+            }
+        }
     }
 
     pub fn create_dir_all<P>(path: P) -> Result<()>
@@ -170,19 +1553,146 @@ pub mod hostfs {
     where
         P: AsRef<path::Path>,
     {
-        let mut sanitized = path::PathBuf::from("/");
-        sanitized.push(path.as_ref());
-        let sanitized = sanitized.clean();
+        let sanitized = super::clean_path(path::Path::new("/").join(path.as_ref()));
         let path = sanitized.strip_prefix("/").map(path::PathBuf::from)?;
 
         Ok(path)
     }
+
+    /// Stricter variant of `sanitize_path` for paths of archive entries:
+    /// instead of being resolved, absolute paths and paths containing `..`
+    /// are rejected.
+    pub fn sanitize_archive_path<P>(path: P) -> Result<path::PathBuf>
+    where
+        P: AsRef<path::Path>,
+    {
+        let mut sanitized = path::PathBuf::new();
+        for component in path.as_ref().components() {
+            match component {
+                path::Component::Normal(name) => sanitized.push(name),
+                path::Component::CurDir => {}
+                _ => {
+                    return Err(Error::InvalidPath(
+                        path.as_ref().to_string_lossy().to_string(),
+                    ))
+                }
+            }
+        }
+
+        Ok(sanitized)
+    }
+}
+
+/// Conversion of file names between the host and the guest.
+///
+/// Emscripten's `FS` works with JS strings, so names which are not valid
+/// UTF-8 cannot be handed to the guest as they are. Instead, each byte
+/// that is not part of a valid UTF-8 sequence is presented to the guest
+/// as the private use character `U+F700 + byte` (`U+F780`-`U+F7FF`), and
+/// such characters are turned back into the original bytes on the way
+/// out. Valid UTF-8 names which happen to contain these characters have
+/// them escaped byte by byte as well, so the mapping is lossless.
+pub mod names {
+    use std::ffi::{OsStr, OsString};
+    use std::os::unix::ffi::{OsStrExt, OsStringExt};
+    use std::str;
+
+    const ESCAPE_BASE: u32 = 0xF700;
+    const ESCAPE_FIRST: u32 = ESCAPE_BASE + 0x80;
+    const ESCAPE_LAST: u32 = ESCAPE_BASE + 0xFF;
+
+    fn is_escape(c: char) -> bool {
+        let c = c as u32;
+        c >= ESCAPE_FIRST && c <= ESCAPE_LAST
+    }
+
+    fn push_escaped(guest: &mut String, bytes: &[u8]) {
+        for byte in bytes {
+            guest.push(std::char::from_u32(ESCAPE_BASE + u32::from(*byte)).unwrap());
+        }
+    }
+
+    fn push_valid(guest: &mut String, valid: &str) {
+        for c in valid.chars() {
+            if is_escape(c) {
+                let mut buf = [0; 4];
+                push_escaped(guest, c.encode_utf8(&mut buf).as_bytes());
+            } else {
+                guest.push(c);
+            }
+        }
+    }
+
+    /// Converts a host name (or path) into the name seen by the guest.
+    pub fn to_guest<S>(name: S) -> String
+    where
+        S: AsRef<OsStr>,
+    {
+        let mut bytes = name.as_ref().as_bytes();
+        let mut guest = String::with_capacity(bytes.len());
+
+        loop {
+            match str::from_utf8(bytes) {
+                Ok(valid) => {
+                    push_valid(&mut guest, valid);
+                    return guest;
+                }
+                Err(err) => {
+                    let (valid, rest) = bytes.split_at(err.valid_up_to());
+                    push_valid(&mut guest, str::from_utf8(valid).unwrap());
+
+                    let invalid_len = err.error_len().unwrap_or_else(|| rest.len());
+                    push_escaped(&mut guest, &rest[..invalid_len]);
+                    bytes = &rest[invalid_len..];
+                }
+            }
+        }
+    }
+
+    /// Converts a name (or path) seen by the guest back into the host one.
+    pub fn from_guest<S>(name: S) -> OsString
+    where
+        S: AsRef<str>,
+    {
+        let mut bytes = Vec::with_capacity(name.as_ref().len());
+        for c in name.as_ref().chars() {
+            if is_escape(c) {
+                bytes.push((c as u32 - ESCAPE_BASE) as u8);
+            } else {
+                let mut buf = [0; 4];
+                bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            }
+        }
+
+        OsString::from_vec(bytes)
+    }
 }
 
 #[cfg(test)]
 mod test {
     use std::path::PathBuf;
 
+    #[test]
+    fn copy_new() -> crate::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let temp_path = dir.path().join(".out.txt.tmp");
+        let path = dir.path().join("out.txt");
+        std::fs::write(&temp_path, b"output")?;
+
+        super::hostfs::copy_new(&temp_path, &path)?;
+        assert_eq!(std::fs::read(&path)?, b"output");
+        assert!(!temp_path.exists());
+
+        std::fs::write(&temp_path, b"again")?;
+        match super::hostfs::copy_new(&temp_path, &path) {
+            Err(crate::Error::OutputExists(existing)) => assert_eq!(existing, path),
+            result => panic!("expected the output to exist, got {:?}", result),
+        }
+        assert_eq!(std::fs::read(&path)?, b"output");
+
+        Ok(())
+    }
+
     #[test]
     fn sanitize_path() {
         assert_eq!(
@@ -200,4 +1710,46 @@ mod test {
             super::hostfs::sanitize_path("out/../out/../out/../out/out.txt")
         );
     }
+
+    #[test]
+    fn sanitize_archive_path() {
+        use crate::Error;
+
+        assert_eq!(
+            Ok(PathBuf::from("out/out.txt")),
+            super::hostfs::sanitize_archive_path("./out/./out.txt")
+        );
+
+        assert_eq!(
+            Err(Error::InvalidPath("/etc/passwd".to_owned())),
+            super::hostfs::sanitize_archive_path("/etc/passwd")
+        );
+
+        assert_eq!(
+            Err(Error::InvalidPath("out/../../out.txt".to_owned())),
+            super::hostfs::sanitize_archive_path("out/../../out.txt")
+        );
+    }
+
+    #[test]
+    fn guest_names() {
+        use super::names::{from_guest, to_guest};
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        assert_eq!(to_guest("/in/zażółć.txt"), "/in/zażółć.txt");
+        assert_eq!(from_guest("/in/zażółć.txt"), OsStr::new("/in/zażółć.txt"));
+
+        let latin1 = OsStr::from_bytes(b"/in/caf\xe9.txt");
+        assert_eq!(to_guest(latin1), "/in/caf\u{f7e9}.txt");
+        assert_eq!(from_guest(to_guest(latin1)), latin1);
+
+        let truncated = OsStr::from_bytes(b"\xc5");
+        assert_eq!(to_guest(truncated), "\u{f7c5}");
+        assert_eq!(from_guest(to_guest(truncated)), truncated);
+
+        let escape_like = OsStr::new("\u{f7e9}");
+        assert_eq!(to_guest(escape_like), "\u{f7ef}\u{f79f}\u{f7a9}");
+        assert_eq!(from_guest(to_guest(escape_like)), escape_like);
+    }
 }
@@ -0,0 +1,275 @@
+use super::error::Error;
+use super::memfs::MemFS;
+use super::Result;
+use std::ffi::OsString;
+use std::fmt;
+use std::fs;
+use std::io::{self, Read, Seek, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FileType {
+    Dir,
+    File,
+    Device,
+    Symlink,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Metadata {
+    pub file_type: FileType,
+    pub len: u64,
+}
+
+impl Metadata {
+    pub fn is_dir(&self) -> bool {
+        self.file_type == FileType::Dir
+    }
+
+    pub fn is_file(&self) -> bool {
+        self.file_type == FileType::File
+    }
+
+    pub fn is_device(&self) -> bool {
+        self.file_type == FileType::Device
+    }
+
+    pub fn is_symlink(&self) -> bool {
+        self.file_type == FileType::Symlink
+    }
+}
+
+/// Open file handed out by a `FsBackend`. Writes go to the current
+/// position, as with `std::fs::File`.
+pub trait FsFile: Read + Write + Seek + Send {
+    /// Truncates or zero-extends the file to `len` bytes, leaving the
+    /// current position as is.
+    fn set_len(&mut self, len: u64) -> io::Result<()>;
+}
+
+impl FsFile for fs::File {
+    fn set_len(&mut self, len: u64) -> io::Result<()> {
+        fs::File::set_len(self, len)
+    }
+}
+
+/// Storage behind a layer of the guest filesystem.
+///
+/// Paths passed to a backend are always absolute and free of `.` and
+/// `..` components.
+pub trait FsBackend: fmt::Debug + Send + Sync {
+    fn open(&self, path: &Path) -> Result<Box<dyn FsFile>>;
+
+    /// Creates a file, replacing any existing file at `path`.
+    fn create(&self, path: &Path) -> Result<Box<dyn FsFile>>;
+
+    fn create_dir(&self, path: &Path) -> Result<()>;
+
+    fn read_dir(&self, path: &Path) -> Result<Vec<OsString>>;
+
+    fn metadata(&self, path: &Path) -> Result<Metadata>;
+
+    /// Removes a file, or a directory together with all of its contents.
+    fn remove(&self, path: &Path) -> Result<()>;
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<()>;
+
+    fn exists(&self, path: &Path) -> Result<bool> {
+        match self.metadata(path) {
+            Ok(_) => Ok(true),
+            Err(Error::NotFound(_)) => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn create_dir_all(&self, path: &Path) -> Result<()> {
+        let mut ancestors: Vec<_> = path.ancestors().collect();
+        ancestors.reverse();
+
+        for path in ancestors {
+            match self.metadata(path) {
+                Ok(ref metadata) if metadata.is_dir() => {}
+                Ok(_) => return Err(Error::NotADirectory(path.to_string_lossy().to_string())),
+                Err(Error::NotFound(_)) => self.create_dir(path)?,
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Creates a symlink at `path` pointing to `target`.
+    fn symlink(&self, path: &Path, _target: &Path) -> Result<()> {
+        Err(unsupported(path, "symlinks"))
+    }
+
+    fn read_link(&self, path: &Path) -> Result<PathBuf> {
+        Err(unsupported(path, "symlinks"))
+    }
+
+    /// Creates a file with the contents of `host_path`. Backends able to
+    /// defer reading them until the file is first accessed override this.
+    fn map_host_file(&self, path: &Path, host_path: &Path) -> Result<()> {
+        let mut source = fs::File::open(host_path)?;
+        let mut file = self.create(path)?;
+        io::copy(&mut source, &mut file)?;
+        file.flush()?;
+
+        Ok(())
+    }
+
+    /// Writes the contents of the file at `path` to `writer`, returning
+    /// their length. Backends keeping files in memory override this to
+    /// hand them over as stored, without copying them through a buffer.
+    fn write_to(&self, path: &Path, writer: &mut dyn Write) -> Result<u64> {
+        let mut file = self.open(path)?;
+        Ok(io::copy(&mut file, writer)?)
+    }
+}
+
+impl FsBackend for MemFS {
+    fn open(&self, path: &Path) -> Result<Box<dyn FsFile>> {
+        Ok(Box::new(self.open_file(path)?))
+    }
+
+    fn create(&self, path: &Path) -> Result<Box<dyn FsFile>> {
+        Ok(Box::new(self.create_file(path)?))
+    }
+
+    fn create_dir(&self, path: &Path) -> Result<()> {
+        MemFS::create_dir(self, path)
+    }
+
+    fn read_dir(&self, path: &Path) -> Result<Vec<OsString>> {
+        MemFS::read_dir(self, path)
+    }
+
+    fn metadata(&self, path: &Path) -> Result<Metadata> {
+        MemFS::metadata(self, path)
+    }
+
+    fn remove(&self, path: &Path) -> Result<()> {
+        MemFS::remove(self, path)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        MemFS::rename(self, from, to)
+    }
+
+    fn create_dir_all(&self, path: &Path) -> Result<()> {
+        MemFS::create_dir_all(self, path)
+    }
+
+    fn map_host_file(&self, path: &Path, host_path: &Path) -> Result<()> {
+        MemFS::map_host_file(self, path, host_path)
+    }
+
+    fn symlink(&self, path: &Path, target: &Path) -> Result<()> {
+        MemFS::create_symlink(self, path, target)
+    }
+
+    fn read_link(&self, path: &Path) -> Result<PathBuf> {
+        MemFS::read_link(self, path)
+    }
+
+    fn write_to(&self, path: &Path, writer: &mut dyn Write) -> Result<u64> {
+        self.open_file(path)?.write_to(writer)
+    }
+}
+
+fn unsupported(path: &Path, what: &str) -> Error {
+    io::Error::other(format!("{} not supported at '{}'", what, path.display())).into()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::contents::CHUNK_SIZE;
+    use std::io::{Read, Write};
+
+    #[test]
+    fn default_create_dir_all() -> Result<()> {
+        // goes through `create_dir` only
+        #[derive(Debug)]
+        struct DirsOnly(MemFS);
+
+        impl FsBackend for DirsOnly {
+            fn open(&self, path: &Path) -> Result<Box<dyn FsFile>> {
+                self.0.open(path)
+            }
+
+            fn create(&self, path: &Path) -> Result<Box<dyn FsFile>> {
+                self.0.create(path)
+            }
+
+            fn create_dir(&self, path: &Path) -> Result<()> {
+                self.0.create_dir(path)
+            }
+
+            fn read_dir(&self, path: &Path) -> Result<Vec<OsString>> {
+                FsBackend::read_dir(&self.0, path)
+            }
+
+            fn metadata(&self, path: &Path) -> Result<Metadata> {
+                self.0.metadata(path)
+            }
+
+            fn remove(&self, path: &Path) -> Result<()> {
+                self.0.remove(path)
+            }
+
+            fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+                self.0.rename(from, to)
+            }
+        }
+
+        let fs = DirsOnly(MemFS::new());
+        FsBackend::create_dir_all(&fs, Path::new("/tmp/a/b"))?;
+        assert!(fs.metadata(Path::new("/tmp/a/b"))?.is_dir());
+
+        fs.create(Path::new("/tmp/a.txt"))?.write_all(b"Hello")?;
+        let mut contents = Vec::new();
+        assert_eq!(fs.write_to(Path::new("/tmp/a.txt"), &mut contents)?, 5);
+        assert_eq!(contents, b"Hello");
+
+        assert_eq!(
+            FsBackend::create_dir_all(&fs, Path::new("/tmp/a.txt/b")).unwrap_err(),
+            Error::NotADirectory("/tmp/a.txt".to_owned())
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn boxed_memfs() -> Result<()> {
+        let fs: Box<dyn FsBackend> = Box::new(MemFS::new());
+        fs.create_dir(Path::new("/tmp"))?;
+        fs.create(Path::new("/tmp/a.txt"))?.write_all(b"Hello")?;
+        fs.rename(Path::new("/tmp/a.txt"), Path::new("/b.txt"))?;
+
+        assert!(!fs.exists(Path::new("/tmp/a.txt"))?);
+        assert_eq!(
+            fs.metadata(Path::new("/b.txt"))?,
+            Metadata {
+                file_type: FileType::File,
+                len: 5
+            }
+        );
+
+        let mut contents = Vec::new();
+        fs.open(Path::new("/b.txt"))?.read_to_end(&mut contents)?;
+        assert_eq!(contents, b"Hello");
+
+        // several chunks
+        let big = vec![7; 3 * CHUNK_SIZE / 2];
+        fs.create(Path::new("/big.bin"))?.write_all(&big)?;
+        let mut contents = Vec::new();
+        assert_eq!(
+            fs.write_to(Path::new("/big.bin"), &mut contents)?,
+            big.len() as u64
+        );
+        assert_eq!(contents, big);
+
+        Ok(())
+    }
+}
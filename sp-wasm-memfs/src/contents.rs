@@ -0,0 +1,529 @@
+use super::error::Error;
+use super::Result;
+use std::cmp;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::iter;
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Size of a single chunk of file contents.
+pub const CHUNK_SIZE: usize = 64 * 1024;
+
+static ZEROS: [u8; CHUNK_SIZE] = [0; CHUNK_SIZE];
+
+/// Host file backing contents that have not been loaded yet.
+///
+/// The file is identified by its device and inode besides its path, so a
+/// different file put in its place is never read, and its length is the
+/// one accounted for when it was mapped.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HostSource {
+    pub path: PathBuf,
+    pub len: u64,
+    pub modified: Option<SystemTime>,
+    pub dev: u64,
+    pub ino: u64,
+}
+
+impl HostSource {
+    pub fn new<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let file = Self::open(path.as_ref())?;
+        Self::from_file(path.as_ref(), &file)?
+            .ok_or_else(|| Error::NotFound(path.as_ref().to_string_lossy().to_string()))
+    }
+
+    /// Opens the host file without following a symlink or blocking on a
+    /// FIFO, either of which could have been put in its place.
+    fn open(path: &Path) -> Result<fs::File> {
+        let file = fs::OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_NOFOLLOW | libc::O_NONBLOCK)
+            .open(path)?;
+
+        Ok(file)
+    }
+
+    /// Describes the opened `file`, unless it isn't a regular file.
+    fn from_file(path: &Path, file: &fs::File) -> Result<Option<Self>> {
+        let metadata = file.metadata()?;
+        if !metadata.is_file() {
+            return Ok(None);
+        }
+
+        Ok(Some(Self {
+            path: path.to_owned(),
+            len: metadata.len(),
+            modified: metadata.modified().ok(),
+            dev: metadata.dev(),
+            ino: metadata.ino(),
+        }))
+    }
+
+    /// Passes the host file to `f` chunk by chunk, failing if it was
+    /// replaced or modified since the source was created.
+    fn read_with<F>(&self, mut f: F) -> Result<()>
+    where
+        F: FnMut(&[u8]) -> Result<()>,
+    {
+        let mut file = Self::open(&self.path).map_err(|_| self.changed())?;
+        self.verify(&file)?;
+
+        let mut buf = vec![0; CHUNK_SIZE];
+        let mut len = 0;
+        loop {
+            let count = file.read(&mut buf)?;
+            if count == 0 {
+                break;
+            }
+            f(&buf[..count])?;
+            len += count as u64;
+        }
+
+        if len != self.len {
+            return Err(self.changed());
+        }
+
+        self.verify(&file)
+    }
+
+    fn verify(&self, file: &fs::File) -> Result<()> {
+        if Self::from_file(&self.path, file)?.as_ref() != Some(self) {
+            return Err(self.changed());
+        }
+
+        Ok(())
+    }
+
+    fn changed(&self) -> Error {
+        Error::HostFileChanged(self.path.to_string_lossy().to_string())
+    }
+}
+
+/// Largest size a file can be written or truncated to, so the guest
+/// can't make the host allocate without bounds.
+pub const MAX_LEN: u64 = 4 * 1024 * 1024 * 1024;
+
+/// Contents of a file node stored as a list of fixed-size chunks.
+///
+/// Every chunk except the last one spans exactly `CHUNK_SIZE` bytes, so
+/// appending never moves previously written data and locating the chunk
+/// holding a given position is a single division. Chunks only store data
+/// up to the last byte written to them, the rest reads as zeros, so gaps
+/// left by writing or truncating past the end take no memory.
+///
+/// Contents created with `Contents::from_host` are only read from the
+/// host on the first call to `Contents::load`.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Contents {
+    chunks: Vec<Vec<u8>>,
+    len: usize,
+    source: Option<HostSource>,
+}
+
+impl Contents {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_host<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let source = HostSource::new(path)?;
+
+        Ok(Self {
+            chunks: Vec::new(),
+            len: source.len as usize,
+            source: Some(source),
+        })
+    }
+
+    pub fn is_loaded(&self) -> bool {
+        self.source.is_none()
+    }
+
+    /// Reads the backing host file, if any, failing if it was modified
+    /// since the contents were created.
+    pub fn load(&mut self) -> Result<()> {
+        let source = match self.source.take() {
+            Some(source) => source,
+            None => return Ok(()),
+        };
+
+        self.len = 0;
+        if let Err(err) = self.load_from(&source) {
+            self.chunks.clear();
+            self.len = source.len as usize;
+            self.source = Some(source);
+            return Err(err);
+        }
+
+        Ok(())
+    }
+
+    fn load_from(&mut self, source: &HostSource) -> Result<()> {
+        source.read_with(|buf| {
+            self.append(buf);
+            Ok(())
+        })
+    }
+
+    /// Writes the contents to `writer`. Contents not loaded yet are
+    /// streamed from the host file without being kept in memory.
+    pub fn write_to(&self, writer: &mut dyn Write) -> Result<u64> {
+        if let Some(source) = self.source.as_ref() {
+            source.read_with(|buf| Ok(writer.write_all(buf)?))?;
+        } else {
+            for chunk in self.chunks() {
+                writer.write_all(chunk)?;
+            }
+        }
+
+        Ok(self.len as u64)
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn append(&mut self, buf: &[u8]) {
+        debug_assert!(self.is_loaded());
+
+        let pos = self.len;
+        self.write_chunks(pos, buf);
+    }
+
+    /// Writes `buf` at `pos`, overwriting existing data and extending the
+    /// contents as needed. Any gap before `pos` reads as zeros.
+    ///
+    /// Fails with `EFBIG` if the contents would grow past `MAX_LEN`.
+    pub fn write_at(&mut self, pos: usize, buf: &[u8]) -> Result<()> {
+        debug_assert!(self.is_loaded());
+
+        match pos.checked_add(buf.len()) {
+            Some(end) if end as u64 <= MAX_LEN => {}
+            _ => return Err(too_large()),
+        }
+
+        self.write_chunks(pos, buf);
+        Ok(())
+    }
+
+    /// Truncates or zero-extends the contents to `len` bytes, dropping or
+    /// adding whole chunks without touching the data kept.
+    ///
+    /// Fails with `EFBIG` if `len` is past `MAX_LEN`.
+    pub fn set_len(&mut self, len: u64) -> Result<()> {
+        debug_assert!(self.is_loaded());
+
+        if len > MAX_LEN {
+            return Err(too_large());
+        }
+
+        self.resize(len as usize);
+        Ok(())
+    }
+
+    pub fn read_at(&self, mut pos: usize, buf: &mut [u8]) -> usize {
+        debug_assert!(self.is_loaded());
+
+        let mut count = 0;
+
+        while count < buf.len() && pos < self.len {
+            let index = pos / CHUNK_SIZE;
+            let offset = pos % CHUNK_SIZE;
+            let n = cmp::min(self.chunk_len(index) - offset, buf.len() - count);
+
+            let stored = self.chunks[index].get(offset..).unwrap_or(&[]);
+            let stored = &stored[..cmp::min(stored.len(), n)];
+            buf[count..count + stored.len()].copy_from_slice(stored);
+            buf[count + stored.len()..count + n].fill(0);
+
+            count += n;
+            pos += n;
+        }
+
+        count
+    }
+
+    /// Iterates over the contents in order. Parts never written to are
+    /// passed on as slices of zeros.
+    pub fn chunks(&self) -> impl Iterator<Item = &[u8]> {
+        self.chunks
+            .iter()
+            .enumerate()
+            .flat_map(move |(index, chunk)| {
+                let gap = self.chunk_len(index) - chunk.len();
+                iter::once(chunk.as_slice()).chain(iter::once(&ZEROS[..gap]))
+            })
+            .filter(|chunk| !chunk.is_empty())
+    }
+
+    pub fn to_vec(&self) -> Vec<u8> {
+        let mut contents = Vec::with_capacity(self.len);
+        for chunk in self.chunks() {
+            contents.extend_from_slice(chunk);
+        }
+        contents
+    }
+
+    pub fn clear(&mut self) {
+        self.chunks.clear();
+        self.len = 0;
+        self.source = None;
+    }
+
+    /// Length of the chunk at `index`, including any zeros not stored.
+    fn chunk_len(&self, index: usize) -> usize {
+        cmp::min(self.len - index * CHUNK_SIZE, CHUNK_SIZE)
+    }
+
+    fn write_chunks(&mut self, mut pos: usize, mut buf: &[u8]) {
+        if pos + buf.len() > self.len {
+            self.resize(pos + buf.len());
+        }
+
+        while !buf.is_empty() {
+            let chunk = &mut self.chunks[pos / CHUNK_SIZE];
+            let offset = pos % CHUNK_SIZE;
+            let n = cmp::min(CHUNK_SIZE - offset, buf.len());
+
+            if chunk.len() < offset + n {
+                // grow as `Vec` would, but never past a whole chunk
+                let capacity = cmp::min(cmp::max(2 * chunk.capacity(), offset + n), CHUNK_SIZE);
+                chunk.reserve_exact(capacity - chunk.len());
+                chunk.resize(offset + n, 0);
+            }
+            chunk[offset..offset + n].copy_from_slice(&buf[..n]);
+
+            pos += n;
+            buf = &buf[n..];
+        }
+    }
+
+    fn resize(&mut self, len: usize) {
+        let count = len.div_ceil(CHUNK_SIZE);
+        self.chunks.resize_with(count, Vec::new);
+        if let Some(chunk) = self.chunks.last_mut() {
+            // data past the new end must read as zeros if extended again
+            chunk.truncate(len - (count - 1) * CHUNK_SIZE);
+        }
+        self.len = len;
+    }
+}
+
+fn too_large() -> Error {
+    io::Error::from_raw_os_error(libc::EFBIG).into()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn append() {
+        let mut contents = Contents::new();
+        contents.append(b"Hello");
+        contents.append(&vec![1; CHUNK_SIZE]);
+        contents.append(&vec![2; CHUNK_SIZE + 3]);
+
+        assert_eq!(contents.len(), 2 * CHUNK_SIZE + 8);
+        assert_eq!(
+            contents.chunks().map(|c| c.len()).collect::<Vec<_>>(),
+            vec![CHUNK_SIZE, CHUNK_SIZE, 8]
+        );
+        assert_eq!(&contents.to_vec()[..6], b"Hello\x01");
+    }
+
+    #[test]
+    fn read_at() {
+        let mut contents = Contents::new();
+        contents.append(&vec![1; CHUNK_SIZE - 2]);
+        contents.append(b"abcd");
+
+        let mut buf = [0; 8];
+        assert_eq!(contents.read_at(CHUNK_SIZE - 3, &mut buf), 5);
+        assert_eq!(&buf[..5], b"\x01abcd");
+
+        assert_eq!(contents.read_at(contents.len(), &mut buf), 0);
+    }
+
+    #[test]
+    fn write_at() {
+        let mut contents = Contents::new();
+        contents.append(&vec![1; CHUNK_SIZE + 2]);
+
+        // across a chunk boundary and past the end
+        contents.write_at(CHUNK_SIZE - 1, b"abcd").unwrap();
+        assert_eq!(contents.len(), CHUNK_SIZE + 3);
+        assert_eq!(&contents.to_vec()[CHUNK_SIZE - 2..], b"\x01abcd");
+
+        // leaving a gap
+        contents.write_at(CHUNK_SIZE + 5, b"e").unwrap();
+        assert_eq!(contents.len(), CHUNK_SIZE + 6);
+        assert_eq!(&contents.to_vec()[CHUNK_SIZE + 2..], b"d\0\0e");
+        assert_eq!(
+            contents.chunks().map(|c| c.len()).collect::<Vec<_>>(),
+            vec![CHUNK_SIZE, 6]
+        );
+    }
+
+    #[test]
+    fn sparse() {
+        let mut contents = Contents::new();
+        contents.write_at(3 * CHUNK_SIZE, b"abc").unwrap();
+        assert_eq!(contents.len(), 3 * CHUNK_SIZE + 3);
+        assert!(contents.chunks[..3].iter().all(|chunk| chunk.is_empty()));
+
+        let mut buf = [1; 5];
+        assert_eq!(contents.read_at(3 * CHUNK_SIZE - 2, &mut buf), 5);
+        assert_eq!(&buf, b"\0\0abc");
+
+        let bytes = contents.to_vec();
+        assert_eq!(bytes.len(), contents.len());
+        assert!(bytes[..3 * CHUNK_SIZE].iter().all(|byte| *byte == 0));
+
+        assert_eq!(
+            contents.write_at(MAX_LEN as usize, b"a").unwrap_err(),
+            Error::Io(io::Error::from_raw_os_error(libc::EFBIG))
+        );
+        assert_eq!(contents.len(), 3 * CHUNK_SIZE + 3);
+    }
+
+    #[test]
+    fn set_len() {
+        let mut contents = Contents::new();
+        contents.append(&vec![1; CHUNK_SIZE + 4]);
+
+        contents.set_len(CHUNK_SIZE as u64 + 2).unwrap();
+        assert_eq!(
+            contents.chunks().map(|c| c.len()).collect::<Vec<_>>(),
+            vec![CHUNK_SIZE, 2]
+        );
+
+        // truncated data doesn't come back
+        contents.set_len(2 * CHUNK_SIZE as u64 + 1).unwrap();
+        assert_eq!(contents.len(), 2 * CHUNK_SIZE + 1);
+        assert_eq!(contents.chunks[1].len(), 2);
+        assert_eq!(
+            &contents.to_vec()[CHUNK_SIZE..CHUNK_SIZE + 4],
+            b"\x01\x01\0\0"
+        );
+
+        contents.set_len(1).unwrap();
+        assert_eq!(contents.to_vec(), b"\x01");
+
+        assert_eq!(
+            contents.set_len(MAX_LEN + 1).unwrap_err(),
+            Error::Io(io::Error::from_raw_os_error(libc::EFBIG))
+        );
+        assert_eq!(contents.len(), 1);
+    }
+
+    #[test]
+    fn load() -> Result<()> {
+        let mut host_file = tempfile::NamedTempFile::new()?;
+        host_file.write_all(b"Hello world!")?;
+
+        let mut contents = Contents::from_host(host_file.path())?;
+        assert!(!contents.is_loaded());
+        assert_eq!(contents.len(), 12);
+
+        contents.load()?;
+        assert!(contents.is_loaded());
+        assert_eq!(contents.to_vec(), b"Hello world!");
+
+        Ok(())
+    }
+
+    #[test]
+    fn write_to() -> Result<()> {
+        let mut host_file = tempfile::NamedTempFile::new()?;
+        host_file.write_all(b"Hello world!")?;
+
+        let contents = Contents::from_host(host_file.path())?;
+        let mut buf = Vec::new();
+        assert_eq!(contents.write_to(&mut buf)?, 12);
+        assert_eq!(buf, b"Hello world!");
+        assert!(!contents.is_loaded());
+
+        host_file.write_all(b" Changed!")?;
+        let path = host_file.path().to_string_lossy().to_string();
+        assert_eq!(
+            contents.write_to(&mut Vec::new()).unwrap_err(),
+            Error::HostFileChanged(path)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn load_changed() -> Result<()> {
+        let mut host_file = tempfile::NamedTempFile::new()?;
+        host_file.write_all(b"Hello world!")?;
+
+        let mut contents = Contents::from_host(host_file.path())?;
+        host_file.write_all(b" Changed!")?;
+
+        let path = host_file.path().to_string_lossy().to_string();
+        assert_eq!(contents.load().unwrap_err(), Error::HostFileChanged(path));
+        assert!(!contents.is_loaded());
+
+        Ok(())
+    }
+
+    #[test]
+    fn load_replaced() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("file");
+        let other = dir.path().join("other");
+        fs::write(&path, b"Hello world!")?;
+        fs::write(&other, b"Hello there!")?;
+        let name = path.to_string_lossy().to_string();
+
+        // by another file of the same length
+        let mut contents = Contents::from_host(&path)?;
+        let copy = dir.path().join("copy");
+        fs::copy(&other, &copy)?;
+        fs::rename(&copy, &path)?;
+        assert_eq!(
+            contents.load().unwrap_err(),
+            Error::HostFileChanged(name.clone())
+        );
+
+        // by a symlink to it
+        let mut contents = Contents::from_host(&other)?;
+        let mut linked = Contents::from_host(&path)?;
+        fs::remove_file(&path)?;
+        std::os::unix::fs::symlink(&other, &path)?;
+        assert_eq!(
+            linked.load().unwrap_err(),
+            Error::HostFileChanged(name.clone())
+        );
+        contents.load()?;
+        assert_eq!(contents.to_vec(), b"Hello there!");
+
+        // or by a FIFO, which must not block
+        let mut contents = Contents::from_host(&other)?;
+        fs::remove_file(&other)?;
+        let c_path = std::ffi::CString::new(other.to_string_lossy().as_bytes()).unwrap();
+        assert_eq!(unsafe { libc::mkfifo(c_path.as_ptr(), 0o600) }, 0);
+        let other_name = other.to_string_lossy().to_string();
+        assert_eq!(
+            contents.load().unwrap_err(),
+            Error::HostFileChanged(other_name)
+        );
+
+        Ok(())
+    }
+}
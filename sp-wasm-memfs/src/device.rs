@@ -0,0 +1,56 @@
+use std::fmt;
+use std::io;
+
+/// Special file with custom read and write behaviour, such as `/dev/null`.
+///
+/// Devices are not seekable: every read and write is passed straight to
+/// the device regardless of the position in the opened file.
+pub trait Device: fmt::Debug + Send {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize>;
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize>;
+}
+
+/// `/dev/null`: reads are always at EOF, writes are discarded.
+#[derive(Debug, Default)]
+pub struct Null;
+
+impl Device for Null {
+    fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+        Ok(0)
+    }
+
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        Ok(buf.len())
+    }
+}
+
+/// `/dev/zero`: reads return zeros, writes are discarded.
+#[derive(Debug, Default)]
+pub struct Zero;
+
+impl Device for Zero {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        for byte in buf.iter_mut() {
+            *byte = 0;
+        }
+        Ok(buf.len())
+    }
+
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        Ok(buf.len())
+    }
+}
+
+/// `/dev/full`: reads return zeros, writes fail with `ENOSPC`.
+#[derive(Debug, Default)]
+pub struct Full;
+
+impl Device for Full {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        Zero.read(buf)
+    }
+
+    fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+        Err(io::Error::from_raw_os_error(libc::ENOSPC))
+    }
+}
@@ -8,8 +8,16 @@ pub enum Error {
     NotFound(String),
     #[error("invalid path: '{0}'")]
     InvalidPath(String),
+    #[error("not a directory: '{0}'")]
+    NotADirectory(String),
     #[error("file is root")]
     IsRoot,
+    #[error("file '{0}' is a symlink")]
+    IsSymlink(String),
+    #[error("file '{0}' is not a symlink")]
+    NotASymlink(String),
+    #[error("host file '{0}' changed since it was mapped")]
+    HostFileChanged(String),
     #[error("{0}")]
     Io(#[from] io::Error),
 }
@@ -20,7 +28,13 @@ impl PartialEq for Error {
             (&Error::AlreadyExists(ref left), &Error::AlreadyExists(ref right)) => left == right,
             (&Error::NotFound(ref left), &Error::NotFound(ref right)) => left == right,
             (&Error::InvalidPath(ref left), &Error::InvalidPath(ref right)) => left == right,
+            (&Error::NotADirectory(ref left), &Error::NotADirectory(ref right)) => left == right,
             (&Error::IsRoot, &Error::IsRoot) => true,
+            (&Error::IsSymlink(ref left), &Error::IsSymlink(ref right)) => left == right,
+            (&Error::NotASymlink(ref left), &Error::NotASymlink(ref right)) => left == right,
+            (&Error::HostFileChanged(ref left), &Error::HostFileChanged(ref right)) => {
+                left == right
+            }
             (&Error::Io(ref left), &Error::Io(ref right)) => left.kind() == right.kind(),
             (_, _) => false,
         }
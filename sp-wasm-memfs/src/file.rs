@@ -1,44 +1,135 @@
+use super::backend::FsFile;
+use super::contents::Contents;
+use super::error::Error;
 use super::node::*;
-use std::io::{self, Read, Write};
-use std::sync::{Arc, Mutex};
+use super::Result;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::ops::Deref;
+use std::sync::{Arc, Mutex, MutexGuard};
 
 #[derive(Debug)]
 pub struct File {
     node: Arc<Mutex<Node>>,
-    rdr_pos: usize,
+    pos: usize,
 }
 
 impl File {
     pub(crate) fn new(node: Arc<Mutex<Node>>) -> Self {
-        Self { node, rdr_pos: 0 }
+        Self { node, pos: 0 }
     }
 
     pub fn reset(&mut self) {
-        self.rdr_pos = 0;
+        self.pos = 0;
+    }
+
+    pub fn len(&self) -> usize {
+        self.node.lock().unwrap().contents.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Borrows the contents of the file without copying them, loading
+    /// them from the host first if needed. The file is locked for as long
+    /// as the returned guard is alive.
+    pub fn contents(&self) -> Result<ContentsRef<'_>> {
+        let mut node = self.node.lock().unwrap();
+        node.contents.load()?;
+
+        Ok(ContentsRef { node })
+    }
+
+    /// Writes the contents of the file to `writer` without loading them
+    /// from the host, see `Contents::write_to`.
+    pub fn write_to(&self, writer: &mut dyn Write) -> Result<u64> {
+        self.node.lock().unwrap().contents.write_to(writer)
+    }
+}
+
+impl FsFile for File {
+    fn set_len(&mut self, len: u64) -> io::Result<()> {
+        let mut node = self.node.lock().unwrap();
+        if node.device.is_some() {
+            return Ok(());
+        }
+
+        node.contents.load().map_err(to_io_error)?;
+        node.contents.set_len(len).map_err(to_io_error)
+    }
+}
+
+pub struct ContentsRef<'a> {
+    node: MutexGuard<'a, Node>,
+}
+
+impl<'a> Deref for ContentsRef<'a> {
+    type Target = Contents;
+
+    fn deref(&self) -> &Self::Target {
+        &self.node.contents
     }
 }
 
 impl Read for File {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        if self.rdr_pos == self.node.lock().unwrap().contents.len() {
-            return Ok(0);
+        let mut node = self.node.lock().unwrap();
+        if let Some(device) = node.device.as_mut() {
+            return device.read(buf);
         }
 
-        let result = (&self.node.lock().unwrap().contents[self.rdr_pos..]).read(buf);
-        result.map(|count| {
-            self.rdr_pos += count;
-            count
-        })
+        node.contents.load().map_err(to_io_error)?;
+
+        let count = node.contents.read_at(self.pos, buf);
+        self.pos += count;
+        Ok(count)
     }
 }
 
 impl Write for File {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        self.node.lock().unwrap().contents.write(buf)
+        let mut node = self.node.lock().unwrap();
+        if let Some(device) = node.device.as_mut() {
+            return device.write(buf);
+        }
+
+        node.contents.load().map_err(to_io_error)?;
+
+        node.contents.write_at(self.pos, buf).map_err(to_io_error)?;
+        self.pos += buf.len();
+        Ok(buf.len())
     }
 
     fn flush(&mut self) -> io::Result<()> {
-        self.node.lock().unwrap().contents.flush()
+        Ok(())
+    }
+}
+
+impl Seek for File {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let (base, offset) = match pos {
+            SeekFrom::Start(pos) => (0, pos as i64),
+            SeekFrom::End(offset) => (self.len() as i64, offset),
+            SeekFrom::Current(offset) => (self.pos as i64, offset),
+        };
+
+        let pos = base + offset;
+        if pos < 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative position",
+            ));
+        }
+
+        self.pos = pos as usize;
+        Ok(pos as u64)
+    }
+}
+
+fn to_io_error(err: Error) -> io::Error {
+    match err {
+        Error::Io(err) => err,
+        err => io::Error::other(err),
     }
 }
 
@@ -49,16 +140,11 @@ mod test {
     #[test]
     fn read() {
         let mut file = File::new(new_file_node("test.txt"));
-        file.node
-            .lock()
-            .unwrap()
-            .contents
-            .write_all(b"Hello world!")
-            .unwrap();
+        file.node.lock().unwrap().contents.append(b"Hello world!");
 
         let mut contents = Vec::new();
         file.read_to_end(&mut contents).unwrap();
-        assert_eq!(file.node.lock().unwrap().contents, contents);
+        assert_eq!(file.node.lock().unwrap().contents.to_vec(), contents);
 
         // once read, need to reset to read again
         let mut contents = Vec::new();
@@ -68,7 +154,7 @@ mod test {
         file.reset();
         let mut contents = Vec::new();
         file.read_to_end(&mut contents).unwrap();
-        assert_eq!(file.node.lock().unwrap().contents, contents);
+        assert_eq!(file.node.lock().unwrap().contents.to_vec(), contents);
     }
 
     #[test]
@@ -77,15 +163,78 @@ mod test {
 
         let contents = b"Hello world!";
         file.write_all(contents).unwrap();
-        assert_eq!(file.node.lock().unwrap().contents, b"Hello world!");
+        assert_eq!(file.node.lock().unwrap().contents.to_vec(), b"Hello world!");
 
         let contents = b" This is a test...";
         file.write_all(contents).unwrap();
         assert_eq!(
-            file.node.lock().unwrap().contents,
+            file.node.lock().unwrap().contents.to_vec(),
             b"Hello world! This is a test..."
         );
 
         assert!(file.flush().is_ok());
     }
+
+    #[test]
+    fn seek() {
+        let mut file = File::new(new_file_node("test.txt"));
+        file.write_all(b"Hello world!").unwrap();
+
+        // writes overwrite in place
+        assert_eq!(file.seek(SeekFrom::Start(6)).unwrap(), 6);
+        file.write_all(b"there").unwrap();
+        assert_eq!(file.node.lock().unwrap().contents.to_vec(), b"Hello there!");
+
+        assert_eq!(file.seek(SeekFrom::End(-6)).unwrap(), 6);
+        let mut contents = String::new();
+        file.read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "there!");
+
+        assert_eq!(file.seek(SeekFrom::Current(-1)).unwrap(), 11);
+        assert!(file.seek(SeekFrom::Current(-12)).is_err());
+    }
+
+    #[test]
+    fn set_len() {
+        let mut file = File::new(new_file_node("test.txt"));
+        file.write_all(b"Hello world!").unwrap();
+
+        file.set_len(5).unwrap();
+        assert_eq!(file.node.lock().unwrap().contents.to_vec(), b"Hello");
+
+        // the position is kept, as with `std::fs::File`
+        file.write_all(b"!").unwrap();
+        assert_eq!(file.len(), 13);
+        assert_eq!(
+            file.node.lock().unwrap().contents.to_vec(),
+            b"Hello\0\0\0\0\0\0\0!"
+        );
+    }
+
+    #[test]
+    fn device() {
+        use crate::device::Full;
+
+        let mut file = File::new(new_device_node("full", Box::new(Full)));
+
+        let mut buf = [1; 4];
+        assert_eq!(file.read(&mut buf).unwrap(), 4);
+        assert_eq!(buf, [0; 4]);
+        assert_eq!(file.read(&mut buf).unwrap(), 4);
+
+        let err = file.write(b"Hello world!").unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::ENOSPC));
+    }
+
+    #[test]
+    fn contents() {
+        let mut file = File::new(new_file_node("test.txt"));
+        file.write_all(b"Hello world!").unwrap();
+
+        assert_eq!(file.len(), 12);
+        assert_eq!(
+            file.contents().unwrap().chunks().collect::<Vec<_>>(),
+            vec![&b"Hello world!"[..]]
+        );
+    }
 }
@@ -1,3 +1,6 @@
+pub mod backend;
+pub mod contents;
+pub mod device;
 pub mod error;
 pub mod file;
 pub mod memfs;
@@ -6,6 +9,9 @@ mod node;
 pub type Result<T> = std::result::Result<T, error::Error>;
 
 pub mod prelude {
-    pub use super::file::File;
+    pub use super::backend::{FileType, FsBackend, FsFile, Metadata};
+    pub use super::contents::Contents;
+    pub use super::device::Device;
+    pub use super::file::{ContentsRef, File};
     pub use super::memfs::MemFS;
 }
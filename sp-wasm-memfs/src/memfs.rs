@@ -1,13 +1,17 @@
+use super::backend::{FileType, Metadata};
+use super::contents::Contents;
+use super::device::Device;
 use super::error::*;
 use super::file::*;
 use super::node::*;
 use super::Result;
-use path_clean::PathClean;
-use std::path::{Path, PathBuf};
+use std::ffi::OsString;
+use std::path::{Component, Path, PathBuf};
 use std::sync::{Arc, Mutex};
 use tool::prelude::*;
 
-#[derive(Debug)]
+/// In-memory filesystem. Clones share the same underlying tree.
+#[derive(Debug, Clone)]
 pub struct MemFS {
     root: Arc<Mutex<Node>>,
 }
@@ -25,18 +29,12 @@ impl MemFS {
         components.next(); // skip first
         let path = components.as_path();
 
-        let name: String = match components.next() {
-            Some(component) => component
-                .as_os_str()
-                .to_str()
-                .ok_or(Error::InvalidPath(
-                    component.as_os_str().to_string_lossy().to_string(),
-                ))?
-                .to_owned(),
+        let name: OsString = match components.next() {
+            Some(component) => component.as_os_str().to_owned(),
             None => return Ok(node),
         };
 
-        if node.lock().unwrap().is_file() {
+        if !node.lock().unwrap().is_dir() {
             if node.lock().unwrap().name == name {
                 return Ok(Arc::clone(&node));
             }
@@ -46,7 +44,7 @@ impl MemFS {
             }
         }
 
-        Err(Error::NotFound(name))
+        Err(Error::NotFound(name.to_string_lossy().to_string()))
     }
 
     fn normalize_path<P>(path: P) -> Result<PathBuf>
@@ -59,10 +57,21 @@ impl MemFS {
             ));
         }
 
-        Ok(PathBuf::from(path.as_ref()).clean())
+        let mut normalized = PathBuf::from("/");
+        for component in path.as_ref().components() {
+            match component {
+                Component::Normal(name) => normalized.push(name),
+                Component::ParentDir => {
+                    normalized.pop();
+                }
+                _ => {}
+            }
+        }
+
+        Ok(normalized)
     }
 
-    fn resolve_parent<P>(path: P) -> Result<(PathBuf, String)>
+    fn resolve_parent<P>(path: P) -> Result<(PathBuf, OsString)>
     where
         P: AsRef<Path>,
     {
@@ -70,7 +79,6 @@ impl MemFS {
         let parent = path.parent().ok_or(Error::IsRoot)?;
         let filename = path
             .file_name()
-            .and_then(|s| s.to_str())
             .ok_or(Error::InvalidPath(path.to_string_lossy().to_string()))?;
 
         Ok((parent.to_owned(), filename.to_owned()))
@@ -95,6 +103,10 @@ impl MemFS {
         P: AsRef<Path>,
     {
         let walk_create = fix(|f, path: PathBuf| -> Result<Arc<Mutex<Node>>> {
+            if self.is_dir(path.as_path())? {
+                return self.walk(path, Arc::clone(&self.root));
+            }
+
             let (parent, filename) = Self::resolve_parent(path)?;
 
             let node = if self.is_dir(parent.as_path())? {
@@ -137,13 +149,178 @@ impl MemFS {
         Ok(File::new(file_node))
     }
 
+    /// Creates a file whose contents are read from `host_path` only once
+    /// the file is first accessed.
+    pub fn map_host_file<P1, P2>(&self, path: P1, host_path: P2) -> Result<()>
+    where
+        P1: AsRef<Path>,
+        P2: AsRef<Path>,
+    {
+        let (parent, filename) = Self::resolve_parent(path)?;
+        let node = self.walk(parent, Arc::clone(&self.root))?;
+        let file_node = new_file_node(filename.clone());
+        file_node.lock().unwrap().contents = Contents::from_host(host_path)?;
+        node.lock().unwrap().children.insert(filename, file_node);
+
+        Ok(())
+    }
+
+    pub fn create_device<P, D>(&self, path: P, device: D) -> Result<()>
+    where
+        P: AsRef<Path>,
+        D: Device + 'static,
+    {
+        let (parent, filename) = Self::resolve_parent(path)?;
+        let node = self.walk(parent, Arc::clone(&self.root))?;
+        let device_node = new_device_node(filename.clone(), Box::new(device));
+        node.lock().unwrap().children.insert(filename, device_node);
+
+        Ok(())
+    }
+
+    /// Creates a symlink, which is never followed by `MemFS` itself.
+    pub fn create_symlink<P1, P2>(&self, path: P1, target: P2) -> Result<()>
+    where
+        P1: AsRef<Path>,
+        P2: AsRef<Path>,
+    {
+        let (parent, filename) = Self::resolve_parent(path)?;
+        let node = self.walk(parent, Arc::clone(&self.root))?;
+        let link_node = new_symlink_node(filename.clone(), target.as_ref().to_owned());
+        node.lock().unwrap().children.insert(filename, link_node);
+
+        Ok(())
+    }
+
+    pub fn read_link<P>(&self, path: P) -> Result<PathBuf>
+    where
+        P: AsRef<Path>,
+    {
+        let (parent, filename) = Self::resolve_parent(path)?;
+        let node = self.walk(parent.join(&filename), Arc::clone(&self.root))?;
+        let node = node.lock().unwrap();
+
+        node.target
+            .clone()
+            .ok_or_else(|| Error::NotASymlink(filename.to_string_lossy().to_string()))
+    }
+
     pub fn open_file<P>(&self, path: P) -> Result<File>
     where
         P: AsRef<Path>,
     {
         let (parent, filename) = Self::resolve_parent(path)?;
-        self.walk(parent.join(filename), Arc::clone(&self.root))
-            .map(|node| File::new(node))
+        let node = self.walk(parent.join(&filename), Arc::clone(&self.root))?;
+        if node.lock().unwrap().is_symlink() {
+            return Err(Error::IsSymlink(filename.to_string_lossy().to_string()));
+        }
+
+        Ok(File::new(node))
+    }
+
+    pub fn read_dir<P>(&self, path: P) -> Result<Vec<OsString>>
+    where
+        P: AsRef<Path>,
+    {
+        let path = Self::normalize_path(path)?;
+        let node = self.walk(path.as_path(), Arc::clone(&self.root))?;
+        let node = node.lock().unwrap();
+
+        if !node.is_dir() {
+            return Err(Error::NotADirectory(path.to_string_lossy().to_string()));
+        }
+
+        Ok(node.children.keys().cloned().collect())
+    }
+
+    /// Moves a file or a directory, replacing an existing file at `to`.
+    pub fn rename<P1, P2>(&self, from: P1, to: P2) -> Result<()>
+    where
+        P1: AsRef<Path>,
+        P2: AsRef<Path>,
+    {
+        let (from_parent, from_name) = Self::resolve_parent(from)?;
+        let (to_parent, to_name) = Self::resolve_parent(to)?;
+
+        let from = from_parent.join(&from_name);
+        let to = to_parent.join(&to_name);
+        if from == to {
+            return Ok(());
+        }
+        if to.starts_with(&from) {
+            return Err(Error::InvalidPath(to.to_string_lossy().to_string()));
+        }
+
+        let from_dir = self.walk(from_parent, Arc::clone(&self.root))?;
+        let to_dir = self.walk(to_parent.as_path(), Arc::clone(&self.root))?;
+
+        {
+            let to_dir = to_dir.lock().unwrap();
+            if !to_dir.is_dir() {
+                return Err(Error::NotADirectory(
+                    to_parent.to_string_lossy().to_string(),
+                ));
+            }
+            if let Some(node) = to_dir.children.get(&to_name) {
+                if node.lock().unwrap().is_dir() {
+                    return Err(Error::AlreadyExists(to_name.to_string_lossy().to_string()));
+                }
+            }
+        }
+
+        let node = {
+            let mut from_dir = from_dir.lock().unwrap();
+            if !from_dir.is_dir() {
+                None
+            } else {
+                from_dir.children.remove(&from_name)
+            }
+        };
+        let node = node.ok_or_else(|| Error::NotFound(from_name.to_string_lossy().to_string()))?;
+
+        node.lock().unwrap().name = to_name.clone();
+        to_dir.lock().unwrap().children.insert(to_name, node);
+
+        Ok(())
+    }
+
+    /// Removes a file, or a directory together with all of its contents.
+    pub fn remove<P>(&self, path: P) -> Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        let (parent, filename) = Self::resolve_parent(path)?;
+        let node = self.walk(parent, Arc::clone(&self.root))?;
+        let mut node = node.lock().unwrap();
+
+        if !node.is_dir() || node.children.remove(&filename).is_none() {
+            return Err(Error::NotFound(filename.to_string_lossy().to_string()));
+        }
+
+        Ok(())
+    }
+
+    pub fn metadata<P>(&self, path: P) -> Result<Metadata>
+    where
+        P: AsRef<Path>,
+    {
+        let path = Self::normalize_path(path)?;
+        let node = self.walk(path, Arc::clone(&self.root))?;
+        let node = node.lock().unwrap();
+
+        let len = match node.file_type {
+            FileType::File => node.contents.len() as u64,
+            FileType::Symlink => node
+                .target
+                .as_ref()
+                .map_or(0, |target| target.as_os_str().len() as u64),
+            _ => 0,
+        };
+
+        Ok(Metadata {
+            file_type: node.file_type,
+            len,
+        })
     }
 
     pub fn is_dir<P>(&self, path: P) -> Result<bool>
@@ -157,6 +334,25 @@ impl MemFS {
         }
     }
 
+    pub fn is_device<P>(&self, path: P) -> Result<bool>
+    where
+        P: AsRef<Path>,
+    {
+        let path = Self::normalize_path(path)?;
+        match self.walk(path, Arc::clone(&self.root)) {
+            Ok(node) => Ok(node.lock().unwrap().is_device()),
+            _ => Ok(false),
+        }
+    }
+
+    pub fn exists<P>(&self, path: P) -> Result<bool>
+    where
+        P: AsRef<Path>,
+    {
+        let path = Self::normalize_path(path)?;
+        Ok(self.walk(path, Arc::clone(&self.root)).is_ok())
+    }
+
     pub fn is_file<P>(&self, path: P) -> Result<bool>
     where
         P: AsRef<Path>,
@@ -180,6 +376,7 @@ impl Default for MemFS {
 #[cfg(test)]
 mod test {
     use super::*;
+    use std::io::Write;
     use std::path::PathBuf;
 
     #[test]
@@ -211,11 +408,11 @@ mod test {
 
         assert_eq!(
             MemFS::resolve_parent("/tmp")?,
-            (PathBuf::from("/"), "tmp".to_owned())
+            (PathBuf::from("/"), OsString::from("tmp"))
         );
         assert_eq!(
             MemFS::resolve_parent("/tmp/a/b/c")?,
-            (PathBuf::from("/tmp/a/b"), "c".to_owned())
+            (PathBuf::from("/tmp/a/b"), OsString::from("c"))
         );
 
         Ok(())
@@ -259,6 +456,10 @@ mod test {
 
         assert!(fs.create_dir_all("/").is_ok());
 
+        fs.create_file("/tmp/a/b/c/d")?;
+        fs.create_dir_all("/tmp/a/b")?;
+        assert!(fs.is_file("/tmp/a/b/c/d")?);
+
         Ok(())
     }
 
@@ -288,6 +489,31 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn create_device() -> Result<()> {
+        use crate::device::Zero;
+        use std::io::Read;
+
+        let fs = MemFS::new();
+        fs.create_dir("/dev")?;
+        fs.create_device("/dev/zero", Zero)?;
+
+        assert!(fs.is_device("/dev/zero")?);
+        assert!(!fs.is_file("/dev/zero")?);
+        assert!(fs.exists("/dev/zero")?);
+
+        let mut buf = [1; 4];
+        fs.open_file("/dev/zero")?.read_exact(&mut buf)?;
+        assert_eq!(buf, [0; 4]);
+
+        assert_eq!(
+            fs.create_device("/d/zero", Zero).unwrap_err(),
+            Error::NotFound("d".to_owned())
+        );
+
+        Ok(())
+    }
+
     #[test]
     fn open_file() -> Result<()> {
         let fs = MemFS::new();
@@ -308,4 +534,180 @@ mod test {
 
         Ok(())
     }
+
+    #[cfg(unix)]
+    #[test]
+    fn non_utf8_names() -> Result<()> {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let fs = MemFS::new();
+        let dir = Path::new("/").join(OsStr::from_bytes(b"caf\xe9"));
+        fs.create_dir(&dir)?;
+        fs.create_file(dir.join(OsStr::from_bytes(b"\xff.txt")))?;
+
+        assert!(fs.is_dir(&dir)?);
+        assert!(fs.is_file(dir.join(OsStr::from_bytes(b"\xff.txt")))?);
+        assert!(!fs.is_file(dir.join("\u{fffd}.txt"))?);
+        assert_eq!(
+            fs.read_dir(&dir)?,
+            vec![OsStr::from_bytes(b"\xff.txt").to_owned()]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn read_dir() -> Result<()> {
+        let fs = MemFS::new();
+        fs.create_dir_all("/tmp/b")?;
+        fs.create_file("/tmp/a")?;
+
+        assert_eq!(
+            fs.read_dir("/tmp")?,
+            vec![OsString::from("a"), OsString::from("b")]
+        );
+        assert_eq!(fs.read_dir("/")?, vec![OsString::from("tmp")]);
+
+        assert_eq!(
+            fs.read_dir("/tmp/a").unwrap_err(),
+            Error::NotADirectory("/tmp/a".to_owned())
+        );
+        assert_eq!(
+            fs.read_dir("/c").unwrap_err(),
+            Error::NotFound("c".to_owned())
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn remove() -> Result<()> {
+        let fs = MemFS::new();
+        fs.create_dir_all("/tmp/a")?;
+        fs.create_file("/tmp/a/b")?;
+        fs.create_file("/c")?;
+
+        fs.remove("/c")?;
+        assert!(!fs.is_file("/c")?);
+
+        fs.remove("/tmp")?;
+        assert!(!fs.is_dir("/tmp")?);
+        assert!(!fs.is_file("/tmp/a/b")?);
+
+        assert_eq!(fs.remove("/").unwrap_err(), Error::IsRoot);
+        assert_eq!(
+            fs.remove("/d").unwrap_err(),
+            Error::NotFound("d".to_owned())
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn rename() -> Result<()> {
+        let fs = MemFS::new();
+        fs.create_dir_all("/tmp/a")?;
+        fs.create_file("/tmp/a/b")?;
+        fs.create_file("/c")?;
+
+        fs.rename("/tmp/a/b", "/tmp/b")?;
+        assert!(fs.is_file("/tmp/b")?);
+        assert!(!fs.exists("/tmp/a/b")?);
+        assert_eq!(
+            fs.read_dir("/tmp")?,
+            vec![OsString::from("a"), OsString::from("b")]
+        );
+
+        fs.rename("/tmp", "/d")?;
+        assert!(fs.is_file("/d/b")?);
+
+        fs.rename("/c", "/d/b")?;
+        assert!(!fs.exists("/c")?);
+
+        assert_eq!(
+            fs.rename("/d", "/d/a/e").unwrap_err(),
+            Error::InvalidPath("/d/a/e".to_owned())
+        );
+        assert_eq!(
+            fs.rename("/d/b", "/d/a").unwrap_err(),
+            Error::AlreadyExists("a".to_owned())
+        );
+        assert_eq!(
+            fs.rename("/e", "/f").unwrap_err(),
+            Error::NotFound("e".to_owned())
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn symlink() -> Result<()> {
+        let fs = MemFS::new();
+        fs.create_dir("/tmp")?;
+        fs.create_file("/tmp/a")?;
+        fs.create_symlink("/tmp/b", "a")?;
+        fs.create_symlink("/c", "/tmp")?;
+
+        assert_eq!(fs.read_link("/tmp/b")?, PathBuf::from("a"));
+        assert!(fs.metadata("/tmp/b")?.is_symlink());
+        assert!(!fs.is_file("/tmp/b")?);
+        assert_eq!(
+            fs.open_file("/tmp/b").unwrap_err(),
+            Error::IsSymlink("b".to_owned())
+        );
+        assert_eq!(
+            fs.read_link("/tmp/a").unwrap_err(),
+            Error::NotASymlink("a".to_owned())
+        );
+
+        // never followed
+        assert!(!fs.exists("/c/a")?);
+        fs.remove("/c")?;
+        assert!(fs.is_file("/tmp/a")?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn metadata() -> Result<()> {
+        let fs = MemFS::new();
+        fs.create_dir("/tmp")?;
+        fs.create_file("/tmp/a")?.write_all(b"Hello")?;
+
+        assert!(fs.metadata("/tmp")?.is_dir());
+        assert_eq!(
+            fs.metadata("/tmp/a")?,
+            Metadata {
+                file_type: FileType::File,
+                len: 5,
+            }
+        );
+        assert_eq!(
+            fs.metadata("/tmp/b").unwrap_err(),
+            Error::NotFound("b".to_owned())
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn map_host_file() -> Result<()> {
+        use std::io::{Read, Write};
+
+        let mut host_file = tempfile::NamedTempFile::new()?;
+        host_file.write_all(b"Hello world!")?;
+
+        let fs = MemFS::new();
+        fs.map_host_file("/test", host_file.path())?;
+        assert!(fs.is_file("/test")?);
+
+        let mut contents = Vec::new();
+        fs.open_file("/test")?.read_to_end(&mut contents)?;
+        assert_eq!(contents, b"Hello world!");
+
+        assert!(fs.map_host_file("/a", "/this/does/not/exist").is_err());
+
+        Ok(())
+    }
 }
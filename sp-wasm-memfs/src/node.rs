@@ -1,30 +1,33 @@
+use super::backend::FileType;
+use super::contents::Contents;
+use super::device::Device;
 use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
 
-#[derive(Debug, PartialEq)]
-pub(crate) enum FileType {
-    Dir,
-    File,
-}
-
 #[derive(Debug)]
 pub(crate) struct Node {
-    pub name: String,
+    pub name: OsString,
     pub file_type: FileType,
-    pub children: BTreeMap<String, Arc<Mutex<Node>>>,
-    pub contents: Vec<u8>,
+    pub children: BTreeMap<OsString, Arc<Mutex<Node>>>,
+    pub contents: Contents,
+    pub device: Option<Box<dyn Device>>,
+    pub target: Option<PathBuf>,
 }
 
 impl Node {
     pub fn new<S>(name: S, file_type: FileType) -> Self
     where
-        S: Into<String>,
+        S: Into<OsString>,
     {
         Self {
             name: name.into(),
             file_type,
             children: BTreeMap::new(),
-            contents: Vec::new(),
+            contents: Contents::new(),
+            device: None,
+            target: None,
         }
     }
 
@@ -35,18 +38,44 @@ impl Node {
     pub fn is_dir(&self) -> bool {
         self.file_type == FileType::Dir
     }
+
+    pub fn is_device(&self) -> bool {
+        self.file_type == FileType::Device
+    }
+
+    pub fn is_symlink(&self) -> bool {
+        self.file_type == FileType::Symlink
+    }
 }
 
 pub(crate) fn new_file_node<S>(name: S) -> Arc<Mutex<Node>>
 where
-    S: Into<String>,
+    S: Into<OsString>,
 {
     Arc::new(Mutex::new(Node::new(name, FileType::File)))
 }
 
 pub(crate) fn new_dir_node<S>(name: S) -> Arc<Mutex<Node>>
 where
-    S: Into<String>,
+    S: Into<OsString>,
 {
     Arc::new(Mutex::new(Node::new(name, FileType::Dir)))
 }
+
+pub(crate) fn new_device_node<S>(name: S, device: Box<dyn Device>) -> Arc<Mutex<Node>>
+where
+    S: Into<OsString>,
+{
+    let mut node = Node::new(name, FileType::Device);
+    node.device = Some(device);
+    Arc::new(Mutex::new(node))
+}
+
+pub(crate) fn new_symlink_node<S>(name: S, target: PathBuf) -> Arc<Mutex<Node>>
+where
+    S: Into<OsString>,
+{
+    let mut node = Node::new(name, FileType::Symlink);
+    node.target = Some(target);
+    Arc::new(Mutex::new(node))
+}
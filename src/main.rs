@@ -1,25 +1,151 @@
 use sp_wasm_engine::prelude::*;
+use sp_wasm_engine::sandbox;
 use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
 use structopt::StructOpt;
 
+/// Host dir mounted in the guest, given as
+/// `host_path:/guest/path[:ro][:passthrough]`.
+#[derive(Debug)]
+struct Mount {
+    host_path: PathBuf,
+    guest_path: PathBuf,
+    read_only: bool,
+    passthrough: bool,
+}
+
+impl FromStr for Mount {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (spec, passthrough) = if s.ends_with(":passthrough") {
+            (&s[..s.len() - 12], true)
+        } else {
+            (s, false)
+        };
+        let (spec, read_only) = if spec.ends_with(":ro") {
+            (&spec[..spec.len() - 3], true)
+        } else {
+            (spec, false)
+        };
+
+        // host paths may contain ':' themselves
+        let mut parts = spec.rsplitn(2, ':');
+        let guest_path = parts.next().unwrap_or_default();
+        let host_path = parts.next().unwrap_or_default();
+
+        if host_path.is_empty() || !guest_path.starts_with('/') {
+            return Err(format!(
+                "invalid mount '{}', expected host_path:/guest/path[:ro][:passthrough]",
+                s
+            ));
+        }
+
+        Ok(Self {
+            host_path: PathBuf::from(host_path),
+            guest_path: PathBuf::from(guest_path),
+            read_only,
+            passthrough,
+        })
+    }
+}
+
 /// Standalone SpiderMonkey instance that can be used to run Emscripten
 /// generated Wasm according to the Golem calling convention.
 #[derive(StructOpt, Debug)]
 #[structopt(name = "wasm-sandbox", version = env!("CARGO_PKG_VERSION"))]
 struct Opts {
-    /// Path to input dir
+    /// Path to input dir mapped onto `/`
     #[structopt(short = "I", long = "input_dir", parse(from_os_str))]
-    input_dir: PathBuf,
+    input_dir: Option<PathBuf>,
+    /// Path to a .tar, .tar.gz or .zip archive unpacked onto `/`
+    #[structopt(long = "input-archive", parse(from_os_str))]
+    input_archive: Option<PathBuf>,
+    /// Path to a file fed to the guest's standard input
+    #[structopt(long = "stdin", parse(from_os_str))]
+    stdin: Option<PathBuf>,
+    /// Environment variables of the guest, as `NAME=VALUE`
+    #[structopt(
+        long = "env",
+        number_of_values = 1,
+        parse(try_from_str = sandbox::env::parse_var)
+    )]
+    env: Vec<(String, String)>,
+    /// Path to a file of `NAME=VALUE` lines to add to the guest's
+    /// environment, before any `--env`
+    #[structopt(long = "env-file", parse(from_os_str))]
+    env_file: Option<PathBuf>,
+    /// Working dir of the guest, created if it doesn't exist; relative
+    /// output files are taken from it
+    #[structopt(long = "workdir", parse(from_os_str))]
+    workdir: Option<PathBuf>,
+    /// Maximum run time in seconds
+    #[structopt(long = "timeout")]
+    timeout: Option<u64>,
+    /// Where the guest's standard output goes: `host`, or a path relative
+    /// to the output dir
+    #[structopt(long = "stdout", default_value = "host")]
+    stdout: Sink,
+    /// Where the guest's standard error goes: `host`, or a path relative
+    /// to the output dir
+    #[structopt(long = "stderr", default_value = "host")]
+    stderr: Sink,
+    /// Maximum size in bytes of the guest's stdout and stderr each
+    #[structopt(long = "max_stdio_size")]
+    max_stdio_size: Option<u64>,
+    /// Host dirs to mount in the guest, as
+    /// `host_path:/guest/path[:ro][:passthrough]`
+    #[structopt(long = "mount", number_of_values = 1)]
+    mounts: Vec<Mount>,
+    /// What to do with symlinks in inputs: reject, skip, follow_within_root
+    /// or preserve_as_link
+    #[structopt(long = "symlinks", default_value = "reject")]
+    symlinks: SymlinkPolicy,
+    /// What to do with FIFOs, sockets and devices in inputs: reject or skip
+    #[structopt(long = "special_files", default_value = "reject")]
+    special_files: SpecialFilePolicy,
+    /// Maximum total size of input files in bytes
+    #[structopt(long = "max_input_size")]
+    max_input_size: Option<u64>,
+    /// Maximum number of input files
+    #[structopt(long = "max_input_files")]
+    max_input_files: Option<u64>,
+    /// Maximum nesting depth of input dirs
+    #[structopt(long = "max_input_depth")]
+    max_input_depth: Option<u64>,
     /// Path to output dir
-    #[structopt(short = "O", long = "output_dir", parse(from_os_str))]
-    output_dir: PathBuf,
+    #[structopt(
+        short = "O",
+        long = "output_dir",
+        parse(from_os_str),
+        required_unless = "output_archive"
+    )]
+    output_dir: Option<PathBuf>,
+    /// Path to a .tar or .tar.gz archive to pack the output files into
+    /// instead, or all files changed by the run if no output files are given
+    #[structopt(long = "output-archive", parse(from_os_str))]
+    output_archive: Option<PathBuf>,
+    /// Path to write a JSON manifest of the run to, listing the sizes and
+    /// SHA-256 hashes of the outputs, the input tree digest, the args and
+    /// the hashes of the Wasm binary and JS glue code
+    #[structopt(long = "manifest", parse(from_os_str))]
+    manifest: Option<PathBuf>,
+    /// Replace existing output files instead of failing
+    #[structopt(long = "overwrite")]
+    overwrite: bool,
+    /// Let the guest work on the virtual filesystem directly instead of on
+    /// copies of the inputs
+    #[structopt(long = "spfs")]
+    spfs: bool,
     /// Path to Emscripten JavaScript glue code
     #[structopt(short = "j", long = "wasm_js", parse(from_os_str))]
     wasm_js: PathBuf,
     /// Path to Emscripten Wasm binary
     #[structopt(short = "w", long = "wasm_bin", parse(from_os_str))]
     wasm_bin: PathBuf,
-    /// Paths to expected output files
+    /// Paths to expected output files, with a trailing `?` marking optional
+    /// ones
     #[structopt(
         short = "o",
         long = "output_file",
@@ -43,9 +169,78 @@ fn main() {
 
     Sandbox::new(&engine)
         .and_then(|sandbox| sandbox.set_exec_args(opts.args.iter()))
-        .and_then(|sandbox| sandbox.load_input_files(&opts.input_dir))
+        .and_then(|sandbox| sandbox.set_overwrite(opts.overwrite))
+        .and_then(|sandbox| sandbox.set_spfs(opts.spfs))
+        .and_then(|sandbox| match opts.stdin {
+            Some(ref stdin) => sandbox.set_stdin(stdin.as_path()),
+            None => Ok(sandbox),
+        })
+        .and_then(|sandbox| match opts.env_file {
+            Some(ref env_file) => {
+                sandbox::env::read_env_file(env_file).and_then(|vars| sandbox.set_env(vars))
+            }
+            None => Ok(sandbox),
+        })
+        .and_then(|sandbox| sandbox.set_env(opts.env.iter().cloned()))
+        .and_then(|sandbox| match opts.workdir {
+            Some(ref workdir) => sandbox.set_cwd(workdir),
+            None => Ok(sandbox),
+        })
+        .and_then(|sandbox| match opts.timeout {
+            Some(secs) => sandbox.set_timeout(Duration::from_secs(secs)),
+            None => Ok(sandbox),
+        })
+        .and_then(|sandbox| sandbox.set_stdout(opts.stdout.clone()))
+        .and_then(|sandbox| sandbox.set_stderr(opts.stderr.clone()))
+        .and_then(|sandbox| match opts.max_stdio_size {
+            Some(limit) => sandbox.set_max_stdio_size(limit),
+            None => Ok(sandbox),
+        })
+        .and_then(|sandbox| match opts.manifest {
+            Some(ref manifest) => sandbox.set_manifest_path(manifest),
+            None => Ok(sandbox),
+        })
+        .and_then(|sandbox| {
+            sandbox.set_map_options(MapOptions {
+                symlinks: opts.symlinks,
+                special_files: opts.special_files,
+                limits: Limits {
+                    max_bytes: opts.max_input_size,
+                    max_files: opts.max_input_files,
+                    max_depth: opts.max_input_depth,
+                },
+                ..MapOptions::default()
+            })
+        })
+        .and_then(|sandbox| match opts.input_dir {
+            Some(ref input_dir) => sandbox.load_input_files(input_dir),
+            None => Ok(sandbox),
+        })
+        .and_then(|sandbox| match opts.input_archive {
+            Some(ref input_archive) => sandbox.load_input_archive(input_archive),
+            None => Ok(sandbox),
+        })
+        .and_then(|sandbox| {
+            opts.mounts.iter().try_fold(sandbox, |sandbox, mount| {
+                sandbox.mount(
+                    &mount.host_path,
+                    &mount.guest_path,
+                    mount.read_only,
+                    mount.passthrough,
+                )
+            })
+        })
         .and_then(|sandbox| sandbox.run(&opts.wasm_js, &opts.wasm_bin))
-        .and_then(|sandbox| sandbox.save_output_files(&opts.output_dir, opts.output_files.iter()))
+        .and_then(|sandbox| match (&opts.output_archive, &opts.output_dir) {
+            (Some(output_archive), _) => {
+                sandbox.save_outputs_to_archive(output_archive, opts.output_files.iter())
+            }
+            (None, Some(output_dir)) => {
+                sandbox.save_output_files(output_dir, opts.output_files.iter())
+            }
+            // enforced by `required_unless`
+            (None, None) => unreachable!(),
+        })
         .unwrap_or_else(|err| {
             eprintln!("{}", err);
             std::process::exit(1)
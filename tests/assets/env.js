@@ -0,0 +1,96 @@
+// Stand-in for the glue Emscripten 1.38 emits for a program printing
+// getenv("NAME") from a global constructor, then getenv("NAME") and
+// getenv("USER") from main, for lack of a toolchain to build one.
+// The environment is built from ENV into the guest's memory at init, as
+// `___buildEnvironment(__get_environ())`, before any global constructor.
+var Module = typeof Module !== "undefined" ? Module : {};
+var FS = {
+    mkdirTree: function(path) {},
+    chdir: function(path) {},
+    unlink: function(path) {},
+    createDevice: function(parent, name, input, output) {}
+};
+var out = Module["print"] || print;
+var ENV = {};
+var HEAP32 = [];
+// strings of each `environ` array, by its address
+var STRINGS = {};
+var DYNAMICTOP = 1024;
+var environ = 8;
+
+function getMemory(size) {
+    var ptr = DYNAMICTOP;
+    DYNAMICTOP += size;
+    return ptr;
+}
+
+function __get_environ() {
+    return environ;
+}
+
+function ___buildEnvironment(environ) {
+    var MAX_ENV_VALUES = 64;
+    var TOTAL_ENV_SIZE = 1024;
+    var poolPtr;
+    var envPtr;
+    if (!___buildEnvironment.called) {
+        ___buildEnvironment.called = true;
+        ENV["USER"] = ENV["LOGNAME"] = "web_user";
+        ENV["PATH"] = "/";
+        ENV["PWD"] = "/";
+        ENV["HOME"] = "/home/web_user";
+        ENV["LANG"] = "C.UTF-8";
+        ENV["_"] = Module["thisProgram"];
+        poolPtr = getMemory(TOTAL_ENV_SIZE);
+        envPtr = getMemory(MAX_ENV_VALUES * 4);
+        HEAP32[envPtr >> 2] = poolPtr;
+        HEAP32[environ >> 2] = envPtr;
+    } else {
+        envPtr = HEAP32[environ >> 2];
+        poolPtr = HEAP32[envPtr >> 2];
+    }
+    var strings = [];
+    var totalSize = 0;
+    for (var key in ENV) {
+        if (typeof ENV[key] === "string") {
+            var line = key + "=" + ENV[key];
+            strings.push(line);
+            totalSize += line.length;
+        }
+    }
+    if (totalSize > TOTAL_ENV_SIZE) {
+        throw new Error("Environment size exceeded TOTAL_ENV_SIZE!");
+    }
+    STRINGS[envPtr] = strings;
+}
+
+function _getenv(name) {
+    var strings = STRINGS[HEAP32[__get_environ() >> 2]];
+    for (var i = 0; i < strings.length; i++) {
+        if (strings[i].indexOf(name + "=") === 0) {
+            // UTF8ToString
+            return decodeURIComponent(escape(strings[i].slice(name.length + 1)));
+        }
+    }
+    return null;
+}
+
+function run() {
+    if (typeof Module["preRun"] === "function") {
+        Module["preRun"] = [Module["preRun"]];
+    }
+    while (Module["preRun"].length > 0) {
+        Module["preRun"].shift()();
+    }
+    // __ATINIT__, global constructors come after the environment
+    ___buildEnvironment(__get_environ());
+    out("static NAME=" + _getenv("NAME"));
+    if (Module["onRuntimeInitialized"]) {
+        Module["onRuntimeInitialized"]();
+    }
+    // main
+    out("NAME=" + _getenv("NAME"));
+    out("USER=" + _getenv("USER"));
+}
+
+run();
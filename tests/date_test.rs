@@ -1,6 +1,7 @@
 use sp_wasm_engine::prelude::*;
 
 #[test]
+#[allow(deprecated)]
 fn date() {
     let engine = Engine::new().unwrap();
     let runtime = Runtime::new(&engine).unwrap();
@@ -0,0 +1,97 @@
+mod common;
+
+use common::*;
+use sp_wasm_engine::sandbox::env;
+use sp_wasm_engine::Error;
+use std::collections::BTreeMap;
+use std::fs;
+
+fn env_parse_var_impl() -> Result<(), String> {
+    assert_eq!(
+        env::parse_var("GREETING=a=b c").map_err(|err| err.to_string())?,
+        ("GREETING".to_owned(), "a=b c".to_owned())
+    );
+    assert_eq!(
+        env::parse_var("EMPTY=").map_err(|err| err.to_string())?,
+        ("EMPTY".to_owned(), "".to_owned())
+    );
+
+    for invalid in &["NO_VALUE", "=value", "NUL=a\0b"] {
+        match env::parse_var(invalid) {
+            Err(Error::InvalidEnv(_)) => {}
+            _ => return Err(format!("expected {:?} to be rejected", invalid)),
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn env_parse_var() {
+    if let Err(e) = env_parse_var_impl() {
+        eprintln!("unexpected error occurred: {}", e)
+    }
+}
+
+fn env_read_env_file_impl() -> Result<(), String> {
+    let workspace = create_workspace()?;
+    let env_file = workspace.path().join("env");
+    fs::write(&env_file, "# comment\nA=1\n\n  \nB=\"quoted\"\n").map_err(|err| err.to_string())?;
+
+    assert_eq!(
+        env::read_env_file(&env_file).map_err(|err| err.to_string())?,
+        vec![
+            ("A".to_owned(), "1".to_owned()),
+            ("B".to_owned(), "\"quoted\"".to_owned())
+        ]
+    );
+
+    fs::write(&env_file, "A=1\nB\n").map_err(|err| err.to_string())?;
+    assert!(env::read_env_file(&env_file).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn env_read_env_file() {
+    if let Err(e) = env_read_env_file_impl() {
+        eprintln!("unexpected error occurred: {}", e)
+    }
+}
+
+fn env_check_size_impl() -> Result<(), String> {
+    let mut vars = env::default_env();
+    env::check_size(&vars).map_err(|err| err.to_string())?;
+
+    // "HOME=/" and the like, each with a NUL
+    let defaults: usize = vars
+        .iter()
+        .map(|(name, value)| name.len() + value.len() + 2)
+        .sum();
+    vars.insert("BIG".to_owned(), "x".repeat(1024 - defaults - 5));
+    env::check_size(&vars).map_err(|err| err.to_string())?;
+    vars.insert("BIG".to_owned(), "x".repeat(1024 - defaults - 4));
+    match env::check_size(&vars) {
+        Err(Error::InvalidEnv(_)) => {}
+        _ => return Err("expected the environment to be too big".to_owned()),
+    }
+
+    let mut vars: BTreeMap<_, _> = (0..63)
+        .map(|i| (format!("V{}", i), String::new()))
+        .collect();
+    env::check_size(&vars).map_err(|err| err.to_string())?;
+    vars.insert("V63".to_owned(), String::new());
+    match env::check_size(&vars) {
+        Err(Error::InvalidEnv(_)) => {}
+        _ => return Err("expected too many variables".to_owned()),
+    }
+
+    Ok(())
+}
+
+#[test]
+fn env_check_size() {
+    if let Err(e) = env_check_size_impl() {
+        eprintln!("unexpected error occurred: {}", e)
+    }
+}
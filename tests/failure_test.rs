@@ -0,0 +1,90 @@
+use sp_wasm_engine::prelude::*;
+
+fn failure_classify_impl() -> Result<(), String> {
+    let cases = vec![
+        (
+            Some("RuntimeError"),
+            "unreachable executed",
+            Failure::Trap(Trap::Unreachable),
+        ),
+        (
+            Some("RuntimeError"),
+            "index out of bounds",
+            Failure::Trap(Trap::OutOfBounds),
+        ),
+        (
+            Some("RuntimeError"),
+            "integer divide by zero",
+            Failure::Trap(Trap::DivideByZero),
+        ),
+        (
+            Some("RuntimeError"),
+            "indirect call signature mismatch",
+            Failure::Trap(Trap::IndirectCallMismatch),
+        ),
+        (
+            Some("InternalError"),
+            "too much recursion",
+            Failure::Trap(Trap::StackOverflow),
+        ),
+        (
+            Some("RuntimeError"),
+            "something new",
+            Failure::Trap(Trap::Other),
+        ),
+        // thrown strings are never more than an abort(), which the guest
+        // could fake just as well
+        (None, "out of memory", Failure::Exception),
+        (Some("Error"), "out of memory", Failure::Exception),
+        (None, "abort(out of memory) at Error", Failure::Abort),
+        (
+            None,
+            "abort(Cannot enlarge memory arrays.) at Error",
+            Failure::Abort,
+        ),
+        (None, "abort(5) at Error", Failure::Abort),
+        (
+            None,
+            "abort(Assertion failed: x > 0, at: test.c,3,main) at Error",
+            Failure::Abort,
+        ),
+        (None, "Assertion failed: x > 0", Failure::Exception),
+        (None, "CompileError: spoofed", Failure::Exception),
+        (
+            Some("CompileError"),
+            "wasm validation error: at offset 8: failed to match magic number",
+            Failure::Compile,
+        ),
+        (
+            None,
+            "abort(CompileError: wasm validation error) at Error",
+            Failure::Abort,
+        ),
+        (
+            Some("LinkError"),
+            "import object field 'f' is not a Function",
+            Failure::Compile,
+        ),
+        (Some("TypeError"), "x is undefined", Failure::Exception),
+        (None, "{\"status\":1}", Failure::Exception),
+    ];
+
+    for (kind, message, failure) in cases {
+        assert_eq!(
+            Failure::classify(kind, message),
+            failure,
+            "{:?}: {}",
+            kind,
+            message
+        );
+    }
+
+    Ok(())
+}
+
+#[test]
+fn failure_classify() {
+    if let Err(e) = failure_classify_impl() {
+        eprintln!("unexpected error occurred: {}", e)
+    }
+}
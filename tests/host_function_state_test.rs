@@ -0,0 +1,66 @@
+use sp_wasm_engine::error::Error;
+use sp_wasm_engine::prelude::*;
+use sp_wasm_engine::sandbox::engine::error::{Error as EngineError, JSError};
+
+fn evaluate_err<T>(result: Result<T, Error>) -> Result<JSError, String> {
+    match result {
+        Err(Error::Engine(EngineError::SMJS(err))) => Ok(err),
+        Err(err) => Err(format!("wrong error received: {}", err)),
+        Ok(_) => Err("expected script to fail".to_owned()),
+    }
+}
+
+fn host_function_state_impl() -> Result<(), String> {
+    struct Calls(u32);
+
+    let engine = Engine::new().map_err(|err| err.to_string())?;
+    let runtime = Runtime::new(&engine).map_err(|err| err.to_string())?;
+
+    runtime.state().insert(Calls(0));
+    runtime
+        .define_function("count", |state: &State| {
+            let mut calls = state.get_mut::<Calls>().ok_or("no counter")?;
+            calls.0 += 1;
+            Ok::<_, &str>(calls.0)
+        })
+        .map_err(|err| err.to_string())?;
+
+    assert_eq!(
+        runtime
+            .evaluate::<u32, _>("count(); count(); count()")
+            .map_err(|err| err.to_string())?,
+        3
+    );
+    assert_eq!(runtime.state().get::<Calls>().map(|calls| calls.0), Some(3));
+
+    // values of different types can be borrowed at once
+    struct Step(u32);
+    runtime.state().insert(Step(10));
+    runtime
+        .define_function("step", |state: &State| {
+            let mut calls = state.get_mut::<Calls>().ok_or("no counter")?;
+            let step = state.get::<Step>().ok_or("no step")?;
+            calls.0 += step.0;
+            Ok::<_, &str>(calls.0)
+        })
+        .map_err(|err| err.to_string())?;
+    assert_eq!(
+        runtime
+            .evaluate::<u32, _>("step()")
+            .map_err(|err| err.to_string())?,
+        13
+    );
+
+    runtime.state().remove::<Calls>();
+    let err = evaluate_err(runtime.evaluate::<(), _>("count()"))?;
+    assert_eq!(err.message, "count: no counter");
+
+    Ok(())
+}
+
+#[test]
+fn host_function_state() {
+    if let Err(e) = host_function_state_impl() {
+        eprintln!("unexpected error occurred: {}", e)
+    }
+}
@@ -0,0 +1,85 @@
+use sp_wasm_engine::error::Error;
+use sp_wasm_engine::prelude::*;
+use sp_wasm_engine::sandbox::engine::error::{Error as EngineError, JSError};
+
+fn evaluate_err<T>(result: Result<T, Error>) -> Result<JSError, String> {
+    match result {
+        Err(Error::Engine(EngineError::SMJS(err))) => Ok(err),
+        Err(err) => Err(format!("wrong error received: {}", err)),
+        Ok(_) => Err("expected script to fail".to_owned()),
+    }
+}
+
+fn host_function_impl() -> Result<(), String> {
+    let engine = Engine::new().map_err(|err| err.to_string())?;
+    let runtime = Runtime::new(&engine).map_err(|err| err.to_string())?;
+
+    runtime
+        .define_function("add", |_: &State, a: i32, b: i32| {
+            a.checked_add(b).ok_or("overflow")
+        })
+        .map_err(|err| err.to_string())?;
+    runtime
+        .define_function("greet", |_: &State, name: Option<String>| {
+            Ok::<_, String>(format!(
+                "hello, {}",
+                name.unwrap_or_else(|| "world".to_owned())
+            ))
+        })
+        .map_err(|err| err.to_string())?;
+    runtime
+        .define_function("reversed", |_: &State, mut bytes: Vec<u8>| {
+            bytes.reverse();
+            Ok::<_, String>(bytes)
+        })
+        .map_err(|err| err.to_string())?;
+
+    assert_eq!(
+        runtime
+            .evaluate::<i32, _>("add(2, 3) * add.length")
+            .map_err(|err| err.to_string())?,
+        10
+    );
+    assert_eq!(
+        runtime
+            .evaluate::<String, _>("greet('zażółć') + '; ' + greet()")
+            .map_err(|err| err.to_string())?,
+        "hello, zażółć; hello, world"
+    );
+    assert_eq!(
+        runtime
+            .evaluate::<Vec<u8>, _>("reversed(new Uint8Array([1, 2, 3]))")
+            .map_err(|err| err.to_string())?,
+        vec![3, 2, 1]
+    );
+
+    let err = evaluate_err(runtime.evaluate::<(), _>("add(Math.pow(2, 30), Math.pow(2, 30))"))?;
+    assert_eq!(err.message, "add: overflow");
+    let err = evaluate_err(runtime.evaluate::<(), _>("add('2', 3)"))?;
+    assert_eq!(
+        err.message,
+        "add: argument 0: couldn't convert JS value: expected a number, got a string"
+    );
+    assert_eq!(
+        runtime
+            .evaluate::<String, _>("try { add(1) } catch (e) { e.message }")
+            .map_err(|err| err.to_string())?,
+        "add: argument 1: couldn't convert JS value: expected a number, got undefined"
+    );
+
+    assert!(runtime
+        .define_function("", |_: &State| Ok::<_, String>(()))
+        .is_err());
+    assert!(runtime
+        .define_function("a\0b", |_: &State| Ok::<_, String>(()))
+        .is_err());
+
+    Ok(())
+}
+
+#[test]
+fn host_function() {
+    if let Err(e) = host_function_impl() {
+        eprintln!("unexpected error occurred: {}", e)
+    }
+}
@@ -0,0 +1,54 @@
+use sp_wasm_engine::error::Error;
+use sp_wasm_engine::prelude::*;
+use sp_wasm_engine::sandbox::engine::error::Error as EngineError;
+
+fn js_convert_errors_impl() -> Result<(), String> {
+    let engine = Engine::new().map_err(|err| err.to_string())?;
+    let runtime = Runtime::new(&engine).map_err(|err| err.to_string())?;
+
+    fn conversion_error<T>(result: Result<T, Error>) -> Result<String, String> {
+        match result {
+            Err(Error::Engine(EngineError::Conversion(message))) => Ok(message),
+            Err(err) => Err(format!("wrong error received: {}", err)),
+            Ok(_) => Err("expected conversion to fail".to_owned()),
+        }
+    }
+
+    assert_eq!(
+        conversion_error(runtime.evaluate::<f64, _>("'1'"))?,
+        "expected a number, got a string"
+    );
+    assert_eq!(
+        conversion_error(runtime.evaluate::<i32, _>("1.5"))?,
+        "expected i32, got 1.5"
+    );
+    assert_eq!(
+        conversion_error(runtime.evaluate::<u32, _>("-1"))?,
+        "expected u32, got -1"
+    );
+    assert_eq!(
+        conversion_error(runtime.evaluate::<i32, _>("Math.pow(2, 31)"))?,
+        "expected i32, got 2147483648"
+    );
+    assert_eq!(
+        conversion_error(runtime.evaluate::<String, _>("undefined"))?,
+        "expected a string, got undefined"
+    );
+    assert_eq!(
+        conversion_error(runtime.evaluate::<Vec<u8>, _>("[1, 2]"))?,
+        "expected a typed array or ArrayBuffer, got an object"
+    );
+    assert!(conversion_error(
+        runtime.evaluate::<serde_json::Value, _>("var cyclic = {}; cyclic.self = cyclic; cyclic")
+    )?
+    .contains("cyclic"));
+
+    Ok(())
+}
+
+#[test]
+fn js_convert_errors() {
+    if let Err(e) = js_convert_errors_impl() {
+        eprintln!("unexpected error occurred: {}", e)
+    }
+}
@@ -0,0 +1,105 @@
+use sp_wasm_engine::prelude::*;
+
+fn js_convert_impl() -> Result<(), String> {
+    let engine = Engine::new().map_err(|err| err.to_string())?;
+    let runtime = Runtime::new(&engine).map_err(|err| err.to_string())?;
+
+    assert_eq!(
+        runtime
+            .evaluate::<f64, _>("1 / 4")
+            .map_err(|err| err.to_string())?,
+        0.25
+    );
+    assert_eq!(
+        runtime
+            .evaluate::<i32, _>("-(1 << 30) * 2")
+            .map_err(|err| err.to_string())?,
+        std::i32::MIN
+    );
+    assert_eq!(
+        runtime
+            .evaluate::<u64, _>("Math.pow(2, 53)")
+            .map_err(|err| err.to_string())?,
+        1 << 53
+    );
+    assert!(runtime
+        .evaluate::<bool, _>("[1, 2].indexOf(2) === 1")
+        .map_err(|err| err.to_string())?);
+    assert_eq!(
+        runtime
+            .evaluate::<String, _>("'zażółć ' + String.fromCodePoint(0x1F600)")
+            .map_err(|err| err.to_string())?,
+        "zażółć 😀"
+    );
+    assert_eq!(
+        runtime
+            .evaluate::<Vec<u8>, _>("new Uint8Array([1, 2, 255])")
+            .map_err(|err| err.to_string())?,
+        vec![1, 2, 255]
+    );
+    assert_eq!(
+        runtime
+            .evaluate::<Vec<u8>, _>("new Uint8Array([0, 1, 2, 3]).subarray(1, 3)")
+            .map_err(|err| err.to_string())?,
+        vec![1, 2]
+    );
+    assert_eq!(
+        runtime
+            .evaluate::<Vec<u8>, _>("new Uint8Array([7, 8]).buffer")
+            .map_err(|err| err.to_string())?,
+        vec![7, 8]
+    );
+    assert_eq!(
+        runtime
+            .evaluate::<serde_json::Value, _>(
+                "({a: [1, 'two', null], b: {c: true}, f: function() {}})"
+            )
+            .map_err(|err| err.to_string())?,
+        serde_json::json!({"a": [1, "two", null], "b": {"c": true}})
+    );
+    assert_eq!(
+        runtime
+            .evaluate::<serde_json::Value, _>("undefined")
+            .map_err(|err| err.to_string())?,
+        serde_json::Value::Null
+    );
+    assert_eq!(
+        runtime
+            .evaluate::<Option<String>, _>("null")
+            .map_err(|err| err.to_string())?,
+        None
+    );
+    assert_eq!(
+        runtime
+            .evaluate::<Option<String>, _>("'x'")
+            .map_err(|err| err.to_string())?,
+        Some("x".to_owned())
+    );
+
+    // same values as through `evaluate_script`
+    assert_eq!(
+        runtime
+            .evaluate::<f64, _>("Date.now()")
+            .map_err(|err| err.to_string())?,
+        0.0
+    );
+    assert_eq!(
+        Runtime::new(&engine)
+            .map_err(|err| err.to_string())?
+            .evaluate::<f64, _>("golem_randEmu()")
+            .map_err(|err| err.to_string())?,
+        0.7641367265279992
+    );
+    assert!(runtime
+        .evaluate::<(), _>("writeFile('/tmp/test.txt', new Uint8Array(2))")
+        .is_err());
+
+    Ok(())
+}
+
+#[test]
+fn js_convert() {
+    if let Err(e) = js_convert_impl() {
+        eprintln!("unexpected error occurred: {}", e)
+    }
+}
@@ -0,0 +1,69 @@
+use sp_wasm_engine::error::Error;
+use sp_wasm_engine::prelude::*;
+use sp_wasm_engine::sandbox::engine::error::{Error as EngineError, JSError};
+use std::time::{Duration, Instant};
+
+fn evaluate_err(runtime: &Runtime, script: &str) -> Result<JSError, String> {
+    match runtime.evaluate::<(), _>(script) {
+        Err(Error::Engine(EngineError::SMJS(err))) => Ok(err),
+        Err(err) => Err(format!("wrong error received: {}", err)),
+        Ok(_) => Err("expected script to fail".to_owned()),
+    }
+}
+
+// (module (func (export "f") unreachable))
+const UNREACHABLE_WASM: &str = "[0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, \
+     0x01, 0x04, 0x01, 0x60, 0x00, 0x00, 0x03, 0x02, 0x01, 0x00, \
+     0x07, 0x05, 0x01, 0x01, 0x66, 0x00, 0x00, 0x0a, 0x05, 0x01, 0x03, 0x00, 0x00, 0x0b]";
+
+fn js_error_failure_impl() -> Result<(), String> {
+    let engine = Engine::new().map_err(|err| err.to_string())?;
+    let runtime = Runtime::new(&engine).map_err(|err| err.to_string())?;
+
+    let err = evaluate_err(
+        &runtime,
+        &format!(
+            "new WebAssembly.Instance(new WebAssembly.Module(new Uint8Array({}))).exports.f();",
+            UNREACHABLE_WASM
+        ),
+    )?;
+    assert_eq!(err.failure, Failure::Trap(Trap::Unreachable));
+
+    let err = evaluate_err(
+        &runtime,
+        "new WebAssembly.Module(new Uint8Array([1, 2, 3]));",
+    )?;
+    assert_eq!(err.failure, Failure::Compile);
+
+    let err = evaluate_err(&runtime, "(function f() { f(); })();")?;
+    assert_eq!(err.failure, Failure::Trap(Trap::StackOverflow));
+
+    let err = evaluate_err(&runtime, "undefined.x;")?;
+    assert_eq!(err.failure, Failure::Exception);
+
+    // running out of memory can't be faked
+    let err = evaluate_err(&runtime, "throw 'abort(Cannot enlarge memory arrays.)';")?;
+    assert_eq!(err.failure, Failure::Abort);
+
+    runtime.set_timeout(Some(Duration::from_millis(100)));
+    let err = evaluate_err(&runtime, "while (true) {}")?;
+    assert_eq!(err.failure, Failure::Timeout);
+    // sleeping doesn't outlast it either
+    let start = Instant::now();
+    let err = evaluate_err(&runtime, "usleep(60 * 1000 * 1000);")?;
+    assert_eq!(err.failure, Failure::Timeout);
+    assert!(start.elapsed() < Duration::from_secs(10));
+    // and the runtime is still usable
+    runtime
+        .evaluate::<(), _>("1 + 1")
+        .map_err(|err| err.to_string())?;
+
+    Ok(())
+}
+
+#[test]
+fn js_error_failure() {
+    if let Err(e) = js_error_failure_impl() {
+        eprintln!("unexpected error occurred: {}", e)
+    }
+}
@@ -0,0 +1,56 @@
+use sp_wasm_engine::error::Error;
+use sp_wasm_engine::prelude::*;
+use sp_wasm_engine::sandbox::engine::error::{Error as EngineError, JSError};
+
+fn evaluate_err(runtime: &Runtime, script: &str) -> Result<JSError, String> {
+    match runtime.evaluate::<(), _>(script) {
+        Err(Error::Engine(EngineError::SMJS(err))) => Ok(err),
+        Err(err) => Err(format!("wrong error received: {}", err)),
+        Ok(_) => Err("expected script to fail".to_owned()),
+    }
+}
+
+fn js_error_stringify_impl() -> Result<(), String> {
+    let engine = Engine::new().map_err(|err| err.to_string())?;
+    let runtime = Runtime::new(&engine).map_err(|err| err.to_string())?;
+
+    // non-ASCII, including a surrogate pair and an unpaired surrogate
+    let err = evaluate_err(&runtime, "throw {text: 'zażółć 😀', lone: '\\ud800'};")?;
+    assert_eq!(
+        err.message,
+        "{\"text\":\"zażółć 😀\",\"lone\":\"\u{fffd}\"}"
+    );
+
+    // well over the old 1 KiB buffer
+    let err = evaluate_err(&runtime, "throw {text: new Array(50001).join('x')};")?;
+    assert_eq!(err.message.len(), "{\"text\":\"\"}".len() + 50000);
+    assert!(!err.message.ends_with(JSError::TRUNCATED));
+
+    runtime.set_max_error_len(16);
+    let err = evaluate_err(&runtime, "throw {text: new Array(100001).join('x')};")?;
+    assert_eq!(
+        err.message,
+        format!("{{\"text\":\"xxxxxxx{}", JSError::TRUNCATED)
+    );
+
+    // never cut in the middle of a char
+    let err = evaluate_err(&runtime, "throw {text: '😀😀😀😀😀😀'};")?;
+    assert_eq!(
+        err.message,
+        format!("{{\"text\":\"😀{}", JSError::TRUNCATED)
+    );
+    let err = evaluate_err(&runtime, "throw new Array(100).join('ą');")?;
+    assert_eq!(
+        err.message,
+        format!("{}{}", "ą".repeat(8), JSError::TRUNCATED)
+    );
+
+    Ok(())
+}
+
+#[test]
+fn js_error_stringify() {
+    if let Err(e) = js_error_stringify_impl() {
+        eprintln!("unexpected error occurred: {}", e)
+    }
+}
@@ -0,0 +1,55 @@
+use sp_wasm_engine::error::Error;
+use sp_wasm_engine::prelude::*;
+use sp_wasm_engine::sandbox::engine::error::{Error as EngineError, JSError};
+
+#[allow(deprecated)]
+fn evaluate_err(runtime: &Runtime, script: &str) -> Result<JSError, String> {
+    match runtime.evaluate_script(script) {
+        Err(Error::Engine(EngineError::SMJS(err))) => Ok(err),
+        Err(err) => Err(format!("wrong error received: {}", err)),
+        Ok(_) => Err("expected script to fail".to_owned()),
+    }
+}
+
+fn js_error_impl() -> Result<(), String> {
+    let engine = Engine::new().map_err(|err| err.to_string())?;
+    let runtime = Runtime::new(&engine).map_err(|err| err.to_string())?;
+
+    let err = evaluate_err(&runtime, "var x = 1;\nfunction f() {\n    null.x;\n}\nf();")?;
+    assert_eq!(err.kind, Some("TypeError".to_owned()));
+    assert_eq!(err.filename, Some("noname".to_owned()));
+    assert_eq!(err.line, Some(3));
+    assert!(err.column.is_some());
+    let stack = err.stack.clone().unwrap_or_default();
+    assert!(
+        stack.starts_with("f@noname:3:"),
+        "unexpected stack: {}",
+        stack
+    );
+    assert!(err.to_string().starts_with("JavaScript error: TypeError: "));
+    assert!(err.to_string().contains("(at noname:3:"));
+
+    let json = serde_json::to_string(&err).map_err(|err| err.to_string())?;
+    let deserialized: JSError = serde_json::from_str(&json).map_err(|err| err.to_string())?;
+    assert_eq!(deserialized, err);
+
+    // anything but an `Error` has no location
+    let err = evaluate_err(&runtime, "throw 'oops';")?;
+    assert_eq!(
+        err,
+        JSError {
+            message: "oops".to_owned(),
+            ..JSError::default()
+        }
+    );
+    assert_eq!(err.to_string(), "JavaScript error: oops");
+
+    Ok(())
+}
+
+#[test]
+fn js_error() {
+    if let Err(e) = js_error_impl() {
+        eprintln!("unexpected error occurred: {}", e)
+    }
+}
@@ -1,6 +1,7 @@
 use sp_wasm_engine::prelude::*;
 
 #[test]
+#[allow(deprecated)]
 fn random_device_determinism() {
     let engine = Engine::new().unwrap();
     let start = Runtime::new(&engine)
@@ -1,6 +1,8 @@
 use sp_wasm_engine::prelude::*;
+use sp_wasm_engine::sandbox::random::RandEmu;
 
 #[test]
+#[allow(deprecated)]
 fn random_device_emulation() {
     let engine = Engine::new().unwrap();
     let runtime = Runtime::new(&engine).unwrap();
@@ -19,4 +21,32 @@ fn random_device_emulation() {
 
     assert_ne!(v1, v2);
     assert_ne!(v2, v3);
+
+    // every runtime has a generator of its own, shared with /dev/urandom
+    let mut expected = RandEmu::new();
+    let other = Runtime::new(&engine).unwrap();
+    assert_eq!(
+        other.evaluate::<f64, _>("golem_randEmu()").unwrap(),
+        expected.next_f64()
+    );
+    assert_eq!(
+        other
+            .evaluate::<i32, _>("readDevice('/dev/urandom')")
+            .unwrap(),
+        i32::from(expected.next_u8())
+    );
+    assert_eq!(
+        other.evaluate::<f64, _>("golem_randEmu()").unwrap(),
+        expected.next_f64()
+    );
+
+    // and the first one carries on where it left off
+    let mut expected = RandEmu::new();
+    for _ in 0..3 {
+        expected.next_f64();
+    }
+    assert_eq!(
+        runtime.evaluate::<f64, _>("golem_randEmu()").unwrap(),
+        expected.next_f64()
+    );
 }
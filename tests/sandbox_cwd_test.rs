@@ -0,0 +1,69 @@
+mod common;
+
+use common::*;
+use sp_wasm_engine::prelude::*;
+use sp_wasm_engine::Error;
+use std::fs;
+
+const INPUT_PART1: &'static [u8] = include_bytes!("assets/aaa.txt");
+const INPUT_PART2: &'static [u8] = include_bytes!("assets/bbb.txt");
+const EM_JS: &'static [u8] = include_bytes!("assets/test.js");
+const EM_WASM: &'static [u8] = include_bytes!("assets/test.wasm");
+
+fn sandbox_cwd_impl() -> Result<(), String> {
+    let test_dir = create_workspace()?;
+    let input_dir = test_dir.path().join("in");
+    fs::create_dir_all(input_dir.join("a")).map_err(|err| err.to_string())?;
+    fs::write(input_dir.join("aaa.txt"), INPUT_PART1).map_err(|err| err.to_string())?;
+    fs::write(input_dir.join("a/bbb.txt"), INPUT_PART2).map_err(|err| err.to_string())?;
+
+    let js = test_dir.path().join("test.js");
+    fs::write(&js, EM_JS).map_err(|err| err.to_string())?;
+    let wasm = test_dir.path().join("test.wasm");
+    fs::write(&wasm, EM_WASM).map_err(|err| err.to_string())?;
+
+    let output_dir = test_dir.path().join("out");
+    fs::create_dir(&output_dir).map_err(|err| err.to_string())?;
+
+    // the test program only uses relative paths
+    let engine = Engine::new().map_err(|err| err.to_string())?;
+    Sandbox::new(&engine)
+        .and_then(|sandbox| sandbox.set_exec_args(vec!["test"]))
+        .and_then(|sandbox| sandbox.mount(&input_dir, "/project", false, false))
+        .and_then(|sandbox| sandbox.set_cwd("/project"))
+        .and_then(|sandbox| sandbox.run(&js, &wasm))
+        .and_then(|sandbox| {
+            sandbox.save_output_files(&output_dir, vec!["ccc.txt", "/project/c/ddd.txt"])
+        })
+        .map_err(|err| err.to_string())?;
+
+    assert_eq!(
+        fs::read_to_string(output_dir.join("ccc.txt")).map_err(|err| err.to_string())?,
+        "THIS IS PART1:\ntest\ntest\n"
+    );
+    assert_eq!(
+        fs::read_to_string(output_dir.join("project/c/ddd.txt")).map_err(|err| err.to_string())?,
+        "THIS IS PART2:\ninput\ntest\n"
+    );
+
+    match Sandbox::new(&engine).and_then(|sandbox| sandbox.set_cwd("project")) {
+        Err(Error::InvalidPath(_)) => {}
+        _ => return Err("expected relative cwd to be rejected".to_owned()),
+    }
+
+    // `PWD` wouldn't fit in the guest's environment
+    let long_cwd = format!("/{}", "a".repeat(1024));
+    match Sandbox::new(&engine).and_then(|sandbox| sandbox.set_cwd(&long_cwd)) {
+        Err(Error::InvalidEnv(_)) => {}
+        _ => return Err("expected oversized cwd to be rejected".to_owned()),
+    }
+
+    Ok(())
+}
+
+#[test]
+fn sandbox_cwd() {
+    if let Err(e) = sandbox_cwd_impl() {
+        eprintln!("unexpected error occurred: {}", e)
+    }
+}
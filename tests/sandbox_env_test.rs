@@ -0,0 +1,39 @@
+mod common;
+
+use common::*;
+use sp_wasm_engine::prelude::*;
+use std::fs;
+
+const ENV_JS: &'static [u8] = include_bytes!("assets/env.js");
+const EM_WASM: &'static [u8] = include_bytes!("assets/test.wasm");
+
+fn sandbox_env_impl() -> Result<(), String> {
+    let test_dir = create_workspace()?;
+    let js = test_dir.path().join("env.js");
+    fs::write(&js, ENV_JS).map_err(|err| err.to_string())?;
+    // not used by the stand-in glue
+    let wasm = test_dir.path().join("test.wasm");
+    fs::write(&wasm, EM_WASM).map_err(|err| err.to_string())?;
+
+    let engine = Engine::new().map_err(|err| err.to_string())?;
+    let sandbox = Sandbox::new(&engine)
+        .and_then(|sandbox| sandbox.set_stdout(Sink::Memory))
+        .and_then(|sandbox| sandbox.set_env(vec![("NAME", "zażółć")]))
+        .and_then(|sandbox| sandbox.run(&js, &wasm))
+        .map_err(|err| err.to_string())?;
+
+    // Emscripten's own defaults are gone, even for global constructors
+    assert_eq!(
+        String::from_utf8_lossy(&sandbox.stdout()),
+        "static NAME=zażółć\nNAME=zażółć\nUSER=null\n"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn sandbox_env() {
+    if let Err(e) = sandbox_env_impl() {
+        eprintln!("unexpected error occurred: {}", e)
+    }
+}
@@ -0,0 +1,93 @@
+mod common;
+
+use common::*;
+use sp_wasm_engine::prelude::*;
+use sp_wasm_engine::{Error, Limit};
+use std::fs;
+
+fn sandbox_limits_impl() -> Result<(), String> {
+    let test_dir = create_workspace()?;
+    let input_dir = test_dir.path().join("in");
+    fs::create_dir(&input_dir).map_err(|err| err.to_string())?;
+    fs::write(input_dir.join("x.txt"), b"xxx").map_err(|err| err.to_string())?;
+    let mount_dir = test_dir.path().join("mount");
+    fs::create_dir_all(mount_dir.join("a")).map_err(|err| err.to_string())?;
+    fs::write(mount_dir.join("a/y.txt"), b"yyy").map_err(|err| err.to_string())?;
+
+    let options = |limits| MapOptions {
+        limits,
+        ..MapOptions::default()
+    };
+
+    // each tree is within the limits on its own, but not both together
+    let cases = vec![
+        (
+            Limits {
+                max_bytes: Some(5),
+                ..Limits::default()
+            },
+            Limit::Bytes,
+            6,
+            5,
+        ),
+        (
+            Limits {
+                max_files: Some(1),
+                ..Limits::default()
+            },
+            Limit::Files,
+            2,
+            1,
+        ),
+        // `/data/a/y.txt`
+        (
+            Limits {
+                max_depth: Some(2),
+                ..Limits::default()
+            },
+            Limit::Depth,
+            3,
+            2,
+        ),
+    ];
+
+    let engine = Engine::new().map_err(|err| err.to_string())?;
+    for (limits, kind, actual, limit) in cases {
+        let result = Sandbox::new(&engine)
+            .and_then(|sandbox| sandbox.set_map_options(options(limits)))
+            .and_then(|sandbox| sandbox.load_input_files(&input_dir))
+            .and_then(|sandbox| sandbox.mount(&mount_dir, "/data", false, false));
+        match result {
+            Err(Error::LimitExceeded {
+                kind: err_kind,
+                actual: err_actual,
+                limit: err_limit,
+            }) => {
+                assert_eq!((err_kind, err_actual, err_limit), (kind, actual, limit));
+            }
+            Err(err) => return Err(format!("wrong error received: {}", err)),
+            Ok(_) => return Err("expected limits to be exceeded".to_owned()),
+        }
+    }
+
+    // passthrough mounts don't count
+    Sandbox::new(&engine)
+        .and_then(|sandbox| {
+            sandbox.set_map_options(options(Limits {
+                max_files: Some(1),
+                ..Limits::default()
+            }))
+        })
+        .and_then(|sandbox| sandbox.load_input_files(&input_dir))
+        .and_then(|sandbox| sandbox.mount(&mount_dir, "/passthrough", false, true))
+        .map_err(|err| err.to_string())?;
+
+    Ok(())
+}
+
+#[test]
+fn sandbox_limits() {
+    if let Err(e) = sandbox_limits_impl() {
+        eprintln!("unexpected error occurred: {}", e)
+    }
+}
@@ -0,0 +1,60 @@
+mod common;
+
+use common::*;
+use sp_wasm_engine::prelude::*;
+use sp_wasm_engine::Error;
+use std::{fs, path::PathBuf};
+
+const INPUT_PART1: &'static [u8] = include_bytes!("assets/aaa.txt");
+const INPUT_PART2: &'static [u8] = include_bytes!("assets/bbb.txt");
+const EM_JS: &'static [u8] = include_bytes!("assets/test.js");
+const EM_WASM: &'static [u8] = include_bytes!("assets/test.wasm");
+
+fn sandbox_missing_outputs_impl() -> Result<(), String> {
+    let test_dir = create_workspace()?;
+    let mut input_dir = PathBuf::from(test_dir.path());
+    input_dir.push("in/");
+    fs::create_dir_all(input_dir.join("a")).map_err(|err| err.to_string())?;
+    fs::write(input_dir.join("aaa.txt"), INPUT_PART1).map_err(|err| err.to_string())?;
+    fs::write(input_dir.join("a/bbb.txt"), INPUT_PART2).map_err(|err| err.to_string())?;
+
+    let js = test_dir.path().join("test.js");
+    fs::write(&js, EM_JS).map_err(|err| err.to_string())?;
+    let wasm = test_dir.path().join("test.wasm");
+    fs::write(&wasm, EM_WASM).map_err(|err| err.to_string())?;
+
+    let output_dir = test_dir.path().join("out/");
+    fs::create_dir(&output_dir).map_err(|err| err.to_string())?;
+
+    let engine = Engine::new().map_err(|err| err.to_string())?;
+    let result = Sandbox::new(&engine)
+        .and_then(|sandbox| sandbox.set_exec_args(vec!["test"]))
+        .and_then(|sandbox| sandbox.load_input_files(&input_dir))
+        .and_then(|sandbox| sandbox.run(&js, &wasm))
+        .and_then(|sandbox| {
+            sandbox.save_output_files(
+                &output_dir,
+                vec!["ccc.txt", "log.txt?", "x.txt", "c/ddd.txt", "c/y.txt"],
+            )
+        });
+
+    // all missing outputs are reported, optional ones aren't
+    assert_eq!(
+        result.err(),
+        Some(Error::MissingOutputs(vec![
+            PathBuf::from("x.txt"),
+            PathBuf::from("c/y.txt")
+        ]))
+    );
+    // and nothing is saved
+    assert!(!output_dir.join("ccc.txt").exists());
+
+    Ok(())
+}
+
+#[test]
+fn sandbox_missing_outputs() {
+    if let Err(e) = sandbox_missing_outputs_impl() {
+        eprintln!("unexpected error occurred: {}", e)
+    }
+}
@@ -0,0 +1,79 @@
+mod common;
+
+use common::*;
+use sp_wasm_engine::prelude::*;
+use std::{
+    fs::{self, File},
+    io::{Read, Write},
+    path::PathBuf,
+};
+
+const INPUT_PART1: &'static [u8] = include_bytes!("assets/aaa.txt");
+const INPUT_PART2: &'static [u8] = include_bytes!("assets/bbb.txt");
+const EM_JS: &'static [u8] = include_bytes!("assets/test.js");
+const EM_WASM: &'static [u8] = include_bytes!("assets/test.wasm");
+
+fn sandbox_mount_impl() -> Result<(), String> {
+    let test_dir = create_workspace()?;
+
+    // inputs split across two host dirs, mounted side by side
+    let mut root_dir = PathBuf::from(test_dir.path());
+    root_dir.push("root/");
+    fs::create_dir(root_dir.as_path()).map_err(|err| err.to_string())?;
+    let mut f = File::create(root_dir.join("aaa.txt")).map_err(|err| err.to_string())?;
+    f.write_all(INPUT_PART1).map_err(|err| err.to_string())?;
+
+    let mut shared_dir = PathBuf::from(test_dir.path());
+    shared_dir.push("shared/");
+    fs::create_dir(shared_dir.as_path()).map_err(|err| err.to_string())?;
+    let mut f = File::create(shared_dir.join("bbb.txt")).map_err(|err| err.to_string())?;
+    f.write_all(INPUT_PART2).map_err(|err| err.to_string())?;
+
+    let mut js = PathBuf::from(test_dir.path());
+    js.push("test.js");
+    let mut f = File::create(js.as_path()).map_err(|err| err.to_string())?;
+    f.write_all(EM_JS).map_err(|err| err.to_string())?;
+
+    let mut wasm = PathBuf::from(test_dir.path());
+    wasm.push("test.wasm");
+    let mut f = File::create(wasm.as_path()).map_err(|err| err.to_string())?;
+    f.write_all(EM_WASM).map_err(|err| err.to_string())?;
+
+    let mut output_dir = PathBuf::from(test_dir.path());
+    output_dir.push("out/");
+    fs::create_dir(output_dir.as_path()).map_err(|err| err.to_string())?;
+
+    let engine = Engine::new().map_err(|err| err.to_string())?;
+    Sandbox::new(&engine)
+        .and_then(|sandbox| sandbox.set_exec_args(vec!["test"]))
+        .and_then(|sandbox| sandbox.mount(root_dir.as_path(), "/", false, false))
+        .and_then(|sandbox| sandbox.mount(shared_dir.as_path(), "/a", true, false))
+        .and_then(|sandbox| sandbox.run(js.to_str().unwrap(), wasm.to_str().unwrap()))
+        .and_then(|sandbox| {
+            sandbox.save_output_files(output_dir.to_str().unwrap(), vec!["ccc.txt", "c/ddd.txt"])
+        })
+        .map_err(|err| err.to_string())?;
+
+    let mut file = File::open(output_dir.join("ccc.txt")).map_err(|err| err.to_string())?;
+    let mut contents = String::new();
+    file.read_to_string(&mut contents)
+        .map_err(|err| err.to_string())?;
+
+    assert_eq!("THIS IS PART1:\ntest\ntest\n".to_owned(), contents);
+
+    let mut file = File::open(output_dir.join("c/ddd.txt")).map_err(|err| err.to_string())?;
+    let mut contents = String::new();
+    file.read_to_string(&mut contents)
+        .map_err(|err| err.to_string())?;
+
+    assert_eq!("THIS IS PART2:\ninput\ntest\n".to_owned(), contents);
+
+    Ok(())
+}
+
+#[test]
+fn sandbox_mount() {
+    if let Err(e) = sandbox_mount_impl() {
+        eprintln!("unexpected error occurred: {}", e)
+    }
+}
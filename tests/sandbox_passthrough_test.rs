@@ -0,0 +1,63 @@
+mod common;
+
+use common::*;
+use sp_wasm_engine::prelude::*;
+use sp_wasm_engine::Error;
+use std::fs;
+
+const INPUT_PART1: &'static [u8] = include_bytes!("assets/aaa.txt");
+const INPUT_PART2: &'static [u8] = include_bytes!("assets/bbb.txt");
+const EM_JS: &'static [u8] = include_bytes!("assets/test.js");
+const EM_WASM: &'static [u8] = include_bytes!("assets/test.wasm");
+
+fn sandbox_passthrough_impl() -> Result<(), String> {
+    let test_dir = create_workspace()?;
+    let root_dir = test_dir.path().join("root");
+    fs::create_dir(&root_dir).map_err(|err| err.to_string())?;
+    fs::write(root_dir.join("aaa.txt"), INPUT_PART1).map_err(|err| err.to_string())?;
+    let shared_dir = test_dir.path().join("shared");
+    fs::create_dir(&shared_dir).map_err(|err| err.to_string())?;
+    fs::write(shared_dir.join("bbb.txt"), INPUT_PART2).map_err(|err| err.to_string())?;
+
+    let js = test_dir.path().join("test.js");
+    fs::write(&js, EM_JS).map_err(|err| err.to_string())?;
+    let wasm = test_dir.path().join("test.wasm");
+    fs::write(&wasm, EM_WASM).map_err(|err| err.to_string())?;
+
+    let output_dir = test_dir.path().join("out");
+    fs::create_dir(&output_dir).map_err(|err| err.to_string())?;
+
+    // `a/bbb.txt` is read from the host dir, not from a copy
+    let engine = Engine::new().map_err(|err| err.to_string())?;
+    Sandbox::new(&engine)
+        .and_then(|sandbox| sandbox.set_exec_args(vec!["test"]))
+        .and_then(|sandbox| sandbox.mount(&root_dir, "/", false, false))
+        .and_then(|sandbox| sandbox.mount(&shared_dir, "/a", true, true))
+        .and_then(|sandbox| sandbox.run(&js, &wasm))
+        .and_then(|sandbox| sandbox.save_output_files(&output_dir, vec!["ccc.txt", "c/ddd.txt"]))
+        .map_err(|err| err.to_string())?;
+
+    assert_eq!(
+        fs::read_to_string(output_dir.join("ccc.txt")).map_err(|err| err.to_string())?,
+        "THIS IS PART1:\ntest\ntest\n"
+    );
+    assert_eq!(
+        fs::read_to_string(output_dir.join("c/ddd.txt")).map_err(|err| err.to_string())?,
+        "THIS IS PART2:\ninput\ntest\n"
+    );
+
+    // `/` also holds the devices and the Wasm binary
+    match Sandbox::new(&engine).and_then(|sandbox| sandbox.mount(&root_dir, "/", false, true)) {
+        Err(Error::InvalidPath(_)) => {}
+        _ => return Err("expected passthrough mount onto / to be rejected".to_owned()),
+    }
+
+    Ok(())
+}
+
+#[test]
+fn sandbox_passthrough() {
+    if let Err(e) = sandbox_passthrough_impl() {
+        eprintln!("unexpected error occurred: {}", e)
+    }
+}
@@ -0,0 +1,68 @@
+use sp_wasm_engine::prelude::*;
+use std::sync::{Arc, Barrier};
+use std::thread;
+
+const NAMES: [&str; 2] = ["first", "second"];
+
+fn sandbox_stdio_impl() -> Result<(), String> {
+    let engine = Engine::new().map_err(|err| err.to_string())?;
+
+    // both sandboxes exist at once, as with tests running in parallel
+    let barrier = Arc::new(Barrier::new(NAMES.len()));
+    let threads: Vec<_> = NAMES
+        .iter()
+        .map(|&name| {
+            let engine = engine.clone();
+            let barrier = Arc::clone(&barrier);
+            thread::spawn(move || -> Result<(Vec<u8>, String), String> {
+                let sandbox = Sandbox::new(&engine)
+                    .and_then(|sandbox| sandbox.set_stdout(Sink::Memory))
+                    .and_then(|sandbox| sandbox.set_env(vec![("NAME", name)]));
+                barrier.wait();
+                let sandbox = sandbox.map_err(|err| err.to_string())?;
+
+                sandbox
+                    .runtime()
+                    .evaluate::<(), _>(&format!("print('{}');", name))
+                    .map_err(|err| err.to_string())?;
+                let env = sandbox
+                    .runtime()
+                    .evaluate::<String, _>("golem_getEnv()")
+                    .map_err(|err| err.to_string())?;
+
+                // and each has a filesystem of its own
+                let bytes: Vec<_> = name.bytes().map(|byte| byte.to_string()).collect();
+                let written = sandbox.runtime().evaluate::<(), _>(&format!(
+                    "writeFile('/name.txt', new Uint8Array([{}]));",
+                    bytes.join(", ")
+                ));
+                barrier.wait();
+                written.map_err(|err| err.to_string())?;
+                let file = sandbox
+                    .runtime()
+                    .evaluate::<Vec<u8>, _>("new Uint8Array(readFile('/name.txt'))")
+                    .map_err(|err| err.to_string())?;
+                assert_eq!(file, name.as_bytes());
+
+                Ok((sandbox.stdout(), env))
+            })
+        })
+        .collect();
+
+    for (thread, name) in threads.into_iter().zip(NAMES.iter()) {
+        let (stdout, env) = thread
+            .join()
+            .map_err(|_| "sandbox thread panicked".to_owned())??;
+        assert_eq!(stdout, format!("{}\n", name).into_bytes());
+        assert!(env.split('\0').any(|var| var == format!("NAME={}", name)));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn sandbox_stdio() {
+    if let Err(e) = sandbox_stdio_impl() {
+        eprintln!("unexpected error occurred: {}", e)
+    }
+}
@@ -65,6 +65,17 @@ fn sandbox_impl() -> Result<(), String> {
 
     assert_eq!("THIS IS PART2:\ninput\ntest\n".to_owned(), contents);
 
+    // args are passed as is, whatever characters they contain
+    let args = vec!["it's", "a\\b", "zażółć"];
+    let sandbox = Sandbox::new(&engine)
+        .and_then(|sandbox| sandbox.set_exec_args(args.clone()))
+        .map_err(|err| err.to_string())?;
+    let joined = sandbox
+        .runtime()
+        .evaluate::<String, _>("Module['arguments'].join('|')")
+        .map_err(|err| err.to_string())?;
+    assert_eq!(joined, args.join("|"));
+
     Ok(())
 }
 
@@ -0,0 +1,60 @@
+use sp_wasm_engine::prelude::*;
+use sp_wasm_engine::Error;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+fn stdin_open_impl() -> Result<(), String> {
+    let stdin = Stdin::from(&b"Hello world!"[..]);
+    let mut contents = Vec::new();
+    let mut reader = stdin.open().map_err(|err| err.to_string())?;
+    reader
+        .read_to_end(&mut contents)
+        .map_err(|err| err.to_string())?;
+    assert_eq!(contents, b"Hello world!");
+    // and stays at EOF
+    assert_eq!(reader.read(&mut [0; 4]).map_err(|err| err.to_string())?, 0);
+
+    // every run gets the input from the start
+    let mut contents = Vec::new();
+    stdin
+        .open()
+        .map_err(|err| err.to_string())?
+        .read_to_end(&mut contents)
+        .map_err(|err| err.to_string())?;
+    assert_eq!(contents, b"Hello world!");
+
+    let stdin = Stdin::from(Path::new("missing/stdin.txt"));
+    assert_eq!(stdin, Stdin::File(PathBuf::from("missing/stdin.txt")));
+    match stdin.open().err() {
+        Some(Error::FileError(_, ref path)) => assert_eq!(path, Path::new("missing/stdin.txt")),
+        _ => return Err("expected missing stdin file to fail".to_owned()),
+    }
+
+    Ok(())
+}
+
+#[test]
+fn stdin_open() {
+    if let Err(e) = stdin_open_impl() {
+        eprintln!("unexpected error occurred: {}", e)
+    }
+}
+
+fn sink_from_str_impl() -> Result<(), String> {
+    assert_eq!("host".parse::<Sink>()?, Sink::Host);
+    assert_eq!("memory".parse::<Sink>()?, Sink::Memory);
+    assert_eq!(
+        "logs/stdout.txt".parse::<Sink>()?,
+        Sink::File(PathBuf::from("logs/stdout.txt"))
+    );
+    assert!("".parse::<Sink>().is_err());
+
+    Ok(())
+}
+
+#[test]
+fn sink_from_str() {
+    if let Err(e) = sink_from_str_impl() {
+        eprintln!("unexpected error occurred: {}", e)
+    }
+}
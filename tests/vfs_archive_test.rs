@@ -0,0 +1,410 @@
+mod common;
+
+use common::*;
+use sp_wasm_engine::prelude::*;
+use sp_wasm_engine::sandbox::vfs::hostfs::Transaction;
+use sp_wasm_engine::{Error, Limit};
+use std::ffi::OsStr;
+use std::fs;
+use std::io::Write;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+fn tar_header(path: &str, entry_type: tar::EntryType, len: u64) -> tar::Header {
+    let mut header = tar::Header::new_gnu();
+    // bypass the `tar` crate's own path checks
+    header.as_old_mut().name[..path.len()].copy_from_slice(path.as_bytes());
+    header.set_entry_type(entry_type);
+    header.set_mode(0o644);
+    header.set_size(len);
+    header.set_cksum();
+    header
+}
+
+fn write_tar<W: Write>(writer: W, entries: &[(&str, &[u8])]) -> Result<W, String> {
+    let mut builder = tar::Builder::new(writer);
+    for (path, contents) in entries {
+        let header = if path.ends_with('/') {
+            tar_header(path, tar::EntryType::Directory, 0)
+        } else {
+            tar_header(path, tar::EntryType::Regular, contents.len() as u64)
+        };
+        builder
+            .append(&header, *contents)
+            .map_err(|err| err.to_string())?;
+    }
+    builder.into_inner().map_err(|err| err.to_string())
+}
+
+fn write_zip(path: &Path, entries: &[(&str, &[u8])]) -> Result<(), String> {
+    let file = fs::File::create(path).map_err(|err| err.to_string())?;
+    let mut zip = zip::ZipWriter::new(file);
+    let options = zip::write::FileOptions::default();
+    for (path, contents) in entries {
+        if path.ends_with('/') {
+            zip.add_directory(path.to_string(), options)
+                .map_err(|err| err.to_string())?;
+        } else {
+            zip.start_file(path.to_string(), options)
+                .map_err(|err| err.to_string())?;
+            zip.write_all(contents).map_err(|err| err.to_string())?;
+        }
+    }
+    zip.finish().map_err(|err| err.to_string())?;
+
+    Ok(())
+}
+
+fn map_archive(path: &Path, options: &MapOptions) -> Result<(VirtualFS, Vec<PathBuf>), Error> {
+    let mut vfs = VirtualFS::new();
+    let mut mapped = Vec::new();
+    vfs.map_archive(path, "/", options, &mut |dest_path, _| {
+        mapped.push(dest_path.to_owned())
+    })?;
+
+    Ok((vfs, mapped))
+}
+
+const ENTRIES: &[(&str, &[u8])] = &[
+    ("./", b""),
+    ("a/", b""),
+    ("a/x.txt", b"xxxx"),
+    // no entry for `b/` itself
+    ("b/c/y.txt", b"yyy"),
+];
+
+fn vfs_archive_formats_impl() -> Result<(), String> {
+    let test_dir = create_workspace()?;
+
+    let tar_path = test_dir.path().join("in.tar");
+    let file = fs::File::create(&tar_path).map_err(|err| err.to_string())?;
+    write_tar(file, ENTRIES)?;
+
+    let tgz_path = test_dir.path().join("in.tar.gz");
+    let file = fs::File::create(&tgz_path).map_err(|err| err.to_string())?;
+    let encoder = flate2::write::GzEncoder::new(file, flate2::Compression::default());
+    write_tar(encoder, ENTRIES)?
+        .finish()
+        .map_err(|err| err.to_string())?;
+
+    let zip_path = test_dir.path().join("in.zip");
+    write_zip(&zip_path, ENTRIES)?;
+
+    for path in &[tar_path, tgz_path, zip_path] {
+        let (mut vfs, mapped) =
+            map_archive(path, &MapOptions::default()).map_err(|err| err.to_string())?;
+
+        assert_eq!(
+            mapped,
+            vec![
+                PathBuf::from("/a"),
+                PathBuf::from("/a/x.txt"),
+                PathBuf::from("/b"),
+                PathBuf::from("/b/c"),
+                PathBuf::from("/b/c/y.txt"),
+            ]
+        );
+        assert_eq!(
+            vfs.read_file("/a/x.txt").map_err(|err| err.to_string())?,
+            b"xxxx"
+        );
+        assert_eq!(
+            vfs.read_file("/b/c/y.txt").map_err(|err| err.to_string())?,
+            b"yyy"
+        );
+    }
+
+    assert_eq!(
+        ArchiveFormat::from_path("in.rar"),
+        Err(Error::UnsupportedArchive(PathBuf::from("in.rar")))
+    );
+
+    Ok(())
+}
+
+#[test]
+fn vfs_archive_formats() {
+    if let Err(e) = vfs_archive_formats_impl() {
+        eprintln!("unexpected error occurred: {}", e)
+    }
+}
+
+fn vfs_archive_unsafe_paths_impl() -> Result<(), String> {
+    let test_dir = create_workspace()?;
+
+    for entry in &["../evil.txt", "a/../../evil.txt", "/etc/evil.txt"] {
+        let tar_path = test_dir.path().join("evil.tar");
+        let file = fs::File::create(&tar_path).map_err(|err| err.to_string())?;
+        write_tar(file, &[("a/x.txt", b"xxxx"), (entry, b"evil")])?;
+
+        assert_eq!(
+            map_archive(&tar_path, &MapOptions::default()).err(),
+            Some(Error::InvalidPath(entry.to_string()))
+        );
+    }
+
+    let zip_path = test_dir.path().join("evil.zip");
+    write_zip(&zip_path, &[("../evil.txt", b"evil")])?;
+    assert_eq!(
+        map_archive(&zip_path, &MapOptions::default()).err(),
+        Some(Error::InvalidPath("../evil.txt".to_owned()))
+    );
+
+    Ok(())
+}
+
+#[test]
+fn vfs_archive_unsafe_paths() {
+    if let Err(e) = vfs_archive_unsafe_paths_impl() {
+        eprintln!("unexpected error occurred: {}", e)
+    }
+}
+
+fn vfs_archive_symlinks_impl() -> Result<(), String> {
+    let test_dir = create_workspace()?;
+    let tar_path = test_dir.path().join("links.tar");
+    let file = fs::File::create(&tar_path).map_err(|err| err.to_string())?;
+    let mut builder = tar::Builder::new(file);
+    builder
+        .append(
+            &tar_header("x.txt", tar::EntryType::Regular, 4),
+            &b"xxxx"[..],
+        )
+        .map_err(|err| err.to_string())?;
+    let mut header = tar_header("link", tar::EntryType::Symlink, 0);
+    header
+        .set_link_name("x.txt")
+        .map_err(|err| err.to_string())?;
+    header.set_cksum();
+    builder
+        .append(&header, &b""[..])
+        .map_err(|err| err.to_string())?;
+    let mut header = tar_header("fifo", tar::EntryType::Fifo, 0);
+    header.set_cksum();
+    builder
+        .append(&header, &b""[..])
+        .map_err(|err| err.to_string())?;
+    builder.finish().map_err(|err| err.to_string())?;
+
+    assert_eq!(
+        map_archive(&tar_path, &MapOptions::default()).err(),
+        Some(Error::Symlink(PathBuf::from("link")))
+    );
+
+    let options = MapOptions {
+        symlinks: SymlinkPolicy::PreserveAsLink,
+        ..MapOptions::default()
+    };
+    assert_eq!(
+        map_archive(&tar_path, &options).err(),
+        Some(Error::SpecialFile(PathBuf::from("fifo")))
+    );
+
+    let options = MapOptions {
+        symlinks: SymlinkPolicy::PreserveAsLink,
+        special_files: SpecialFilePolicy::Skip,
+        ..MapOptions::default()
+    };
+    let (vfs, mapped) = map_archive(&tar_path, &options).map_err(|err| err.to_string())?;
+    assert_eq!(
+        mapped,
+        vec![PathBuf::from("/x.txt"), PathBuf::from("/link")]
+    );
+    assert_eq!(
+        vfs.read_link("/link").map_err(|err| err.to_string())?,
+        PathBuf::from("x.txt")
+    );
+
+    Ok(())
+}
+
+#[test]
+fn vfs_archive_symlinks() {
+    if let Err(e) = vfs_archive_symlinks_impl() {
+        eprintln!("unexpected error occurred: {}", e)
+    }
+}
+
+fn write_zip_symlinks(path: &Path, entries: &[(&str, &[u8])]) -> Result<(), String> {
+    write_zip(path, entries)?;
+    // the `zip` crate only writes regular files, so the file type in the
+    // external attributes (`S_IFREG | 0o644`) is patched to `S_IFLNK`
+    let zip = fs::read(path).map_err(|err| err.to_string())?;
+    let mut patched = Vec::with_capacity(zip.len());
+    let mut rest = &zip[..];
+    while !rest.is_empty() {
+        if rest.starts_with(&[0xa4, 0x81]) {
+            patched.extend_from_slice(&[0xa4, 0xa1]);
+            rest = &rest[2..];
+        } else {
+            patched.push(rest[0]);
+            rest = &rest[1..];
+        }
+    }
+    fs::write(path, patched).map_err(|err| err.to_string())
+}
+
+fn vfs_archive_zip_symlinks_impl() -> Result<(), String> {
+    let test_dir = create_workspace()?;
+    let options = MapOptions {
+        symlinks: SymlinkPolicy::PreserveAsLink,
+        ..MapOptions::default()
+    };
+
+    let zip_path = test_dir.path().join("links.zip");
+    write_zip_symlinks(&zip_path, &[("link", b"x\xff.txt")])?;
+    let (vfs, _) = map_archive(&zip_path, &options).map_err(|err| err.to_string())?;
+    assert_eq!(
+        vfs.read_link("/link").map_err(|err| err.to_string())?,
+        PathBuf::from(OsStr::from_bytes(b"x\xff.txt"))
+    );
+
+    let zip_path = test_dir.path().join("long.zip");
+    write_zip_symlinks(&zip_path, &[("link", &[b'x'; 4097][..])])?;
+    match map_archive(&zip_path, &options) {
+        Err(Error::Archive(path, _)) => assert_eq!(path, zip_path),
+        Err(err) => return Err(format!("wrong error received: {}", err)),
+        Ok(_) => return Err("expected mapping to fail".to_owned()),
+    }
+
+    Ok(())
+}
+
+#[test]
+fn vfs_archive_zip_symlinks() {
+    if let Err(e) = vfs_archive_zip_symlinks_impl() {
+        eprintln!("unexpected error occurred: {}", e)
+    }
+}
+
+fn vfs_archive_limits_impl() -> Result<(), String> {
+    let test_dir = create_workspace()?;
+    let tar_path = test_dir.path().join("in.tar");
+    let file = fs::File::create(&tar_path).map_err(|err| err.to_string())?;
+    write_tar(file, ENTRIES)?;
+
+    let options = MapOptions {
+        limits: Limits {
+            max_bytes: Some(6),
+            ..Limits::default()
+        },
+        ..MapOptions::default()
+    };
+    let mut vfs = VirtualFS::new();
+    assert_eq!(
+        vfs.map_archive(&tar_path, "/", &options, &mut |_, _| {})
+            .err(),
+        Some(Error::LimitExceeded {
+            kind: Limit::Bytes,
+            actual: 7,
+            limit: 6,
+        })
+    );
+    // nothing was mapped
+    assert!(!vfs.is_dir("/a").map_err(|err| err.to_string())?);
+
+    let options = MapOptions {
+        limits: Limits {
+            max_bytes: Some(7),
+            max_files: Some(2),
+            max_depth: Some(3),
+        },
+        ..MapOptions::default()
+    };
+    map_archive(&tar_path, &options).map_err(|err| err.to_string())?;
+
+    Ok(())
+}
+
+#[test]
+fn vfs_archive_limits() {
+    if let Err(e) = vfs_archive_limits_impl() {
+        eprintln!("unexpected error occurred: {}", e)
+    }
+}
+
+fn vfs_archive_save_impl() -> Result<(), String> {
+    let test_dir = create_workspace()?;
+
+    let save = |name: &str, order: &[&str]| -> Result<Vec<u8>, String> {
+        let mut vfs = VirtualFS::new();
+        for path in order {
+            vfs.create_dir_all(Path::new(path).parent().unwrap())
+                .map_err(|err| err.to_string())?;
+            vfs.write_file(path, path.as_bytes())
+                .map_err(|err| err.to_string())?;
+        }
+
+        let paths: Vec<_> = order.iter().map(PathBuf::from).collect();
+        let archive_path = test_dir.path().join(name);
+        let mut transaction = Transaction::new(false);
+        vfs.save_archive(&mut transaction, &archive_path, &paths)
+            .map_err(|err| err.to_string())?;
+        transaction.commit().map_err(|err| err.to_string())?;
+        fs::read(&archive_path).map_err(|err| err.to_string())
+    };
+
+    let first = save("first.tar", &["/out/b.txt", "/a.txt", "/out/a.txt"])?;
+    let second = save(
+        "second.tar",
+        &["/out/a.txt", "/out/b.txt", "/a.txt", "/a.txt"],
+    )?;
+    assert_eq!(first, second);
+
+    let mut archive = tar::Archive::new(&first[..]);
+    let mut names = Vec::new();
+    for entry in archive.entries().map_err(|err| err.to_string())? {
+        let entry = entry.map_err(|err| err.to_string())?;
+        let header = entry.header();
+        assert_eq!(header.mtime().map_err(|err| err.to_string())?, 0);
+        assert_eq!(header.uid().map_err(|err| err.to_string())?, 0);
+        assert_eq!(header.gid().map_err(|err| err.to_string())?, 0);
+        names.push(entry.path().map_err(|err| err.to_string())?.into_owned());
+    }
+    assert_eq!(
+        names,
+        vec![
+            PathBuf::from("a.txt"),
+            PathBuf::from("out/a.txt"),
+            PathBuf::from("out/b.txt"),
+        ]
+    );
+
+    // the archive can be loaded back
+    let (mut vfs, _) = map_archive(&test_dir.path().join("first.tar"), &MapOptions::default())
+        .map_err(|err| err.to_string())?;
+    assert_eq!(
+        vfs.read_file("/out/b.txt").map_err(|err| err.to_string())?,
+        b"/out/b.txt"
+    );
+
+    let first = save("first.tar.gz", &["/out/b.txt", "/a.txt"])?;
+    let second = save("second.tar.gz", &["/a.txt", "/out/b.txt"])?;
+    assert_eq!(first, second);
+
+    // nor is anything left behind if the rest of the transaction fails
+    let taken = test_dir.path().join("taken.txt");
+    fs::write(&taken, b"taken").map_err(|err| err.to_string())?;
+    let archive_path = test_dir.path().join("dropped.tar");
+    let mut vfs = VirtualFS::new();
+    vfs.write_file("/a.txt", b"aaa")
+        .map_err(|err| err.to_string())?;
+    let mut transaction = Transaction::new(false);
+    vfs.save_archive(&mut transaction, &archive_path, &[PathBuf::from("/a.txt")])
+        .map_err(|err| err.to_string())?;
+    match transaction.create(&taken) {
+        Err(Error::OutputExists(_)) => {}
+        _ => return Err("expected existing file not to be replaced".to_owned()),
+    }
+    drop(transaction);
+    assert!(!archive_path.exists());
+
+    Ok(())
+}
+
+#[test]
+fn vfs_archive_save() {
+    if let Err(e) = vfs_archive_save_impl() {
+        eprintln!("unexpected error occurred: {}", e)
+    }
+}
@@ -0,0 +1,99 @@
+use sp_wasm_engine::prelude::*;
+use std::ffi::OsString;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+type FsResult<T> = Result<T, MemFSError>;
+
+/// Test double recording every path opened through it.
+#[derive(Debug, Default)]
+struct Recording {
+    fs: MemFS,
+    opened: Arc<Mutex<Vec<PathBuf>>>,
+}
+
+impl FsBackend for Recording {
+    fn open(&self, path: &Path) -> FsResult<Box<dyn FsFile>> {
+        self.opened.lock().unwrap().push(path.to_owned());
+        self.fs.open(path)
+    }
+
+    fn create(&self, path: &Path) -> FsResult<Box<dyn FsFile>> {
+        self.fs.create(path)
+    }
+
+    fn create_dir(&self, path: &Path) -> FsResult<()> {
+        FsBackend::create_dir(&self.fs, path)
+    }
+
+    fn read_dir(&self, path: &Path) -> FsResult<Vec<OsString>> {
+        FsBackend::read_dir(&self.fs, path)
+    }
+
+    fn metadata(&self, path: &Path) -> FsResult<Metadata> {
+        FsBackend::metadata(&self.fs, path)
+    }
+
+    fn remove(&self, path: &Path) -> FsResult<()> {
+        FsBackend::remove(&self.fs, path)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> FsResult<()> {
+        FsBackend::rename(&self.fs, from, to)
+    }
+}
+
+fn vfs_backend_impl() -> Result<(), String> {
+    // upper layer in a custom backend
+    let upper = Recording::default();
+    let opened = Arc::clone(&upper.opened);
+
+    let mut vfs = VirtualFS::with_backend(upper);
+    vfs.create_dir_all("/out").map_err(|err| err.to_string())?;
+    vfs.write_file("/out/a.txt", b"aaa")
+        .map_err(|err| err.to_string())?;
+
+    let contents = vfs.read_file("/out/a.txt").map_err(|err| err.to_string())?;
+    assert_eq!(b"aaa".to_vec(), contents);
+    assert_eq!(*opened.lock().unwrap(), vec![PathBuf::from("/out/a.txt")]);
+    assert_eq!(
+        vfs.changed_files().map_err(|err| err.to_string())?,
+        vec![PathBuf::from("/out/a.txt")]
+    );
+
+    // devices are there all the same
+    assert!(vfs.is_device("/dev/null").map_err(|err| err.to_string())?);
+    vfs.open_file("/dev/null")
+        .and_then(|mut dev| Ok(dev.write_all(b"discarded")?))
+        .map_err(|err| err.to_string())?;
+
+    // lower layer in a custom backend
+    let lower = Recording::default();
+    let opened = Arc::clone(&lower.opened);
+    lower
+        .create_dir(Path::new("/in"))
+        .map_err(|err| err.to_string())?;
+    lower
+        .create(Path::new("/in/b.txt"))
+        .and_then(|mut file| Ok(file.write_all(b"bbb")?))
+        .map_err(|err| err.to_string())?;
+
+    let mut vfs = VirtualFS::with_lower(vec![Layer::new(lower)]);
+    let contents = vfs.read_file("/in/b.txt").map_err(|err| err.to_string())?;
+    assert_eq!(b"bbb".to_vec(), contents);
+    assert_eq!(*opened.lock().unwrap(), vec![PathBuf::from("/in/b.txt")]);
+    assert!(vfs
+        .changed_files()
+        .map_err(|err| err.to_string())?
+        .is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn vfs_backend() {
+    if let Err(e) = vfs_backend_impl() {
+        eprintln!("unexpected error occurred: {}", e)
+    }
+}
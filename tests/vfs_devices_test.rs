@@ -0,0 +1,57 @@
+use sp_wasm_engine::prelude::*;
+use std::ffi::OsString;
+use std::io::{Read, Write};
+
+fn vfs_devices_impl() -> Result<(), String> {
+    let mut vfs = VirtualFS::new();
+    assert_eq!(
+        vfs.read_dir("/dev").map_err(|err| err.to_string())?,
+        vec![
+            OsString::from("full"),
+            OsString::from("null"),
+            OsString::from("urandom"),
+            OsString::from("zero"),
+        ]
+    );
+    assert!(vfs.is_device("/dev/null").map_err(|err| err.to_string())?);
+    assert!(!vfs.is_file("/dev/null").map_err(|err| err.to_string())?);
+
+    let mut buf = [1; 16];
+    let mut dev = vfs.open_file("/dev/null").map_err(|err| err.to_string())?;
+    assert_eq!(0, dev.read(&mut buf).map_err(|err| err.to_string())?);
+    dev.write_all(b"discarded").map_err(|err| err.to_string())?;
+
+    let mut dev = vfs.open_file("/dev/zero").map_err(|err| err.to_string())?;
+    dev.read_exact(&mut buf).map_err(|err| err.to_string())?;
+    assert_eq!([0; 16], buf);
+
+    let mut dev = vfs.open_file("/dev/full").map_err(|err| err.to_string())?;
+    assert!(dev.write_all(b"no space").is_err());
+
+    let mut dev = vfs
+        .open_file("/dev/urandom")
+        .map_err(|err| err.to_string())?;
+    dev.read_exact(&mut buf).map_err(|err| err.to_string())?;
+    assert_ne!([0; 16], buf);
+
+    // devices survive sealing and are never reported as outputs
+    vfs.seal();
+    assert!(vfs.is_device("/dev/zero").map_err(|err| err.to_string())?);
+    let mut dev = vfs.open_file("/dev/null").map_err(|err| err.to_string())?;
+    dev.write_all(b"discarded").map_err(|err| err.to_string())?;
+    vfs.write_file("/out.txt", b"out")
+        .map_err(|err| err.to_string())?;
+    assert_eq!(
+        vfs.changed_files().map_err(|err| err.to_string())?,
+        vec![std::path::PathBuf::from("/out.txt")]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn vfs_devices() {
+    if let Err(e) = vfs_devices_impl() {
+        eprintln!("unexpected error occurred: {}", e)
+    }
+}
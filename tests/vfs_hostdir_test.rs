@@ -0,0 +1,70 @@
+use sp_wasm_engine::prelude::*;
+use std::ffi::OsString;
+use std::fs;
+use std::os::unix::fs::symlink;
+use std::path::PathBuf;
+
+fn vfs_hostdir_impl() -> Result<(), String> {
+    let outside = tempfile::tempdir().map_err(|err| err.to_string())?;
+    fs::write(outside.path().join("secret.txt"), b"secret").map_err(|err| err.to_string())?;
+
+    let data = tempfile::tempdir().map_err(|err| err.to_string())?;
+    fs::write(data.path().join("a.txt"), b"aaa").map_err(|err| err.to_string())?;
+    symlink(outside.path(), data.path().join("escape")).map_err(|err| err.to_string())?;
+
+    let out = tempfile::tempdir().map_err(|err| err.to_string())?;
+
+    let mut vfs = VirtualFS::new();
+    vfs.mount(
+        "/in/data",
+        HostDir::open(data.path(), true).map_err(|err| err.to_string())?,
+    )
+    .map_err(|err| err.to_string())?;
+    vfs.mount(
+        "/out",
+        HostDir::open(out.path(), false).map_err(|err| err.to_string())?,
+    )
+    .map_err(|err| err.to_string())?;
+
+    assert!(vfs.is_dir("/in/data").map_err(|err| err.to_string())?);
+    assert_eq!(
+        vfs.read_dir("/in").map_err(|err| err.to_string())?,
+        vec![OsString::from("data")]
+    );
+    assert_eq!(
+        vfs.read_dir("/in/data").map_err(|err| err.to_string())?,
+        vec![OsString::from("a.txt")]
+    );
+
+    let contents = vfs
+        .read_file("/in/data/a.txt")
+        .map_err(|err| err.to_string())?;
+    assert_eq!(b"aaa".to_vec(), contents);
+
+    // neither `..` nor symlinks leave the mounted dir
+    assert!(vfs.read_file("/in/data/escape/secret.txt").is_err());
+    assert!(vfs.read_file("/in/data/../../secret.txt").is_err());
+
+    // read-only mount
+    assert!(vfs.write_file("/in/data/b.txt", b"bbb").is_err());
+    assert!(vfs.remove("/in/data/a.txt").is_err());
+
+    // writes go straight to the host
+    vfs.write_file("/out/b.txt", b"bbb")
+        .map_err(|err| err.to_string())?;
+    let contents = fs::read(out.path().join("b.txt")).map_err(|err| err.to_string())?;
+    assert_eq!(b"bbb".to_vec(), contents);
+    assert!(!vfs
+        .changed_files()
+        .map_err(|err| err.to_string())?
+        .contains(&PathBuf::from("/out/b.txt")));
+
+    Ok(())
+}
+
+#[test]
+fn vfs_hostdir() {
+    if let Err(e) = vfs_hostdir_impl() {
+        eprintln!("unexpected error occurred: {}", e)
+    }
+}
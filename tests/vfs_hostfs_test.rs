@@ -0,0 +1,118 @@
+mod common;
+
+use common::*;
+use sp_wasm_engine::sandbox::vfs::hostfs::Transaction;
+use sp_wasm_engine::Error;
+use std::fs;
+use std::path::Path;
+
+fn dir_entries(dir: &Path) -> Result<Vec<String>, String> {
+    let mut entries = Vec::new();
+    for entry in fs::read_dir(dir).map_err(|err| err.to_string())? {
+        let entry = entry.map_err(|err| err.to_string())?;
+        entries.push(entry.file_name().to_string_lossy().to_string());
+    }
+    entries.sort();
+
+    Ok(entries)
+}
+
+fn hostfs_transaction_impl() -> Result<(), String> {
+    let test_dir = create_workspace()?;
+    let out = test_dir.path();
+
+    // nothing is written until committed
+    let mut transaction = Transaction::new(false);
+    transaction
+        .write_file(out.join("a.txt"), &mut &b"aaa"[..])
+        .map_err(|err| err.to_string())?;
+    transaction
+        .write_file(out.join("b.txt"), &mut &b"bbb"[..])
+        .map_err(|err| err.to_string())?;
+    assert!(!out.join("a.txt").exists());
+    transaction.commit().map_err(|err| err.to_string())?;
+
+    assert_eq!(dir_entries(out)?, vec!["a.txt", "b.txt"]);
+    assert_eq!(
+        fs::read(out.join("b.txt")).map_err(|err| err.to_string())?,
+        b"bbb"
+    );
+
+    // existing files are not clobbered
+    let mut transaction = Transaction::new(false);
+    assert_eq!(
+        transaction
+            .write_file(out.join("a.txt"), &mut &b"new"[..])
+            .err(),
+        Some(Error::OutputExists(out.join("a.txt")))
+    );
+    drop(transaction);
+    assert_eq!(
+        fs::read(out.join("a.txt")).map_err(|err| err.to_string())?,
+        b"aaa"
+    );
+
+    // dropped transactions leave no temp files behind
+    let mut transaction = Transaction::new(false);
+    transaction
+        .write_file(out.join("c.txt"), &mut &b"ccc"[..])
+        .map_err(|err| err.to_string())?;
+    drop(transaction);
+    assert_eq!(dir_entries(out)?, vec!["a.txt", "b.txt"]);
+
+    // missing dirs come and go with the transaction
+    let mut transaction = Transaction::new(false);
+    transaction
+        .write_file(out.join("e/f/g.txt"), &mut &b"ggg"[..])
+        .map_err(|err| err.to_string())?;
+    assert!(out.join("e/f").is_dir());
+    drop(transaction);
+    assert_eq!(dir_entries(out)?, vec!["a.txt", "b.txt"]);
+
+    // all-or-nothing, even if an output appears in the meantime
+    let mut transaction = Transaction::new(false);
+    transaction
+        .write_file(out.join("c.txt"), &mut &b"ccc"[..])
+        .map_err(|err| err.to_string())?;
+    transaction
+        .write_file(out.join("e/e.txt"), &mut &b"eee"[..])
+        .map_err(|err| err.to_string())?;
+    transaction
+        .write_file(out.join("d.txt"), &mut &b"ddd"[..])
+        .map_err(|err| err.to_string())?;
+    fs::write(out.join("d.txt"), b"other").map_err(|err| err.to_string())?;
+    assert_eq!(
+        transaction.commit().err(),
+        Some(Error::OutputExists(out.join("d.txt")))
+    );
+    assert_eq!(dir_entries(out)?, vec!["a.txt", "b.txt", "d.txt"]);
+    assert_eq!(
+        fs::read(out.join("d.txt")).map_err(|err| err.to_string())?,
+        b"other"
+    );
+
+    // unless overwriting is allowed, which leaves nothing else behind
+    let mut transaction = Transaction::new(true);
+    transaction
+        .write_file(out.join("a.txt"), &mut &b"new"[..])
+        .map_err(|err| err.to_string())?;
+    transaction
+        .write_file(out.join("e/e.txt"), &mut &b"eee"[..])
+        .map_err(|err| err.to_string())?;
+    transaction.commit().map_err(|err| err.to_string())?;
+    assert_eq!(
+        fs::read(out.join("a.txt")).map_err(|err| err.to_string())?,
+        b"new"
+    );
+    assert_eq!(dir_entries(out)?, vec!["a.txt", "b.txt", "d.txt", "e"]);
+    assert_eq!(dir_entries(&out.join("e"))?, vec!["e.txt"]);
+
+    Ok(())
+}
+
+#[test]
+fn hostfs_transaction() {
+    if let Err(e) = hostfs_transaction_impl() {
+        eprintln!("unexpected error occurred: {}", e)
+    }
+}
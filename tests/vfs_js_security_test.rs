@@ -4,6 +4,7 @@ use sp_wasm_engine::sandbox::engine::error::Error as EngineError;
 use std::path;
 
 #[test]
+#[allow(deprecated)]
 fn vfs_js_security() {
     let engine = Engine::new().unwrap();
     let runtime = Runtime::new(&engine).unwrap();
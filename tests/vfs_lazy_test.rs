@@ -0,0 +1,43 @@
+mod common;
+
+use common::*;
+use sp_wasm_engine::prelude::*;
+use std::{fs::File, io::Write, path::PathBuf};
+
+fn vfs_lazy_impl() -> Result<(), String> {
+    let test_dir = create_workspace()?;
+    let mut path = PathBuf::from(test_dir.path());
+    path.push("a.txt");
+    let mut f = File::create(path.as_path()).map_err(|err| err.to_string())?;
+    f.write_all(b"aaa").map_err(|err| err.to_string())?;
+
+    let mut path = PathBuf::from(test_dir.path());
+    path.push("b.txt");
+    let mut f = File::create(path.as_path()).map_err(|err| err.to_string())?;
+    f.write_all(b"bbb").map_err(|err| err.to_string())?;
+
+    // map into VFS without reading any contents
+    let mut vfs = VirtualFS::new();
+    let options = MapOptions {
+        lazy: true,
+        ..MapOptions::default()
+    };
+    vfs.map_path_with(test_dir.path(), "/", &options, &mut |_, _| {})
+        .map_err(|err| err.to_string())?;
+
+    let contents = vfs.read_file("/a.txt").map_err(|err| err.to_string())?;
+    assert_eq!(b"aaa".to_vec(), contents);
+
+    // modifying the host file before it's read is detected
+    f.write_all(b"bbb").map_err(|err| err.to_string())?;
+    assert!(vfs.read_file("/b.txt").is_err());
+
+    Ok(())
+}
+
+#[test]
+fn vfs_lazy() {
+    if let Err(e) = vfs_lazy_impl() {
+        eprintln!("unexpected error occurred: {}", e)
+    }
+}
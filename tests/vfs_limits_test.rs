@@ -0,0 +1,99 @@
+mod common;
+
+use common::*;
+use sp_wasm_engine::prelude::*;
+use sp_wasm_engine::{Error, Limit};
+use std::fs;
+
+fn vfs_limits_impl() -> Result<(), String> {
+    let test_dir = create_workspace()?;
+    let input_dir = test_dir.path();
+    fs::create_dir_all(input_dir.join("a/b")).map_err(|err| err.to_string())?;
+    fs::write(input_dir.join("x.txt"), b"xxxx").map_err(|err| err.to_string())?;
+    fs::write(input_dir.join("a/y.txt"), b"yyy").map_err(|err| err.to_string())?;
+    fs::write(input_dir.join("a/b/z.txt"), b"zz").map_err(|err| err.to_string())?;
+
+    let usage = Usage::measure(input_dir, &MapOptions::default()).map_err(|err| err.to_string())?;
+    assert_eq!(
+        usage,
+        Usage {
+            bytes: 9,
+            files: 3,
+            depth: 3,
+        }
+    );
+
+    let cases = vec![
+        (
+            Limits {
+                max_bytes: Some(8),
+                ..Limits::default()
+            },
+            Limit::Bytes,
+            9,
+            8,
+        ),
+        (
+            Limits {
+                max_files: Some(2),
+                ..Limits::default()
+            },
+            Limit::Files,
+            3,
+            2,
+        ),
+        (
+            Limits {
+                max_depth: Some(2),
+                ..Limits::default()
+            },
+            Limit::Depth,
+            3,
+            2,
+        ),
+    ];
+
+    for (limits, kind, actual, limit) in cases {
+        let options = MapOptions {
+            limits,
+            ..MapOptions::default()
+        };
+
+        // nothing gets mapped when a limit is exceeded
+        let mut vfs = VirtualFS::new();
+        let err = vfs
+            .map_path_with(input_dir, "/", &options, &mut |_, _| {})
+            .err();
+        assert_eq!(
+            err,
+            Some(Error::LimitExceeded {
+                kind,
+                actual,
+                limit
+            })
+        );
+        assert!(!vfs.is_file("/x.txt").map_err(|err| err.to_string())?);
+    }
+
+    let options = MapOptions {
+        limits: Limits {
+            max_bytes: Some(9),
+            max_files: Some(3),
+            max_depth: Some(3),
+        },
+        ..MapOptions::default()
+    };
+    let mut vfs = VirtualFS::new();
+    vfs.map_path_with(input_dir, "/", &options, &mut |_, _| {})
+        .map_err(|err| err.to_string())?;
+    assert!(vfs.is_file("/a/b/z.txt").map_err(|err| err.to_string())?);
+
+    Ok(())
+}
+
+#[test]
+fn vfs_limits() {
+    if let Err(e) = vfs_limits_impl() {
+        eprintln!("unexpected error occurred: {}", e)
+    }
+}
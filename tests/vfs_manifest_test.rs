@@ -0,0 +1,116 @@
+mod common;
+
+use common::*;
+use sp_wasm_engine::prelude::*;
+use sp_wasm_engine::sandbox::manifest::sha256;
+use std::fs;
+use std::path::Path;
+
+fn vfs_digest_impl() -> Result<(), String> {
+    let digest = |files: &[(&str, &[u8])]| -> Result<String, String> {
+        let mut vfs = VirtualFS::new();
+        for (path, contents) in files {
+            vfs.create_dir_all("/in/a").map_err(|err| err.to_string())?;
+            vfs.write_file(path, contents)
+                .map_err(|err| err.to_string())?;
+        }
+        vfs.digest("/in").map_err(|err| err.to_string())
+    };
+
+    let first = digest(&[("/in/x.txt", b"x"), ("/in/a/y.txt", b"y")])?;
+    // independent of the order the files were created in
+    assert_eq!(
+        first,
+        digest(&[("/in/a/y.txt", b"y"), ("/in/x.txt", b"x")])?
+    );
+    // but not of their names or contents
+    assert_ne!(
+        first,
+        digest(&[("/in/x.txt", b"y"), ("/in/a/y.txt", b"x")])?
+    );
+    assert_ne!(
+        first,
+        digest(&[("/in/x.txt", b"x"), ("/in/a/z.txt", b"y")])?
+    );
+
+    // mounted backends are left out
+    let mut vfs = VirtualFS::new();
+    vfs.create_dir_all("/in/a").map_err(|err| err.to_string())?;
+    vfs.write_file("/in/x.txt", b"x")
+        .map_err(|err| err.to_string())?;
+    vfs.write_file("/in/a/y.txt", b"y")
+        .map_err(|err| err.to_string())?;
+    let mounted = MemFS::new();
+    FsBackend::create(&mounted, Path::new("/z.txt")).map_err(|err| err.to_string())?;
+    vfs.mount("/in/m", mounted).map_err(|err| err.to_string())?;
+    assert_eq!(first, vfs.digest("/in").map_err(|err| err.to_string())?);
+
+    // lazily mapped files are hashed the same as loaded ones
+    let test_dir = create_workspace()?;
+    fs::create_dir(test_dir.path().join("a")).map_err(|err| err.to_string())?;
+    fs::write(test_dir.path().join("x.txt"), b"x").map_err(|err| err.to_string())?;
+    fs::write(test_dir.path().join("a/y.txt"), b"y").map_err(|err| err.to_string())?;
+    let mut vfs = VirtualFS::new();
+    vfs.create_dir_all("/in").map_err(|err| err.to_string())?;
+    let options = MapOptions {
+        lazy: true,
+        ..MapOptions::default()
+    };
+    vfs.map_path_with(test_dir.path(), "/in", &options, &mut |_, _| {})
+        .map_err(|err| err.to_string())?;
+    assert_eq!(first, vfs.digest("/in").map_err(|err| err.to_string())?);
+
+    let (size, hash) = sha256(&mut &b"abc"[..]).map_err(|err| err.to_string())?;
+    assert_eq!(size, 3);
+    assert_eq!(
+        hash,
+        "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn vfs_digest() {
+    if let Err(e) = vfs_digest_impl() {
+        eprintln!("unexpected error occurred: {}", e)
+    }
+}
+
+fn manifest_save_impl() -> Result<(), String> {
+    let test_dir = create_workspace()?;
+    let manifest = Manifest {
+        args: vec!["in.txt".to_owned(), "out.txt".to_owned()],
+        input_digest: "00".to_owned(),
+        outputs: vec![ManifestOutput {
+            guest_path: "/out.txt".to_owned(),
+            host_path: "out/out.txt".to_owned(),
+            size: 3,
+            sha256: "ba".to_owned(),
+        }],
+        ..Manifest::default()
+    };
+
+    let manifest_path = test_dir.path().join("manifest.json");
+    manifest
+        .save(&manifest_path)
+        .map_err(|err| err.to_string())?;
+
+    let json = fs::read_to_string(&manifest_path).map_err(|err| err.to_string())?;
+    let value: serde_json::Value = serde_json::from_str(&json).map_err(|err| err.to_string())?;
+    assert_eq!(value["outputs"][0]["guest_path"], "/out.txt");
+    assert_eq!(value["outputs"][0]["size"], 3);
+    assert_eq!(value["args"][1], "out.txt");
+
+    let loaded: Manifest = serde_json::from_str(&json).map_err(|err| err.to_string())?;
+    assert_eq!(loaded, manifest);
+
+    Ok(())
+}
+
+#[test]
+fn manifest_save() {
+    if let Err(e) = manifest_save_impl() {
+        eprintln!("unexpected error occurred: {}", e)
+    }
+}
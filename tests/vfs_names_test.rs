@@ -0,0 +1,60 @@
+#![cfg(target_os = "linux")]
+
+mod common;
+
+use common::*;
+use sp_wasm_engine::prelude::*;
+use sp_wasm_engine::sandbox::vfs::names;
+use std::{
+    ffi::OsStr,
+    fs::{self, File},
+    io::Write,
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+};
+
+fn vfs_names_impl() -> Result<(), String> {
+    let test_dir = create_workspace()?;
+    let mut dir = PathBuf::from(test_dir.path());
+    dir.push(OsStr::from_bytes(b"caf\xe9"));
+    fs::create_dir(dir.as_path()).map_err(|err| err.to_string())?;
+    dir.push(OsStr::from_bytes(b"\xff.txt"));
+    let mut f = File::create(dir).map_err(|err| err.to_string())?;
+    f.write_all(b"aaa").map_err(|err| err.to_string())?;
+
+    // map into VFS
+    let mut vfs = VirtualFS::new();
+    let mut guest_paths = Vec::new();
+    vfs.map_path(test_dir.path(), "/", &mut |_, dest_path| {
+        guest_paths.push(names::to_guest(dest_path))
+    })
+    .map_err(|err| err.to_string())?;
+
+    assert_eq!(
+        guest_paths,
+        vec![
+            "/caf\u{f7e9}".to_owned(),
+            "/caf\u{f7e9}/\u{f7ff}.txt".to_owned()
+        ]
+    );
+
+    // names are kept byte for byte...
+    let path = Path::new(OsStr::from_bytes(b"/caf\xe9/\xff.txt"));
+    let contents = vfs.read_file(path).map_err(|err| err.to_string())?;
+    assert_eq!(b"aaa".to_vec(), contents);
+
+    // ...and can be recovered from the names seen by the guest
+    let contents = vfs
+        .read_file(names::from_guest(&guest_paths[1]))
+        .map_err(|err| err.to_string())?;
+    assert_eq!(b"aaa".to_vec(), contents);
+
+    Ok(())
+}
+
+#[test]
+fn vfs_names() {
+    if let Err(e) = vfs_names_impl() {
+        eprintln!("unexpected error occurred: {}", e)
+    }
+}
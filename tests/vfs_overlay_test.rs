@@ -0,0 +1,112 @@
+mod common;
+
+use common::*;
+use sp_wasm_engine::prelude::*;
+use sp_wasm_engine::Error;
+use std::{fs::File, io::Write, path::PathBuf};
+
+fn vfs_overlay_impl() -> Result<(), String> {
+    let test_dir = create_workspace()?;
+    let mut path = PathBuf::from(test_dir.path());
+    path.push("a.txt");
+    let mut f = File::create(path).map_err(|err| err.to_string())?;
+    f.write_all(b"aaa").map_err(|err| err.to_string())?;
+
+    let mut path = PathBuf::from(test_dir.path());
+    path.push("b.txt");
+    let mut f = File::create(path).map_err(|err| err.to_string())?;
+    f.write_all(b"bbb").map_err(|err| err.to_string())?;
+
+    // inputs become the lower layer
+    let mut vfs = VirtualFS::new();
+    vfs.map_path(test_dir.path(), "/", &mut |_, _| {})
+        .map_err(|err| err.to_string())?;
+    vfs.seal();
+
+    vfs.write_file("/a.txt", b"AAA")
+        .map_err(|err| err.to_string())?;
+    vfs.write_file("/c.txt", b"ccc")
+        .map_err(|err| err.to_string())?;
+    vfs.remove("/b.txt").map_err(|err| err.to_string())?;
+
+    let contents = vfs.read_file("/a.txt").map_err(|err| err.to_string())?;
+    assert_eq!(b"AAA".to_vec(), contents);
+    assert!(!vfs.is_file("/b.txt").map_err(|err| err.to_string())?);
+    assert_eq!(
+        vfs.changed_files().map_err(|err| err.to_string())?,
+        vec![PathBuf::from("/a.txt"), PathBuf::from("/c.txt")]
+    );
+    assert_eq!(
+        vfs.whiteouts().collect::<Vec<_>>(),
+        vec![PathBuf::from("/b.txt")]
+    );
+
+    // lower layer is left untouched and can be shared
+    let mut vfs = VirtualFS::with_lower(vfs.lower().to_vec());
+    let contents = vfs.read_file("/a.txt").map_err(|err| err.to_string())?;
+    assert_eq!(b"aaa".to_vec(), contents);
+    let contents = vfs.read_file("/b.txt").map_err(|err| err.to_string())?;
+    assert_eq!(b"bbb".to_vec(), contents);
+    assert!(vfs
+        .changed_files()
+        .map_err(|err| err.to_string())?
+        .is_empty());
+
+    // nor can it be changed through a handle
+    let mut file = vfs.open_file("/a.txt").map_err(|err| err.to_string())?;
+    assert!(file.write_all(b"AAA").is_err());
+    let contents = vfs.read_file("/a.txt").map_err(|err| err.to_string())?;
+    assert_eq!(b"aaa".to_vec(), contents);
+
+    Ok(())
+}
+
+#[test]
+fn vfs_overlay() {
+    if let Err(e) = vfs_overlay_impl() {
+        eprintln!("unexpected error occurred: {}", e)
+    }
+}
+
+fn vfs_read_only_impl() -> Result<(), String> {
+    let mut vfs = VirtualFS::new();
+    vfs.create_dir_all("/in/sub")
+        .map_err(|err| err.to_string())?;
+    vfs.write_file("/in/a.txt", b"aaa")
+        .map_err(|err| err.to_string())?;
+    vfs.set_read_only("/in").map_err(|err| err.to_string())?;
+
+    let is_erofs = |result: Result<(), Error>| match result {
+        Err(Error::FileError(ref err, _)) => err.raw_os_error() == Some(libc::EROFS),
+        _ => false,
+    };
+    assert!(is_erofs(vfs.write_file("/in/a.txt", b"AAA")));
+    assert!(is_erofs(vfs.write_file("/in/sub/b.txt", b"bbb")));
+    assert!(is_erofs(vfs.write_at("/in/a.txt", 0, b"A")));
+    assert!(is_erofs(vfs.set_len("/in/a.txt", 0)));
+    assert!(is_erofs(vfs.create_dir_all("/in/sub/x")));
+    assert!(is_erofs(vfs.remove("/in/a.txt")));
+    assert!(is_erofs(vfs.rename("/in/a.txt", "/a.txt")));
+    assert!(is_erofs(vfs.create_symlink("/in/link", "a.txt")));
+
+    // not even through a handle, though files can still be read
+    let mut file = vfs.open_file("/in/a.txt").map_err(|err| err.to_string())?;
+    assert!(file.write_all(b"AAA").is_err());
+    let contents = vfs.read_file("/in/a.txt").map_err(|err| err.to_string())?;
+    assert_eq!(b"aaa".to_vec(), contents);
+
+    // existing dirs are left as they are, and the rest stays writable
+    vfs.create_dir_all("/in/sub")
+        .map_err(|err| err.to_string())?;
+    vfs.write_file("/out.txt", b"out")
+        .map_err(|err| err.to_string())?;
+
+    Ok(())
+}
+
+#[test]
+fn vfs_read_only() {
+    if let Err(e) = vfs_read_only_impl() {
+        eprintln!("unexpected error occurred: {}", e)
+    }
+}
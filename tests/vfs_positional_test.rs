@@ -0,0 +1,136 @@
+use sp_wasm_engine::prelude::*;
+use std::path::PathBuf;
+
+fn vfs_positional_impl() -> Result<(), String> {
+    let mut vfs = VirtualFS::new();
+    vfs.write_file("/a.txt", b"Hello world!")
+        .map_err(|err| err.to_string())?;
+    vfs.seal();
+
+    let mut buf = [0; 5];
+    assert_eq!(
+        vfs.read_at("/a.txt", 6, &mut buf)
+            .map_err(|err| err.to_string())?,
+        5
+    );
+    assert_eq!(&buf, b"world");
+    assert_eq!(
+        vfs.read_at("/a.txt", 10, &mut buf)
+            .map_err(|err| err.to_string())?,
+        2
+    );
+
+    // writing to a file from a lower layer copies it up first
+    vfs.write_at("/a.txt", 6, b"there")
+        .map_err(|err| err.to_string())?;
+    vfs.write_at("/a.txt", 14, b"!")
+        .map_err(|err| err.to_string())?;
+    assert_eq!(
+        vfs.read_file("/a.txt").map_err(|err| err.to_string())?,
+        b"Hello there!\0\0!"
+    );
+    assert_eq!(
+        vfs.changed_files().map_err(|err| err.to_string())?,
+        vec![PathBuf::from("/a.txt")]
+    );
+    let mut lower = VirtualFS::with_lower(vfs.lower().to_vec());
+    assert_eq!(
+        lower.read_file("/a.txt").map_err(|err| err.to_string())?,
+        b"Hello world!"
+    );
+
+    vfs.set_len("/a.txt", 5).map_err(|err| err.to_string())?;
+    assert_eq!(
+        vfs.read_file("/a.txt").map_err(|err| err.to_string())?,
+        b"Hello"
+    );
+    vfs.set_len("/a.txt", 7).map_err(|err| err.to_string())?;
+    assert_eq!(
+        vfs.read_file("/a.txt").map_err(|err| err.to_string())?,
+        b"Hello\0\0"
+    );
+    // past the limit, the file is left as is
+    assert!(vfs.set_len("/a.txt", 1 << 40).is_err());
+    assert_eq!(
+        vfs.read_file("/a.txt").map_err(|err| err.to_string())?,
+        b"Hello\0\0"
+    );
+    assert!(vfs.set_len("/b.txt", 0).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn vfs_positional() {
+    if let Err(e) = vfs_positional_impl() {
+        eprintln!("unexpected error occurred: {}", e)
+    }
+}
+
+fn vfs_rename_impl() -> Result<(), String> {
+    let mut vfs = VirtualFS::new();
+    vfs.create_dir_all("/in/sub")
+        .map_err(|err| err.to_string())?;
+    vfs.write_file("/in/a.txt", b"aaa")
+        .map_err(|err| err.to_string())?;
+    vfs.seal();
+
+    // files from lower layers are moved by copying
+    vfs.rename("/in/a.txt", "/in/sub/b.txt")
+        .map_err(|err| err.to_string())?;
+    assert!(!vfs.is_file("/in/a.txt").map_err(|err| err.to_string())?);
+    assert_eq!(
+        vfs.read_file("/in/sub/b.txt")
+            .map_err(|err| err.to_string())?,
+        b"aaa"
+    );
+
+    // dirs in the upper layer are moved as a whole
+    vfs.create_dir_all("/out/x")
+        .map_err(|err| err.to_string())?;
+    vfs.write_file("/out/x/c.txt", b"ccc")
+        .map_err(|err| err.to_string())?;
+    vfs.rename("/out", "/done").map_err(|err| err.to_string())?;
+    assert!(!vfs.is_dir("/out").map_err(|err| err.to_string())?);
+    assert_eq!(
+        vfs.read_file("/done/x/c.txt")
+            .map_err(|err| err.to_string())?,
+        b"ccc"
+    );
+
+    // existing files are replaced
+    vfs.rename("/done/x/c.txt", "/in/sub/b.txt")
+        .map_err(|err| err.to_string())?;
+    assert_eq!(
+        vfs.read_file("/in/sub/b.txt")
+            .map_err(|err| err.to_string())?,
+        b"ccc"
+    );
+
+    // but dirs from lower layers can't be moved
+    assert!(vfs.rename("/in", "/moved").is_err());
+    assert!(vfs.is_dir("/in/sub").map_err(|err| err.to_string())?);
+
+    // nor onto an empty dir, which is left in place
+    vfs.create_dir_all("/empty")
+        .map_err(|err| err.to_string())?;
+    assert!(vfs.rename("/in", "/empty").is_err());
+    assert!(vfs.is_dir("/empty").map_err(|err| err.to_string())?);
+    assert!(vfs.is_dir("/in/sub").map_err(|err| err.to_string())?);
+
+    // which dirs in the upper layer replace, unlike non-empty ones
+    assert!(vfs.rename("/done", "/in").is_err());
+    assert!(vfs.is_dir("/done/x").map_err(|err| err.to_string())?);
+    vfs.rename("/done", "/empty")
+        .map_err(|err| err.to_string())?;
+    assert!(vfs.is_dir("/empty/x").map_err(|err| err.to_string())?);
+
+    Ok(())
+}
+
+#[test]
+fn vfs_rename() {
+    if let Err(e) = vfs_rename_impl() {
+        eprintln!("unexpected error occurred: {}", e)
+    }
+}
@@ -0,0 +1,108 @@
+mod common;
+
+use common::*;
+use sp_wasm_engine::prelude::*;
+use sp_wasm_engine::Error;
+use std::fs;
+use std::os::unix::fs::symlink;
+use std::os::unix::net::UnixListener;
+use std::path::{Path, PathBuf};
+
+fn map(input_dir: &Path, options: &MapOptions) -> Result<VirtualFS, Error> {
+    let mut vfs = VirtualFS::new();
+    vfs.map_path_with(input_dir, "/", options, &mut |_, _| {})?;
+    Ok(vfs)
+}
+
+fn options(symlinks: SymlinkPolicy) -> MapOptions {
+    MapOptions {
+        symlinks,
+        ..MapOptions::default()
+    }
+}
+
+fn vfs_symlinks_impl() -> Result<(), String> {
+    let test_dir = create_workspace()?;
+    let outside = test_dir.path().join("outside");
+    fs::create_dir(&outside).map_err(|err| err.to_string())?;
+    fs::write(outside.join("secret.txt"), b"secret").map_err(|err| err.to_string())?;
+
+    let input_dir = test_dir.path().join("in");
+    fs::create_dir_all(input_dir.join("sub")).map_err(|err| err.to_string())?;
+    fs::write(input_dir.join("a.txt"), b"aaa").map_err(|err| err.to_string())?;
+    fs::write(input_dir.join("sub/b.txt"), b"bbb").map_err(|err| err.to_string())?;
+    symlink("sub", input_dir.join("link_dir")).map_err(|err| err.to_string())?;
+    symlink("a.txt", input_dir.join("link_file")).map_err(|err| err.to_string())?;
+
+    // rejected by default
+    assert!(match map(&input_dir, &MapOptions::default()) {
+        Err(Error::Symlink(_)) => true,
+        _ => false,
+    });
+
+    let vfs = map(&input_dir, &options(SymlinkPolicy::Skip)).map_err(|err| err.to_string())?;
+    assert!(vfs.is_file("/sub/b.txt").map_err(|err| err.to_string())?);
+    assert!(!vfs.is_dir("/link_dir").map_err(|err| err.to_string())?);
+    assert!(!vfs.is_file("/link_file").map_err(|err| err.to_string())?);
+
+    let mut vfs = map(&input_dir, &options(SymlinkPolicy::FollowWithinRoot))
+        .map_err(|err| err.to_string())?;
+    let contents = vfs
+        .read_file("/link_dir/b.txt")
+        .map_err(|err| err.to_string())?;
+    assert_eq!(b"bbb".to_vec(), contents);
+    let contents = vfs.read_file("/link_file").map_err(|err| err.to_string())?;
+    assert_eq!(b"aaa".to_vec(), contents);
+
+    let vfs =
+        map(&input_dir, &options(SymlinkPolicy::PreserveAsLink)).map_err(|err| err.to_string())?;
+    assert!(vfs.is_symlink("/link_dir").map_err(|err| err.to_string())?);
+    assert_eq!(
+        vfs.read_link("/link_file").map_err(|err| err.to_string())?,
+        PathBuf::from("a.txt")
+    );
+
+    // links leaving the mapped dir or looping back are never followed
+    symlink(outside.join("secret.txt"), input_dir.join("sub/escape"))
+        .map_err(|err| err.to_string())?;
+    assert_eq!(
+        map(&input_dir, &options(SymlinkPolicy::FollowWithinRoot)).err(),
+        Some(Error::SymlinkEscape(input_dir.join("sub/escape")))
+    );
+    fs::remove_file(input_dir.join("sub/escape")).map_err(|err| err.to_string())?;
+
+    symlink("..", input_dir.join("sub/loop")).map_err(|err| err.to_string())?;
+    assert_eq!(
+        map(&input_dir, &options(SymlinkPolicy::FollowWithinRoot)).err(),
+        Some(Error::SymlinkCycle(input_dir.join("sub/loop")))
+    );
+    fs::remove_file(input_dir.join("sub/loop")).map_err(|err| err.to_string())?;
+
+    // special files are never read
+    let _socket =
+        UnixListener::bind(input_dir.join("sub/socket")).map_err(|err| err.to_string())?;
+    let special = MapOptions {
+        symlinks: SymlinkPolicy::Skip,
+        ..MapOptions::default()
+    };
+    assert_eq!(
+        map(&input_dir, &special).err(),
+        Some(Error::SpecialFile(input_dir.join("sub/socket")))
+    );
+
+    let special = MapOptions {
+        special_files: SpecialFilePolicy::Skip,
+        ..special
+    };
+    let vfs = map(&input_dir, &special).map_err(|err| err.to_string())?;
+    assert!(!vfs.is_file("/sub/socket").map_err(|err| err.to_string())?);
+
+    Ok(())
+}
+
+#[test]
+fn vfs_symlinks() {
+    if let Err(e) = vfs_symlinks_impl() {
+        eprintln!("unexpected error occurred: {}", e)
+    }
+}